@@ -0,0 +1,132 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Limit of a ranking run which ran out, see `Budget`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BudgetLimit {
+    /// `--max-total-bytes`
+    Bytes,
+    /// `--max-total-time`
+    Time,
+}
+
+impl fmt::Display for BudgetLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetLimit::Bytes => write!(f, "total bytes"),
+            BudgetLimit::Time => write!(f, "total time"),
+        }
+    }
+}
+
+/// Bytes downloaded and time spent by speed tests of a run, shared by all
+/// of them; no new test starts once either limit is reached
+#[derive(Debug)]
+pub struct Budget {
+    max_bytes: Option<u64>,
+    deadline: Option<Instant>,
+    bytes: AtomicU64,
+    reported: AtomicBool,
+}
+
+impl Budget {
+    pub fn new(max_bytes: Option<u64>, max_time: Option<Duration>) -> Self {
+        Self {
+            max_bytes,
+            // a limit too far away to be represented is no limit
+            deadline: max_time.and_then(|max_time| Instant::now().checked_add(max_time)),
+            bytes: AtomicU64::new(0),
+            reported: AtomicBool::new(false),
+        }
+    }
+
+    pub fn add_bytes(&self, bytes: usize) {
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn exhausted(&self) -> Option<BudgetLimit> {
+        if self
+            .max_bytes
+            .is_some_and(|max_bytes| self.bytes() >= max_bytes)
+        {
+            return Some(BudgetLimit::Bytes);
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Some(BudgetLimit::Time);
+        }
+        None
+    }
+
+    /// Like `exhausted`, but only the first time it is
+    pub fn newly_exhausted(&self) -> Option<BudgetLimit> {
+        let limit = self.exhausted()?;
+        (!self.reported.swap(true, Ordering::Relaxed)).then_some(limit)
+    }
+}
+
+/// Parses durations like `30s`, `500ms`, `2m` or `1h`; bare numbers are
+/// seconds
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let duration = duration.trim();
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(duration.len());
+    let (number, unit) = duration.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration: {}", duration))?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.,
+        "" | "s" => number,
+        "m" => number * 60.,
+        "h" => number * 3600.,
+        _ => return Err(format!("invalid duration unit: {}", unit)),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("invalid duration: {}", duration))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_budget_runs_out_once() {
+        let budget = Budget::new(Some(1000), None);
+        budget.add_bytes(999);
+        assert_eq!(budget.exhausted(), None);
+        budget.add_bytes(1);
+        assert_eq!(budget.newly_exhausted(), Some(BudgetLimit::Bytes));
+        assert_eq!(budget.newly_exhausted(), None);
+        assert_eq!(budget.exhausted(), Some(BudgetLimit::Bytes));
+        assert_eq!(
+            Budget::new(None, Some(Duration::ZERO)).exhausted(),
+            Some(BudgetLimit::Time)
+        );
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("1.5m"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2"), Ok(Duration::from_secs(2)));
+        assert!(parse_duration("2d").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("99999999999999999999h").is_err());
+    }
+
+    #[test]
+    fn unrepresentable_deadline_is_no_limit() {
+        let max_time = parse_duration("18000000000000000000s").unwrap();
+        let budget = Budget::new(None, Some(max_time));
+        assert_eq!(budget.exhausted(), None);
+    }
+}
@@ -1,4 +1,17 @@
+use crate::budget;
+use crate::config_file;
+use crate::dns::{self, ResolveOverride, Resolver, ResolverSpec};
+use crate::freshness::{FreshnessCheck, StaleAction};
+use crate::http::{self, HttpSettings, Identity};
 use crate::mirror::Mirror;
+use crate::net::IpFamily;
+use crate::progress::ProgressEvent;
+use crate::ranker::RankerOptions;
+use crate::repo_check::RepoCheck;
+use crate::report::ReportFormat;
+use crate::score::ScoreFunction;
+use crate::speed_test::RankBy;
+use crate::target_configs::alpine::AlpineTarget;
 use crate::target_configs::arch4edu::Arch4eduTarget;
 use crate::target_configs::archarm::ArcharmTarget;
 use crate::target_configs::archlinux::ArchTarget;
@@ -8,24 +21,31 @@ use crate::target_configs::artix::ArtixTarget;
 use crate::target_configs::blackarch::BlackArchTarget;
 use crate::target_configs::cachyos::CachyOSTarget;
 use crate::target_configs::chaotic::ChaoticTarget;
-// use crate::target_configs::debian::DebianTarget;
+use crate::target_configs::debian::DebianTarget;
 use crate::target_configs::endeavouros::EndeavourOSTarget;
+use crate::target_configs::fedora::FedoraTarget;
+use crate::target_configs::gentoo::GentooTarget;
 use crate::target_configs::manjaro::ManjaroTarget;
 use crate::target_configs::openbsd::OpenBSDTarget;
+use crate::target_configs::opensuse::OpenSUSETarget;
+use crate::target_configs::pacman_mirrorlist::PacmanMirrorlistTarget;
 use crate::target_configs::rebornos::RebornOSTarget;
 use crate::target_configs::stdin::StdinTarget;
-// use crate::target_configs::ubuntu::UbuntuTarget;
+use crate::target_configs::ubuntu::UbuntuTarget;
 use ambassador::{Delegate, delegatable_trait};
-use clap::{Parser, Subcommand};
+use byte_unit::Byte;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use reqwest::NoProxy;
 use serde::de::DeserializeOwned;
 use std::collections::HashSet;
+use std::env;
 use std::fmt;
 use std::fs;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::mpsc;
 use std::time::Duration;
 use thiserror::Error;
-use tokio::runtime::Runtime;
 use url::Url;
 
 #[derive(Debug, PartialEq, Clone)]
@@ -63,6 +83,16 @@ pub enum AppError {
     BlankOutput,
     #[error("stdout closed")]
     StdoutBrokenPipe,
+    #[error("failed to read history file {path}: {reason}")]
+    InvalidHistory { path: String, reason: String },
+    #[error("could not determine history file location, consider setting --history-file")]
+    NoHistoryFile,
+    #[error("invalid config: {0}")]
+    InvalidConfig(String),
+    #[error("invalid HTTP settings: {0}")]
+    InvalidHttpSettings(String),
+    #[error("no backup of {0} to roll back to")]
+    NoBackup(String),
     #[error(transparent)]
     UrlParseError(#[from] url::ParseError),
     #[error(transparent)]
@@ -89,11 +119,20 @@ impl From<reqwest::Error> for AppError {
 pub trait LogFormatter {
     fn format_comment(&self, message: impl fmt::Display) -> String;
     fn format_mirror(&self, mirror: &Mirror) -> String;
+
+    /// Lines of the final output; targets which combine all mirrors into a
+    /// single entry override it
+    fn format_mirrors(&self, mirrors: &[Mirror]) -> Vec<String> {
+        mirrors.iter().map(|m| self.format_mirror(m)).collect()
+    }
 }
 
 #[delegatable_trait]
 pub trait FetchMirrors {
-    fn fetch_mirrors(&self, tx_progress: mpsc::Sender<String>) -> Result<Vec<Mirror>, AppError>;
+    fn fetch_mirrors(
+        &self,
+        tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError>;
 }
 
 #[derive(Debug, Subcommand, Clone, Delegate)]
@@ -103,6 +142,9 @@ pub enum Target {
     /// accepts lines of urls OR lines with tab-separated urls and countries
     Stdin(StdinTarget),
 
+    /// test alpine mirrors
+    Alpine(AlpineTarget),
+
     /// test archlinux mirrors
     Arch(ArchTarget),
 
@@ -136,10 +178,20 @@ pub enum Target {
     #[command(name = "chaotic-aur")]
     Chaotic(ChaoticTarget),
 
+    /// test debian mirrors
+    Debian(DebianTarget),
+
     /// test endeavouros mirrors
     #[command(name = "endeavouros")]
     EndeavourOS(EndeavourOSTarget),
 
+    /// test fedora, or other MirrorManager-based (Rocky, Alma, CentOS Stream),
+    /// mirrors
+    Fedora(FedoraTarget),
+
+    /// test gentoo distfiles mirrors
+    Gentoo(GentooTarget),
+
     /// test manjaro mirrors
     Manjaro(ManjaroTarget),
 
@@ -147,9 +199,66 @@ pub enum Target {
     #[command(name = "openbsd")]
     OpenBSD(OpenBSDTarget),
 
+    /// test openSUSE mirrors
+    #[command(name = "opensuse")]
+    OpenSUSE(OpenSUSETarget),
+
+    /// test servers of any pacman mirrorlist file, keeping their templates
+    #[command(name = "pacman-mirrorlist")]
+    PacmanMirrorlist(PacmanMirrorlistTarget),
+
     /// test rebornos mirrors
     #[command(name = "rebornos")]
     RebornOS(RebornOSTarget),
+
+    /// test ubuntu mirrors
+    Ubuntu(UbuntuTarget),
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum Command {
+    #[command(flatten)]
+    Target(Target),
+
+    /// show mirrors recorded by previous runs with --history
+    History(HistoryArgs),
+
+    /// inspect configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+
+    /// restore the most recent backup made by --install
+    Rollback(RollbackArgs),
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct RollbackArgs {
+    /// File previously installed with --install
+    pub file: String,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum ConfigCommand {
+    /// print the effective configuration merged from config files, env and
+    /// command line arguments
+    Show(ConfigShowArgs),
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ConfigShowArgs {
+    /// Also show options of this target, e.g. arch
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct HistoryArgs {
+    /// Max number of mirrors to show
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    /// Show only mirrors whose url contains this string
+    #[arg(long)]
+    pub mirror: Option<String>,
 }
 
 fn parse_positive_usize(s: &str) -> Result<usize, String> {
@@ -169,12 +278,31 @@ fn parse_positive_usize(s: &str) -> Result<usize, String> {
     rename_all_env = "SCREAMING_SNAKE_CASE"
 )]
 pub struct Config {
-    /// Per-mirror speed test timeout in milliseconds
     #[command(subcommand)]
-    pub target: Target,
+    pub command: Command,
+
+    /// Config file to read instead of /etc/rate-mirrors.toml and
+    ///   $XDG_CONFIG_HOME/rate-mirrors/config.toml
+    #[arg(
+        env = "RATE_MIRRORS_CONFIG",
+        long = "config",
+        name = "config_file",
+        value_name = "FILE",
+        verbatim_doc_comment
+    )]
+    pub config_file: Option<String>,
+
+    /// Apply settings of the [profile.<name>] config file section
+    #[arg(env = "RATE_MIRRORS_PROFILE", long)]
+    pub profile: Option<String>,
 
     /// Test only specified protocols (can be passed multiple times)
-    #[arg(env = "RATE_MIRRORS_PROTOCOL", long = "protocol", name = "protocol")]
+    #[arg(
+        env = "RATE_MIRRORS_PROTOCOL",
+        long = "protocol",
+        name = "protocol",
+        value_delimiter = ','
+    )]
     pub protocols: Vec<Protocol>,
 
     /// Per-mirror speed test timeout in milliseconds
@@ -284,6 +412,143 @@ pub struct Config {
     )]
     pub top_mirrors_number_to_retest: usize,
 
+    /// Number of times to re-test each top mirror; with more than one sample,
+    ///   mirrors are ranked by the lower bound of the 95% confidence interval
+    ///   of their speed, failed samples counting as zero
+    #[arg(
+        env = "RATE_MIRRORS_SAMPLES",
+        long,
+        default_value = "1",
+        value_parser = parse_positive_usize,
+        verbatim_doc_comment
+    )]
+    pub samples: usize,
+
+    /// Test mirrors over IPv4 or IPv6 only, dropping mirrors without an
+    ///   address of the family, or over both separately; one of: 4, 6, both
+    #[arg(env = "RATE_MIRRORS_IP_FAMILY", long, verbatim_doc_comment)]
+    pub ip_family: Option<IpFamily>,
+
+    /// Stop starting speed tests once they downloaded this much in total,
+    ///   e.g. 50MB, and rank the mirrors tested so far
+    #[arg(env = "RATE_MIRRORS_MAX_TOTAL_BYTES", long, verbatim_doc_comment)]
+    pub max_total_bytes: Option<Byte>,
+
+    /// Stop starting speed tests after this long in total, e.g. 30s or 2m,
+    ///   and rank the mirrors tested so far
+    #[arg(
+        env = "RATE_MIRRORS_MAX_TOTAL_TIME",
+        long,
+        value_parser = budget::parse_duration,
+        verbatim_doc_comment
+    )]
+    pub max_total_time: Option<Duration>,
+
+    /// Metered connections: measure speeds by a few ranged requests of
+    ///   doubling sizes (--lite-probes, --lite-probe-bytes), 240 KiB per
+    ///   test by default; mirrors ignoring ranges are cut off at as much
+    #[arg(env = "RATE_MIRRORS_LITE", long, verbatim_doc_comment)]
+    pub lite: bool,
+
+    /// Number of ranged requests per speed test of --lite, 2 to 16
+    #[arg(
+        env = "RATE_MIRRORS_LITE_PROBES",
+        long,
+        default_value = "4",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(2..=16)
+    )]
+    pub lite_probes: usize,
+
+    /// Size of the first ranged request of --lite, each next one doubles it
+    #[arg(
+        env = "RATE_MIRRORS_LITE_PROBE_BYTES",
+        long,
+        default_value = "16384",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub lite_probe_bytes: usize,
+
+    /// Re-test top mirrors over kept-alive connections, opened by a HEAD
+    ///   request first, so that the re-test speed and connection time
+    ///   exclude the connection set-up (reported as warm-up)
+    #[arg(env = "RATE_MIRRORS_WARM_RETEST", long, verbatim_doc_comment)]
+    pub warm_retest: bool,
+
+    /// What to rank mirrors by, one of: speed, latency;
+    ///   latency only measures time to first byte of small range requests
+    ///   and skips downloads entirely
+    #[arg(
+        env = "RATE_MIRRORS_RANK_BY",
+        long,
+        default_value = "speed",
+        verbatim_doc_comment
+    )]
+    pub rank_by: RankBy,
+
+    /// Number of requests per mirror with --rank-by=latency,
+    ///   the median of their latencies is used
+    #[arg(
+        env = "RATE_MIRRORS_LATENCY_PROBES",
+        long,
+        default_value = "5",
+        value_parser = parse_positive_usize,
+        verbatim_doc_comment
+    )]
+    pub latency_probes: usize,
+
+    /// Rank by a weighted sum of metrics, normalised over the run, instead
+    ///   of speed alone, e.g. "speed*0.6 + latency*0.2 + freshness*0.2";
+    ///   metrics: speed, latency, freshness (sync delay), reliability
+    ///   (upstream score); presets: balanced, fresh
+    #[arg(env = "RATE_MIRRORS_SCORE", long, verbatim_doc_comment)]
+    pub score: Option<ScoreFunction>,
+
+    /// Compare sync markers of ranked mirrors and drop or demote the ones
+    ///   lagging behind the freshest one (or --freshness-reference)
+    #[arg(env = "RATE_MIRRORS_VERIFY_FRESHNESS", long, verbatim_doc_comment)]
+    pub verify_freshness: bool,
+
+    /// Sync marker relative to mirror URLs holding a unix timestamp, e.g.
+    ///   lastsync; Last-Modified of the tested file is compared by default
+    #[arg(env = "RATE_MIRRORS_FRESHNESS_PATH", long, verbatim_doc_comment)]
+    pub freshness_path: Option<String>,
+
+    /// Trusted mirror URL to compare sync markers with
+    #[arg(env = "RATE_MIRRORS_FRESHNESS_REFERENCE", long)]
+    pub freshness_reference: Option<Url>,
+
+    /// Max lag in seconds behind the reference before a mirror is stale
+    #[arg(env = "RATE_MIRRORS_MAX_SYNC_LAG", long, default_value = "86400")]
+    pub max_sync_lag: i64,
+
+    /// What to do with stale mirrors, one of: drop, demote
+    #[arg(env = "RATE_MIRRORS_STALE_MIRRORS", long, default_value = "demote")]
+    pub stale_mirrors: StaleAction,
+
+    /// Download the repository database ($repo.db next to the tested
+    ///   $repo.files) of top mirrors, going down the ranking until
+    ///   --max-mirrors-to-output of them pass, and exclude the ones serving
+    ///   an invalid one or lacking packages most mirrors (or
+    ///   --verify-repo-reference) have
+    #[arg(env = "RATE_MIRRORS_VERIFY_REPO", long, verbatim_doc_comment)]
+    pub verify_repo: bool,
+
+    /// Trusted mirror URL to compare repository databases with
+    #[arg(env = "RATE_MIRRORS_VERIFY_REPO_REFERENCE", long)]
+    pub verify_repo_reference: Option<Url>,
+
+    /// With --verify-repo, also require the signature file of a package
+    #[arg(env = "RATE_MIRRORS_VERIFY_REPO_SIGNATURES", long)]
+    pub verify_repo_signatures: bool,
+
+    /// Timeout in milliseconds of downloading a repository database
+    #[arg(
+        env = "RATE_MIRRORS_VERIFY_REPO_TIMEOUT",
+        long,
+        default_value = "30000"
+    )]
+    pub verify_repo_timeout: u64,
+
     /// Max number of mirrors to output
     #[arg(env = "RATE_MIRRORS_MAX_MIRRORS_TO_OUTPUT", long, value_parser = parse_positive_usize)]
     pub max_mirrors_to_output: Option<usize>,
@@ -292,6 +557,23 @@ pub struct Config {
     #[arg(env = "RATE_MIRRORS_SAVE", long = "save", verbatim_doc_comment)]
     pub save_to_file: Option<String>,
 
+    /// Atomically replace this file with the output in case of success,
+    ///   e.g. /etc/pacman.d/mirrorlist, keeping its mode and owner and
+    ///   backing up previous versions as FILE.rate-mirrors.1..N;
+    ///   system files need root, and thus --allow-root
+    #[arg(
+        env = "RATE_MIRRORS_INSTALL",
+        long,
+        value_name = "FILE",
+        conflicts_with = "save_to_file",
+        verbatim_doc_comment
+    )]
+    pub install: Option<String>,
+
+    /// Number of backups to keep with --install
+    #[arg(env = "RATE_MIRRORS_INSTALL_BACKUPS", long, default_value = "3")]
+    pub install_backups: usize,
+
     /// Allow running by root
     #[arg(env = "RATE_MIRRORS_ALLOW_ROOT", long)]
     pub allow_root: bool,
@@ -308,14 +590,104 @@ pub struct Config {
     #[arg(env = "RATE_MIRRORS_DISABLE_UNTESTED_FALLBACK", long)]
     pub disable_untested_fallback: bool,
 
+    /// Write a machine-readable report of every fetched, filtered, tested
+    ///   and failed mirror to this file; use "-" for stdout, in which case
+    ///   mirrors and comments are not printed to stdout
+    #[arg(env = "RATE_MIRRORS_REPORT", long, verbatim_doc_comment)]
+    pub report: Option<String>,
+
+    /// Report format, one of: json, ndjson
+    #[arg(env = "RATE_MIRRORS_REPORT_FORMAT", long, default_value = "json")]
+    pub report_format: ReportFormat,
+
+    /// Record speed test results to the history file and blend speeds
+    /// measured by previous runs into the ranking
+    #[arg(env = "RATE_MIRRORS_HISTORY", long)]
+    pub history: bool,
+
+    /// History file location
+    ///   [default: $XDG_STATE_HOME/rate-mirrors/history.json]
+    #[arg(env = "RATE_MIRRORS_HISTORY_FILE", long, verbatim_doc_comment)]
+    pub history_file: Option<String>,
+
+    /// Age in hours after which a past measurement counts half as much
+    #[arg(env = "RATE_MIRRORS_HISTORY_HALF_LIFE", long, default_value = "168")]
+    pub history_half_life: f64,
+
+    /// Max share (0-1) of the blended speed which comes from history;
+    ///   the actual share grows with the number of recent past runs
+    #[arg(
+        env = "RATE_MIRRORS_HISTORY_WEIGHT",
+        long,
+        default_value = "0.5",
+        verbatim_doc_comment
+    )]
+    pub history_weight: f64,
+
+    /// Proxy for every request, e.g. http://proxy:3128 or socks5://proxy:1080;
+    ///   HTTP_PROXY, HTTPS_PROXY and ALL_PROXY env vars are used otherwise
+    #[arg(env = "RATE_MIRRORS_PROXY", long, verbatim_doc_comment)]
+    pub proxy: Option<String>,
+
+    /// Comma-separated hosts, domains and networks to bypass --proxy for
+    #[arg(env = "RATE_MIRRORS_NO_PROXY", long, requires = "proxy")]
+    pub no_proxy: Option<String>,
+
+    /// PEM file of additional root certificates to trust
+    #[arg(env = "RATE_MIRRORS_CA_FILE", long)]
+    pub ca_file: Option<PathBuf>,
+
+    /// PEM client certificate for mutual TLS, may include the key
+    #[arg(env = "RATE_MIRRORS_CLIENT_CERT", long)]
+    pub client_cert: Option<PathBuf>,
+
+    /// PEM private key of --client-cert
+    #[arg(env = "RATE_MIRRORS_CLIENT_KEY", long, requires = "client_cert")]
+    pub client_key: Option<PathBuf>,
+
+    /// Accept invalid TLS certificates; for lab use only
+    #[arg(env = "RATE_MIRRORS_INSECURE_SKIP_VERIFY", long)]
+    pub insecure_skip_verify: bool,
+
+    /// DNS resolver of every request: system, doh:<url> (DNS over HTTPS)
+    ///   or static:<file> (hosts file format, nothing else is resolved)
+    #[arg(env = "RATE_MIRRORS_RESOLVER", long, verbatim_doc_comment)]
+    pub resolver: Option<ResolverSpec>,
+
+    /// Resolve a host to an address, like curl: host:port:addr
+    ///   (can be passed multiple times)
+    #[arg(
+        env = "RATE_MIRRORS_RESOLVE",
+        long,
+        value_delimiter = ',',
+        verbatim_doc_comment
+    )]
+    pub resolve: Vec<ResolveOverride>,
+
     /// Pre-parsed set of excluded country codes (lowercase)
     #[arg(skip)]
     pub excluded_countries_set: HashSet<String>,
+
+    /// Lines printed by `config show`
+    #[arg(skip)]
+    pub effective_config: Vec<String>,
 }
 
 impl Config {
-    pub fn new() -> Self {
-        let mut config = Self::parse();
+    pub fn new() -> Result<Self, AppError> {
+        let command = Self::command();
+        let (args, from_files) =
+            config_file::apply_config_files(&command, env::args_os().collect())?;
+        let matches = command.clone().get_matches_from(&args);
+        let mut config = Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        if let Command::Config(ConfigCommand::Show(show_args)) = &config.command {
+            config.effective_config = config_file::effective_config(
+                &command,
+                &matches,
+                show_args.target.as_deref(),
+                &from_files,
+            )?;
+        }
         config.excluded_countries_set = config
             .exclude_countries
             .as_ref()
@@ -326,7 +698,16 @@ impl Config {
                     .collect()
             })
             .unwrap_or_default();
-        config
+        Ok(config)
+    }
+
+    pub fn history_file(&self) -> Result<PathBuf, AppError> {
+        match &self.history_file {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => xdg_state_home()
+                .map(|dir| dir.join("rate-mirrors").join("history.json"))
+                .ok_or(AppError::NoHistoryFile),
+        }
     }
 
     pub fn is_country_excluded(&self, code: &str) -> bool {
@@ -334,6 +715,109 @@ impl Config {
             .contains(&code.to_ascii_lowercase())
     }
 
+    pub fn ranker_options(&self) -> RankerOptions {
+        RankerOptions {
+            per_mirror_timeout: self.per_mirror_timeout,
+            min_per_mirror: self.min_per_mirror,
+            max_per_mirror: self.max_per_mirror,
+            min_bytes_per_mirror: self.min_bytes_per_mirror,
+            eps: self.eps,
+            eps_checks: self.eps_checks,
+            concurrency: self.concurrency,
+            concurrency_for_unlabeled: self.concurrency_for_unlabeled,
+            max_jumps: self.max_jumps,
+            entry_country: self.entry_country.clone(),
+            excluded_countries: self.excluded_countries_set.clone(),
+            country_neighbors_per_country: self.country_neighbors_per_country,
+            country_test_mirrors_per_country: self.country_test_mirrors_per_country,
+            top_mirrors_number_to_retest: self.top_mirrors_number_to_retest,
+            rank_by: self.rank_by,
+            latency_probes: self.latency_probes,
+            samples: self.samples,
+            ip_family: self.ip_family,
+            warm_retest: self.warm_retest,
+            max_total_bytes: self.max_total_bytes.map(|bytes| bytes.as_u64()),
+            max_total_time: self.max_total_time,
+            lite: self.lite,
+            lite_probes: self.lite_probes,
+            lite_probe_bytes: self.lite_probe_bytes,
+        }
+    }
+
+    pub fn freshness_check(&self) -> Option<FreshnessCheck> {
+        self.verify_freshness.then(|| FreshnessCheck {
+            path: self.freshness_path.clone(),
+            reference: self.freshness_reference.clone(),
+            max_lag: self.max_sync_lag,
+            action: self.stale_mirrors,
+            timeout: self.per_mirror_timeout,
+        })
+    }
+
+    /// Reads certificates and keys of HTTP settings
+    pub fn http_settings(&self) -> Result<HttpSettings, AppError> {
+        let read = |path: &PathBuf| {
+            fs::read(path).map_err(|e| {
+                AppError::InvalidHttpSettings(format!("failed to read {}: {}", path.display(), e))
+            })
+        };
+        let invalid = |what: &str, e: String| {
+            AppError::InvalidHttpSettings(format!("invalid {}: {}", what, e))
+        };
+        let proxy = match &self.proxy {
+            Some(proxy) => Some(
+                reqwest::Proxy::all(proxy)
+                    .map_err(|e| invalid("proxy", e.to_string()))?
+                    .no_proxy(self.no_proxy.as_deref().and_then(NoProxy::from_string)),
+            ),
+            None => None,
+        };
+        let root_certificates = match &self.ca_file {
+            Some(path) => {
+                http::parse_certificates(&read(path)?).map_err(|e| invalid("CA file", e))?
+            }
+            None => Vec::new(),
+        };
+        let identity = match &self.client_cert {
+            Some(path) => {
+                let mut pem = read(path)?;
+                if let Some(key) = &self.client_key {
+                    pem.push(b'\n');
+                    pem.extend(read(key)?);
+                }
+                Some(Identity::from_pem(&pem).map_err(|e| invalid("client certificate", e))?)
+            }
+            None => None,
+        };
+        let resolver = match &self.resolver {
+            None | Some(ResolverSpec::System) => Resolver::System,
+            Some(ResolverSpec::Doh(url)) => Resolver::Doh(url.clone()),
+            Some(ResolverSpec::Static(path)) => {
+                let content = String::from_utf8_lossy(&read(path)?).into_owned();
+                Resolver::Static(dns::parse_hosts(&content).map_err(|e| {
+                    AppError::InvalidHttpSettings(format!("invalid {}: {}", path.display(), e))
+                })?)
+            }
+        };
+        Ok(HttpSettings {
+            proxy,
+            root_certificates,
+            identity,
+            insecure_skip_verify: self.insecure_skip_verify,
+            resolver,
+            overrides: dns::overrides_to_hosts(&self.resolve),
+            ip_family: self.ip_family,
+        })
+    }
+
+    pub fn repo_check(&self) -> Option<RepoCheck> {
+        self.verify_repo.then(|| RepoCheck {
+            reference: self.verify_repo_reference.clone(),
+            signatures: self.verify_repo_signatures,
+            timeout: self.verify_repo_timeout,
+        })
+    }
+
     pub fn is_protocol_allowed_for_url(&self, url: &Url) -> bool {
         if self.protocols.is_empty() {
             matches!(url.scheme(), "http" | "https")
@@ -346,15 +830,25 @@ impl Config {
     }
 }
 
+/// `$XDG_STATE_HOME`, falling back to `~/.local/state`
+pub fn xdg_state_home() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+}
+
+/// `$XDG_CONFIG_HOME`, falling back to `~/.config`
+pub fn xdg_config_home() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+/// Shared client of the process, see `http::client`
 pub fn default_client_builder() -> Result<reqwest::Client, AppError> {
-    reqwest::Client::builder()
-        .user_agent(format!(
-            "{}/{}",
-            env!("CARGO_PKG_NAME").replace('_', "-"),
-            env!("CARGO_PKG_VERSION")
-        ))
-        .build()
-        .map_err(|e| AppError::RequestError(format!("failed to build HTTP client: {}", e)))
+    http::client(None, true)
 }
 
 fn convert_reqwest_error(e: reqwest::Error, url: &str) -> AppError {
@@ -366,8 +860,7 @@ fn convert_reqwest_error(e: reqwest::Error, url: &str) -> AppError {
 }
 
 pub fn fetch_json<T: DeserializeOwned>(url: &str, timeout_ms: u64) -> Result<T, AppError> {
-    let runtime = Runtime::new().unwrap();
-    let result = runtime.block_on(async {
+    http::runtime().block_on(async {
         let client = default_client_builder()?;
         let response = client
             .get(url)
@@ -387,14 +880,11 @@ pub fn fetch_json<T: DeserializeOwned>(url: &str, timeout_ms: u64) -> Result<T,
         response.json::<T>().await.map_err(|e| {
             AppError::RequestError(format!("failed to decode JSON from {}: {}", url, e))
         })
-    });
-    runtime.shutdown_timeout(Duration::from_secs(1));
-    result
+    })
 }
 
 pub fn fetch_text(url: &str, timeout_ms: u64) -> Result<String, AppError> {
-    let runtime = Runtime::new().unwrap();
-    let result = runtime.block_on(async {
+    http::runtime().block_on(async {
         let client = default_client_builder()?;
         let response = client
             .get(url)
@@ -414,9 +904,7 @@ pub fn fetch_text(url: &str, timeout_ms: u64) -> Result<String, AppError> {
         response.text_with_charset("utf-8").await.map_err(|e| {
             AppError::RequestError(format!("failed to read response from {}: {}", url, e))
         })
-    });
-    runtime.shutdown_timeout(Duration::from_secs(1));
-    result
+    })
 }
 
 /// Fetches text content from either a remote URL or a local file path.
@@ -487,8 +975,8 @@ mod tests {
     }
 
     fn arch_target(config: &Config) -> &ArchTarget {
-        match &config.target {
-            Target::Arch(target) => target,
+        match &config.command {
+            Command::Target(Target::Arch(target)) => target,
             other => panic!("expected Arch target, got {other:?}"),
         }
     }
@@ -550,4 +1038,19 @@ mod tests {
 
         assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
     }
+
+    #[test]
+    fn lite_probe_options_are_bounded() {
+        let parse =
+            |arg: &str| parse_arch_with_mirror_source_env(None, &["rate-mirrors", arg, "arch"]);
+
+        assert_eq!(parse("--lite-probes=16").unwrap().lite_probes, 16);
+        for arg in [
+            "--lite-probes=1",
+            "--lite-probes=64",
+            "--lite-probe-bytes=0",
+        ] {
+            assert_eq!(parse(arg).unwrap_err().kind(), ErrorKind::ValueValidation);
+        }
+    }
 }
@@ -0,0 +1,535 @@
+// Config files map onto command line options: every value found in a file is
+// added to the arguments as its option unless the option is given on the
+// command line or by its `RATE_MIRRORS_*` env var, so the resulting precedence
+// is config file -> env -> command line.
+//
+// # global options
+// concurrency = 8
+// protocol = ["https"]
+//
+// [target.arch]
+// max_delay = 21600
+//
+// [profile.laptop]
+// concurrency = 2
+//
+// [profile.laptop.target.arch]
+// fetch_first_tier_only = true
+
+use crate::config::{AppError, xdg_config_home};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgMatches, Command};
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+pub const SYSTEM_CONFIG_FILE: &str = "/etc/rate-mirrors.toml";
+
+/// Options which select config files and therefore can't be set in them
+const NOT_CONFIGURABLE: [&str; 2] = ["config", "profile"];
+
+pub fn user_config_file() -> Option<PathBuf> {
+    xdg_config_home().map(|dir| dir.join("rate-mirrors").join("config.toml"))
+}
+
+pub struct ConfigFile {
+    pub path: PathBuf,
+    table: Table,
+}
+
+impl ConfigFile {
+    pub fn parse(path: PathBuf, content: &str) -> Result<Self, AppError> {
+        let table = content
+            .parse::<Table>()
+            .map_err(|e| AppError::InvalidConfig(format!("{}: {}", path.display(), e)))?;
+        Ok(Self { path, table })
+    }
+
+    fn load(path: &Path) -> Result<Option<Self>, AppError> {
+        match fs::read_to_string(path) {
+            Ok(content) => Self::parse(path.to_path_buf(), &content).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(AppError::InvalidConfig(format!(
+                "{}: {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+
+    fn section(&self, keys: &[&str]) -> Result<Option<&Table>, AppError> {
+        let mut table = &self.table;
+        for key in keys {
+            match table.get(*key) {
+                Some(Value::Table(inner)) => table = inner,
+                Some(_) => {
+                    return Err(AppError::InvalidConfig(format!(
+                        "{}: [{}] must be a table",
+                        self.path.display(),
+                        keys.join(".")
+                    )));
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(table))
+    }
+
+    fn has_profile(&self, profile: &str) -> bool {
+        matches!(self.section(&["profile", profile]), Ok(Some(_)))
+    }
+}
+
+/// Loads `explicit_path` if given (it must exist), otherwise the system-wide
+/// and then the per-user config file, whichever exist.
+pub fn load_config_files(explicit_path: Option<&str>) -> Result<Vec<ConfigFile>, AppError> {
+    if let Some(path) = explicit_path {
+        let path = PathBuf::from(path);
+        return match ConfigFile::load(&path)? {
+            Some(file) => Ok(vec![file]),
+            None => Err(AppError::InvalidConfig(format!(
+                "{}: file not found",
+                path.display()
+            ))),
+        };
+    }
+    let mut files = Vec::new();
+    let paths = [Some(PathBuf::from(SYSTEM_CONFIG_FILE)), user_config_file()];
+    for path in paths.iter().flatten() {
+        if let Some(file) = ConfigFile::load(path)? {
+            files.push(file);
+        }
+    }
+    Ok(files)
+}
+
+/// A config file value resolved to the option it sets
+#[derive(Debug, PartialEq)]
+pub struct ConfigValue {
+    /// long name of the option
+    pub long: String,
+    pub env: String,
+    pub value: String,
+    pub path: PathBuf,
+}
+
+fn to_env_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Integer(i) => Some(i.to_string()),
+        Value::Float(f) => Some(f.to_string()),
+        Value::Boolean(b) => Some(b.to_string()),
+        Value::Array(items) => items
+            .iter()
+            .map(to_env_value)
+            .collect::<Option<Vec<_>>>()
+            .map(|items| items.join(",")),
+        Value::Datetime(_) | Value::Table(_) => None,
+    }
+}
+
+fn resolve_section(
+    command: &Command,
+    file: &ConfigFile,
+    table: &Table,
+    section: &str,
+    skip: &[&str],
+    values: &mut Vec<ConfigValue>,
+) -> Result<(), AppError> {
+    for (key, value) in table.iter() {
+        if skip.contains(&key.as_str()) && value.is_table() {
+            continue;
+        }
+        let long = key.replace('_', "-");
+        let env = command
+            .get_arguments()
+            .filter(|_| !NOT_CONFIGURABLE.contains(&long.as_str()))
+            .find(|arg| arg.get_long() == Some(long.as_str()))
+            .and_then(|arg| arg.get_env())
+            .ok_or_else(|| {
+                AppError::InvalidConfig(format!(
+                    "{}: unknown option `{}` in {}",
+                    file.path.display(),
+                    key,
+                    section
+                ))
+            })?;
+        let value = to_env_value(value).ok_or_else(|| {
+            AppError::InvalidConfig(format!(
+                "{}: unsupported value of `{}` in {}",
+                file.path.display(),
+                key,
+                section
+            ))
+        })?;
+        values.push(ConfigValue {
+            long,
+            env: env.to_string_lossy().into_owned(),
+            value,
+            path: file.path.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Resolves config file sections relevant to the given target and profile,
+/// in increasing order of precedence: for each file global options, target
+/// section, profile options, profile target section; later files win.
+pub fn resolve_values(
+    command: &Command,
+    files: &[ConfigFile],
+    target: Option<&str>,
+    profile: Option<&str>,
+) -> Result<Vec<ConfigValue>, AppError> {
+    if let Some(profile) = profile {
+        if !files.iter().any(|file| file.has_profile(profile)) {
+            return Err(AppError::InvalidConfig(format!(
+                "profile `{}` is not defined in any config file",
+                profile
+            )));
+        }
+    }
+    let target_command = target.and_then(|name| command.find_subcommand(name));
+
+    let mut values = Vec::new();
+    for file in files {
+        let mut prefixes = vec![vec![]];
+        if let Some(profile) = profile {
+            prefixes.push(vec!["profile", profile]);
+        }
+        for prefix in prefixes {
+            if let Some(table) = file.section(&prefix)? {
+                let name = if prefix.is_empty() {
+                    "global options".to_string()
+                } else {
+                    format!("[{}]", prefix.join("."))
+                };
+                resolve_section(
+                    command,
+                    file,
+                    table,
+                    &name,
+                    &["target", "profile"],
+                    &mut values,
+                )?;
+            }
+            if let (Some(target), Some(target_command)) = (target, target_command) {
+                let keys: Vec<&str> = prefix.iter().copied().chain(["target", target]).collect();
+                if let Some(table) = file.section(&keys)? {
+                    let name = format!("[{}]", keys.join("."));
+                    resolve_section(target_command, file, table, &name, &[], &mut values)?;
+                }
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Target whose config sections apply: the target subcommand itself or the
+/// one passed to `config show`
+fn selected_target(matches: &ArgMatches) -> Option<String> {
+    match matches.subcommand()? {
+        ("config", config_matches) => config_matches
+            .subcommand_matches("show")?
+            .get_one::<String>("target")
+            .cloned(),
+        (name, _) => Some(name.to_string()),
+    }
+}
+
+/// `--long=value` of a config value, nothing for an unset flag
+fn to_arg(arg: &Arg, value: &ConfigValue) -> Option<OsString> {
+    if arg.get_action().takes_values() {
+        return Some(format!("--{}={}", value.long, value.value).into());
+    }
+    // falsey values as of env vars of flags
+    let unset = ["", "n", "no", "f", "false", "off", "0"].contains(&value.value.as_str());
+    (!unset).then(|| format!("--{}", value.long).into())
+}
+
+fn given_on_command_line(matches: &ArgMatches, arg: &Arg) -> bool {
+    matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+}
+
+/// Adds config file values to `args` as options, unless these are given on
+/// the command line or by env vars; returns the arguments along with the
+/// values taken from files, by env var. Values of a target selected by
+/// `config show` are only returned.
+pub fn apply_config_files(
+    command: &Command,
+    mut args: Vec<OsString>,
+) -> Result<(Vec<OsString>, HashMap<String, ConfigValue>), AppError> {
+    let matches = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)
+        .ok();
+    let get = |id: &str| {
+        matches
+            .as_ref()
+            .and_then(|m| m.get_one::<String>(id).cloned())
+    };
+    let files = load_config_files(get("config_file").as_deref())?;
+    let target = matches.as_ref().and_then(selected_target);
+    let values = resolve_values(
+        command,
+        &files,
+        target.as_deref(),
+        get("profile").as_deref(),
+    )?;
+
+    let target_matches = target
+        .as_deref()
+        .and_then(|name| matches.as_ref()?.subcommand_matches(name));
+    let target_command = target
+        .as_deref()
+        .and_then(|name| command.find_subcommand(name));
+    let mut global_args = Vec::new();
+    let mut target_args = Vec::new();
+    let mut applied = HashMap::new();
+    // later values win, being resolved in increasing order of precedence
+    for value in values.into_iter().rev() {
+        if applied.contains_key(&value.env) || env::var_os(&value.env).is_some() {
+            continue;
+        }
+        let has_env = |arg: &&Arg| arg.get_env().is_some_and(|env| env == value.env.as_str());
+        if let Some(arg) = command.get_arguments().find(has_env) {
+            match &matches {
+                Some(matches) if given_on_command_line(matches, arg) => continue,
+                _ => global_args.extend(to_arg(arg, &value)),
+            }
+        } else if let Some(arg) = target_command.and_then(|c| c.get_arguments().find(has_env)) {
+            match target_matches {
+                Some(matches) if given_on_command_line(matches, arg) => continue,
+                Some(_) => target_args.extend(to_arg(arg, &value)),
+                None => {}
+            }
+        }
+        applied.insert(value.env.clone(), value);
+    }
+
+    // global options go before the subcommand, its options to the end (or
+    // before `--`), targets taking no positional arguments
+    let end = args.iter().position(|a| a == "--").unwrap_or(args.len());
+    args.splice(end..end, target_args);
+    let start = args.len().min(1);
+    args.splice(start..start, global_args);
+    Ok((args, applied))
+}
+
+fn effective_line(arg: &Arg, value: Option<String>, source: String) -> Option<String> {
+    let key = arg.get_long()?.replace('-', "_");
+    Some(match value {
+        Some(value) => format!("{} = {:?} # {}", key, value, source),
+        None => format!("# {} = (unset)", key),
+    })
+}
+
+fn file_source(value: &ConfigValue) -> String {
+    format!("file {}", value.path.display())
+}
+
+/// Renders options in config file syntax, each one annotated with where its
+/// value came from: default, file, env or cli. Options of `target` are
+/// listed in its own section; as they can't be passed on the command line
+/// here, only defaults, files and env apply to them.
+pub fn effective_config(
+    command: &Command,
+    matches: &ArgMatches,
+    target: Option<&str>,
+    from_files: &HashMap<String, ConfigValue>,
+) -> Result<Vec<String>, AppError> {
+    let mut lines = Vec::new();
+    for arg in command.get_arguments() {
+        let Some(env) = arg.get_env().map(|e| e.to_string_lossy().into_owned()) else {
+            continue;
+        };
+        let id = arg.get_id().as_str();
+        let value = matches.get_raw(id).map(|values| {
+            values
+                .map(|v| v.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join(",")
+        });
+        let source = match (matches.value_source(id), from_files.get(&env)) {
+            (Some(ValueSource::CommandLine), Some(value)) => file_source(value),
+            (Some(ValueSource::CommandLine), None) => "cli".to_string(),
+            (Some(ValueSource::EnvVariable), _) => format!("env {}", env),
+            _ => "default".to_string(),
+        };
+        lines.extend(effective_line(arg, value, source));
+    }
+
+    if let Some(target) = target {
+        let target_command = command
+            .find_subcommand(target)
+            .filter(|_| !["config", "history", "rollback", "help"].contains(&target))
+            .ok_or_else(|| AppError::InvalidConfig(format!("unknown target `{}`", target)))?;
+        lines.push(String::new());
+        lines.push(format!("[target.{}]", target));
+        for arg in target_command.get_arguments() {
+            let Some(env) = arg.get_env().map(|e| e.to_string_lossy().into_owned()) else {
+                continue;
+            };
+            let (value, source) = match (env::var(&env), from_files.get(&env)) {
+                (Ok(value), _) => (Some(value), format!("env {}", env)),
+                (Err(_), Some(value)) => (Some(value.value.clone()), file_source(value)),
+                (Err(_), None) => {
+                    let defaults: Vec<_> = arg
+                        .get_default_values()
+                        .iter()
+                        .map(|v| v.to_string_lossy().into_owned())
+                        .collect();
+                    let value = (!defaults.is_empty()).then(|| defaults.join(","));
+                    (value, "default".to_string())
+                }
+            };
+            lines.extend(effective_line(arg, value, source));
+        }
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{self, Config, Target};
+    use clap::{CommandFactory, Parser};
+
+    fn file(name: &str, content: &str) -> ConfigFile {
+        ConfigFile::parse(PathBuf::from(name), content).unwrap()
+    }
+
+    fn resolved(values: &[ConfigValue]) -> Vec<(&str, &str)> {
+        values
+            .iter()
+            .map(|v| (v.env.as_str(), v.value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn maps_sections_onto_env_vars_in_precedence_order() {
+        let system = file(
+            "system.toml",
+            r#"
+            concurrency = 8
+            protocol = ["https", "http"]
+
+            [target.arch]
+            max_delay = 3600
+
+            [target.manjaro]
+            branch = "testing"
+            "#,
+        );
+        let user = file(
+            "user.toml",
+            r#"
+            concurrency = 4
+
+            [profile.laptop]
+            max-jumps = 3
+
+            [profile.laptop.target.arch]
+            fetch_first_tier_only = true
+            "#,
+        );
+
+        let values = resolve_values(
+            &Config::command(),
+            &[system, user],
+            Some("arch"),
+            Some("laptop"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            resolved(&values),
+            [
+                ("RATE_MIRRORS_CONCURRENCY", "8"),
+                ("RATE_MIRRORS_PROTOCOL", "https,http"),
+                ("RATE_MIRRORS_MAX_DELAY", "3600"),
+                ("RATE_MIRRORS_CONCURRENCY", "4"),
+                ("RATE_MIRRORS_MAX_JUMPS", "3"),
+                ("RATE_MIRRORS_FETCH_FIRST_TIER_ONLY", "true"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        let config = file("bad.toml", "[target.arch]\nno_such_option = 1\n");
+        let err = resolve_values(&Config::command(), &[config], Some("arch"), None).unwrap_err();
+        assert!(err.to_string().contains("no_such_option"));
+    }
+
+    #[test]
+    fn rejects_options_selecting_config_files() {
+        let config = file("bad.toml", "profile = \"laptop\"\n");
+        assert!(resolve_values(&Config::command(), &[config], None, None).is_err());
+    }
+
+    #[test]
+    fn effective_config_reports_value_sources() {
+        let command = Config::command();
+        let matches = command
+            .clone()
+            .try_get_matches_from(["rate-mirrors", "--max-jumps=3", "config", "show", "arch"])
+            .unwrap();
+        let lines = effective_config(&command, &matches, Some("arch"), &HashMap::new()).unwrap();
+
+        assert!(lines.contains(&"max_jumps = \"3\" # cli".to_string()));
+        assert!(lines.contains(&"concurrency = \"16\" # default".to_string()));
+        assert!(lines.contains(&"# save = (unset)".to_string()));
+        assert!(lines.contains(&"[target.arch]".to_string()));
+        assert!(lines.contains(&"max_delay = \"86400\" # default".to_string()));
+    }
+
+    #[test]
+    fn applies_values_as_arguments_unless_given() {
+        let path = env::temp_dir().join(format!("rate-mirrors-config-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "concurrency = 2\nmax_jumps = 9\nallow_root = true\n\n[target.arch]\nmax_delay = 3600\n",
+        )
+        .unwrap();
+        let config_arg = format!("--config={}", path.display());
+        let apply = |args: &[&str]| {
+            let args = ["rate-mirrors", config_arg.as_str()]
+                .into_iter()
+                .chain(args.iter().copied());
+            apply_config_files(&Config::command(), args.map(OsString::from).collect()).unwrap()
+        };
+
+        let (args, from_files) = apply(&["--max-jumps=5", "arch", "--", "ignored"]);
+        let config = Config::try_parse_from(&args[..args.len() - 2]).unwrap();
+        assert_eq!(config.concurrency, 2);
+        assert_eq!(config.max_jumps, 5);
+        assert!(config.allow_root);
+        match &config.command {
+            config::Command::Target(Target::Arch(target)) => {
+                assert_eq!(target.max_delay, 3600)
+            }
+            other => panic!("expected Arch target, got {other:?}"),
+        }
+        assert_eq!(args[args.len() - 2..], ["--", "ignored"]);
+        assert!(!from_files.contains_key("RATE_MIRRORS_MAX_JUMPS"));
+        assert_eq!(from_files["RATE_MIRRORS_MAX_DELAY"].path, path);
+
+        // options of the target shown are not passed to `config show`
+        let (args, from_files) = apply(&["config", "show", "arch"]);
+        assert!(Config::try_parse_from(&args).is_ok());
+        assert_eq!(from_files["RATE_MIRRORS_MAX_DELAY"].value, "3600");
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn rejects_unknown_profile() {
+        let config = file("config.toml", "concurrency = 1\n");
+        assert!(resolve_values(&Config::command(), &[config], None, Some("nope")).is_err());
+    }
+}
@@ -33,6 +33,8 @@ impl Hash for Country {
 }
 impl Eq for Country {}
 impl Country {
+    /// Case-insensitive lookup by 2-letter code or by name
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<&'static Country> {
         lazy_static! {
             static ref COUNTRIES: Vec<Country> = vec![
@@ -0,0 +1,397 @@
+use crate::net::IpFamily;
+use reqwest::Client;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use url::Url;
+
+const DOH_TIMEOUT: Duration = Duration::from_secs(5);
+const DNS_MESSAGE: &str = "application/dns-message";
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+
+/// Addresses of host names (lowercase), of a hosts file or `--resolve`
+pub type Hosts = HashMap<String, Vec<IpAddr>>;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// `--resolver`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolverSpec {
+    System,
+    /// DNS over HTTPS (RFC 8484) server
+    Doh(Url),
+    /// hosts file, nothing else is resolved
+    Static(PathBuf),
+}
+
+impl FromStr for ResolverSpec {
+    type Err = String;
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        match spec.split_once(':') {
+            None if spec == "system" => Ok(ResolverSpec::System),
+            Some(("doh", url)) => Url::parse(url)
+                .map(ResolverSpec::Doh)
+                .map_err(|e| format!("invalid DoH URL: {}", e)),
+            Some(("static", path)) if !path.is_empty() => Ok(ResolverSpec::Static(path.into())),
+            _ => Err("expected one of: system, doh:<url>, static:<file>".to_string()),
+        }
+    }
+}
+
+/// `--resolve host:port:addr`; the port is matched by nothing, as resolvers
+/// aren't told the port, and is accepted for compatibility with curl
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolveOverride {
+    pub host: String,
+    pub port: u16,
+    pub addr: IpAddr,
+}
+
+impl FromStr for ResolveOverride {
+    type Err = String;
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected host:port:addr, got {}", spec);
+        let (host, rest) = spec.split_once(':').ok_or_else(invalid)?;
+        let (port, addr) = rest.split_once(':').ok_or_else(invalid)?;
+        let addr = addr.trim_start_matches('[').trim_end_matches(']');
+        Ok(ResolveOverride {
+            host: host.to_ascii_lowercase(),
+            port: port.parse().map_err(|_| invalid())?,
+            addr: addr.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Source of addresses of every HTTP client, see `HttpSettings`
+#[derive(Debug, Clone, Default)]
+pub enum Resolver {
+    #[default]
+    System,
+    Doh(Url),
+    Static(Hosts),
+}
+
+/// Reads a file of the `/etc/hosts` format
+pub fn parse_hosts(content: &str) -> Result<Hosts, String> {
+    let mut hosts = Hosts::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let Some(addr) = fields.next() else {
+            continue;
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid address on line {}", number + 1))?;
+        for name in fields {
+            hosts
+                .entry(name.to_ascii_lowercase())
+                .or_default()
+                .push(addr);
+        }
+    }
+    Ok(hosts)
+}
+
+pub fn overrides_to_hosts(overrides: &[ResolveOverride]) -> Hosts {
+    let mut hosts = Hosts::new();
+    for entry in overrides {
+        hosts
+            .entry(entry.host.clone())
+            .or_default()
+            .push(entry.addr);
+    }
+    hosts
+}
+
+fn addrs(ips: Vec<IpAddr>) -> Addrs {
+    Box::new(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)))
+}
+
+/// Resolves names of `hosts`, others by `fallback` if any
+pub struct HostsResolver {
+    pub hosts: Hosts,
+    pub fallback: Option<Arc<dyn Resolve>>,
+}
+
+impl Resolve for HostsResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        if let Some(ips) = self.hosts.get(&name.as_str().to_ascii_lowercase()) {
+            let ips = ips.clone();
+            return Box::pin(async move { Ok(addrs(ips)) });
+        }
+        match &self.fallback {
+            Some(fallback) => fallback.resolve(name),
+            None => {
+                let error = format!("no address of {} in the hosts file", name.as_str());
+                Box::pin(async move { Err(error.into()) })
+            }
+        }
+    }
+}
+
+/// Resolves names by a DNS over HTTPS server, keeping answers for the run
+pub struct DohResolver {
+    url: Url,
+    /// resolves the name of the server by the system resolver
+    client: Client,
+    /// `--ip-family`; a failed query of addresses of the other family is
+    /// taken as none, as are failed AAAA queries unless IPv6 is requested
+    family: Option<IpFamily>,
+    cache: Arc<Mutex<HashMap<String, Vec<IpAddr>>>>,
+}
+
+impl DohResolver {
+    pub fn new(url: Url, client: Client, family: Option<IpFamily>) -> Self {
+        Self {
+            url,
+            client,
+            family,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl Resolve for DohResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let name = name.as_str().to_ascii_lowercase();
+        let (url, client, cache) = (self.url.clone(), self.client.clone(), self.cache.clone());
+        let family = self.family;
+        Box::pin(async move {
+            if let Some(ips) = cache.lock().unwrap().get(&name) {
+                return Ok(addrs(ips.clone()));
+            }
+            let (v4, v6) = futures::join!(
+                query(&client, &url, &name, TYPE_A),
+                query(&client, &url, &name, TYPE_AAAA)
+            );
+            let (mut ips, v6) = match family {
+                Some(IpFamily::V6) => (v4.unwrap_or_default(), v6?),
+                _ => (v4?, v6.unwrap_or_default()),
+            };
+            ips.extend(v6);
+            if ips.is_empty() {
+                return Err(format!("no address of {} from {}", name, url).into());
+            }
+            cache.lock().unwrap().insert(name, ips.clone());
+            Ok(addrs(ips))
+        })
+    }
+}
+
+async fn query(
+    client: &Client,
+    url: &Url,
+    name: &str,
+    qtype: u16,
+) -> Result<Vec<IpAddr>, BoxError> {
+    let response = client
+        .post(url.as_str())
+        .header(CONTENT_TYPE, DNS_MESSAGE)
+        .header(ACCEPT, DNS_MESSAGE)
+        .body(encode_query(name, qtype)?)
+        .timeout(DOH_TIMEOUT)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(format!("DoH server answered HTTP {}", response.status().as_u16()).into());
+    }
+    Ok(decode_answers(&response.bytes().await?)?)
+}
+
+fn encode_query(name: &str, qtype: u16) -> Result<Vec<u8>, String> {
+    // id 0 as RFC 8484 recommends, recursion desired, one question
+    let mut message = vec![0, 0, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("invalid host name {}", name));
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&qtype.to_be_bytes());
+    message.extend_from_slice(&[0, 1]);
+    Ok(message)
+}
+
+/// Addresses of A and AAAA records of the answer section of a DNS response
+fn decode_answers(message: &[u8]) -> Result<Vec<IpAddr>, String> {
+    let invalid = || "invalid DNS response".to_string();
+    let u16_at = |at: usize| -> Result<u16, String> {
+        let bytes = message.get(at..at + 2).ok_or_else(invalid)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+    let rcode = u16_at(2)? & 0x000f;
+    if rcode != 0 {
+        return Err(format!("DNS error, rcode {}", rcode));
+    }
+    let (questions, answers) = (u16_at(4)?, u16_at(6)?);
+    let mut at = 12;
+    for _ in 0..questions {
+        at = skip_name(message, at).ok_or_else(invalid)? + 4;
+    }
+    let mut ips = Vec::new();
+    for _ in 0..answers {
+        at = skip_name(message, at).ok_or_else(invalid)?;
+        let (rtype, length) = (u16_at(at)?, u16_at(at + 8)? as usize);
+        let data = message.get(at + 10..at + 10 + length).ok_or_else(invalid)?;
+        match (rtype, data.len()) {
+            (TYPE_A, 4) => ips.push(Ipv4Addr::from(<[u8; 4]>::try_from(data).unwrap()).into()),
+            (TYPE_AAAA, 16) => ips.push(Ipv6Addr::from(<[u8; 16]>::try_from(data).unwrap()).into()),
+            // CNAMEs and the like, the addresses of their targets follow
+            _ => {}
+        }
+        at += 10 + length;
+    }
+    Ok(ips)
+}
+
+/// Position right after the (possibly compressed) name at `at`
+fn skip_name(message: &[u8], mut at: usize) -> Option<usize> {
+    loop {
+        let length = *message.get(at)?;
+        match length {
+            0 => return Some(at + 1),
+            // a pointer ends the name
+            _ if length & 0xc0 == 0xc0 => return Some(at + 2),
+            _ => at += 1 + length as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_resolver_options() {
+        assert_eq!("system".parse(), Ok(ResolverSpec::System));
+        assert_eq!(
+            "static:/etc/hosts".parse(),
+            Ok(ResolverSpec::Static("/etc/hosts".into()))
+        );
+        assert!(matches!(
+            "doh:https://dns.example.org/dns-query".parse(),
+            Ok(ResolverSpec::Doh(_))
+        ));
+        assert!("doh".parse::<ResolverSpec>().is_err());
+
+        let entry: ResolveOverride = "Mirror.example.org:443:[::1]".parse().unwrap();
+        assert_eq!(entry.host, "mirror.example.org");
+        assert_eq!(entry.addr, IpAddr::from(Ipv6Addr::LOCALHOST));
+        assert!("mirror.example.org:443".parse::<ResolveOverride>().is_err());
+
+        let hosts = parse_hosts(
+            "# mirrors\n127.0.0.1 a.example.org B.example.org\n\n::1 a.example.org # v6\n",
+        )
+        .unwrap();
+        assert_eq!(hosts["a.example.org"].len(), 2);
+        assert_eq!(
+            hosts["b.example.org"],
+            vec![IpAddr::from(Ipv4Addr::LOCALHOST)]
+        );
+        assert!(parse_hosts("localhost 127.0.0.1").is_err());
+    }
+
+    #[test]
+    fn decodes_dns_answers() {
+        let mut message = encode_query("mirror.example.org", TYPE_A).unwrap();
+        // response, 1 question, 2 answers: a CNAME and an A record
+        message[2..8].copy_from_slice(&[0x81, 0x80, 0, 1, 0, 2]);
+        message.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 6]);
+        message.extend_from_slice(&[3, b'c', b'd', b'n', 0xc0, 19]);
+        message.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+        message.extend_from_slice(&[192, 0, 2, 1]);
+        assert_eq!(
+            decode_answers(&message),
+            Ok(vec![IpAddr::from([192, 0, 2, 1])])
+        );
+
+        message[3] = 0x83;
+        assert_eq!(
+            decode_answers(&message),
+            Err("DNS error, rcode 3".to_string())
+        );
+        assert!(decode_answers(&message[..20]).is_err());
+    }
+
+    /// DoH server answering A queries with 127.0.0.1 and AAAA ones with
+    /// HTTP 500
+    async fn serve_without_aaaa(listener: tokio::net::TcpListener) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                while let Ok(read @ 1..) = stream.read(&mut buffer).await {
+                    request.extend_from_slice(&buffer[..read]);
+                    let head = String::from_utf8_lossy(&request).to_ascii_lowercase();
+                    let Some(head_end) = head.find("\r\n\r\n").map(|at| at + 4) else {
+                        continue;
+                    };
+                    let length: usize = head
+                        .split("content-length: ")
+                        .nth(1)
+                        .and_then(|rest| rest.split("\r\n").next()?.parse().ok())
+                        .unwrap();
+                    if request.len() < head_end + length {
+                        continue;
+                    }
+                    let body: Vec<u8> = request.drain(..head_end + length).skip(head_end).collect();
+                    let response = match u16::from_be_bytes([body[length - 4], body[length - 3]]) {
+                        TYPE_A => {
+                            let mut answer = body;
+                            answer[2..8].copy_from_slice(&[0x81, 0x80, 0, 1, 0, 1]);
+                            answer.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+                            answer.extend_from_slice(&[127, 0, 0, 1]);
+                            let mut response = format!(
+                                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+                                DNS_MESSAGE,
+                                answer.len()
+                            )
+                            .into_bytes();
+                            response.extend(answer);
+                            response
+                        }
+                        _ => b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n"
+                            .to_vec(),
+                    };
+                    if stream.write_all(&response).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn failed_aaaa_queries_mean_no_ipv6_addresses() {
+        crate::http::runtime().block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = Url::parse(&format!(
+                "http://{}/dns-query",
+                listener.local_addr().unwrap()
+            ))
+            .unwrap();
+            tokio::spawn(serve_without_aaaa(listener));
+            let resolve = |family| {
+                let resolver = DohResolver::new(url.clone(), Client::new(), family);
+                resolver.resolve(Name::from_str("mirror.example.org").unwrap())
+            };
+
+            for family in [None, Some(IpFamily::V4), Some(IpFamily::Both)] {
+                let addrs: Vec<_> = resolve(family).await.unwrap().collect();
+                assert_eq!(addrs, vec![SocketAddr::from(([127, 0, 0, 1], 0))]);
+            }
+            assert!(resolve(Some(IpFamily::V6)).await.is_err());
+        });
+    }
+}
@@ -0,0 +1,270 @@
+use crate::config::default_client_builder;
+use crate::http;
+use crate::mirror::Mirror;
+use crate::speed_test::SpeedTestResult;
+use chrono::DateTime;
+use futures::StreamExt;
+use futures::stream;
+use reqwest::header::LAST_MODIFIED;
+use serde::Serialize;
+use std::str::FromStr;
+use std::time::Duration;
+use url::Url;
+
+/// Number of sync markers fetched at once
+const CONCURRENCY: usize = 8;
+
+/// What to do with mirrors lagging behind by more than `--max-sync-lag`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StaleAction {
+    /// exclude them from the output
+    Drop,
+    /// move them after up-to-date ones, keeping their order
+    Demote,
+}
+
+impl FromStr for StaleAction {
+    type Err = &'static str;
+    fn from_str(action: &str) -> Result<Self, Self::Err> {
+        match action {
+            "drop" => Ok(StaleAction::Drop),
+            "demote" => Ok(StaleAction::Demote),
+            _ => Err("could not parse stale mirrors action"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Freshness {
+    /// unix timestamp of the sync marker
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub synced_at: Option<i64>,
+    /// seconds behind the reference (the freshest mirror by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lag: Option<i64>,
+    pub stale: bool,
+    /// why the sync marker is unknown; such mirrors are never stale
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Freshness {
+    fn from_marker(marker: Result<i64, String>) -> Self {
+        match marker {
+            Ok(synced_at) => Freshness {
+                synced_at: Some(synced_at),
+                lag: None,
+                stale: false,
+                error: None,
+            },
+            Err(error) => Freshness {
+                synced_at: None,
+                lag: None,
+                stale: false,
+                error: Some(error),
+            },
+        }
+    }
+}
+
+/// Settings of `--verify-freshness`, see `Config::freshness_check`
+#[derive(Debug, Clone)]
+pub struct FreshnessCheck {
+    /// sync marker relative to mirror URLs, e.g. `lastsync`, holding a unix
+    /// timestamp; `Last-Modified` of the tested file is used otherwise
+    pub path: Option<String>,
+    /// mirror to compare with instead of the freshest one
+    pub reference: Option<Url>,
+    /// seconds
+    pub max_lag: i64,
+    pub action: StaleAction,
+    pub timeout: u64,
+}
+
+impl FreshnessCheck {
+    fn marker_url(&self, mirror: &Mirror) -> Option<Url> {
+        match &self.path {
+            Some(path) => mirror.url.join(path).ok(),
+            None => Some(mirror.url_to_test.clone()),
+        }
+    }
+
+    /// Marker of the reference mirror at the same path as the one of `mirror`
+    fn reference_url(&self, reference: &Url, mirror: &Mirror) -> Option<Url> {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => mirror.url.make_relative(&mirror.url_to_test)?,
+        };
+        reference.join(&path).ok()
+    }
+
+    async fn fetch_marker(
+        &self,
+        client: &reqwest::Client,
+        url: Option<Url>,
+    ) -> Result<i64, String> {
+        let url = url.ok_or("no sync marker URL")?;
+        let request = match self.path {
+            Some(_) => client.get(url),
+            None => client.head(url),
+        };
+        let response = request
+            .timeout(Duration::from_millis(self.timeout))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status().as_u16()));
+        }
+        let last_modified = response
+            .headers()
+            .get(LAST_MODIFIED)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+            .map(|date| date.timestamp());
+        if self.path.is_some() {
+            let body = response.text().await.map_err(|e| e.to_string())?;
+            if let Ok(timestamp) = body.trim().parse::<i64>() {
+                return Ok(timestamp);
+            }
+        }
+        last_modified.ok_or_else(|| "no sync marker".to_string())
+    }
+
+    /// Fetches sync markers of `results` and sets their `freshness`. Returns
+    /// the reference timestamp, if any marker is known, and why the marker
+    /// of the `reference` mirror couldn't be used, the freshest mirror being
+    /// compared with instead.
+    pub fn verify(&self, results: &mut [SpeedTestResult]) -> (Option<i64>, Option<String>) {
+        let client = match default_client_builder() {
+            Ok(client) => client,
+            Err(e) => {
+                for result in results.iter_mut() {
+                    result.freshness = Some(Freshness::from_marker(Err(e.to_string())));
+                }
+                return (None, None);
+            }
+        };
+        let (markers, reference) = http::runtime().block_on(async {
+            let markers: Vec<Result<i64, String>> = stream::iter(
+                results
+                    .iter()
+                    .map(|result| self.fetch_marker(&client, self.marker_url(&result.item))),
+            )
+            .buffered(CONCURRENCY)
+            .collect()
+            .await;
+            let reference = match (&self.reference, results.first()) {
+                (Some(reference), Some(result)) => {
+                    let url = self.reference_url(reference, &result.item);
+                    let description = url.as_ref().map_or(reference.to_string(), Url::to_string);
+                    let marker = self.fetch_marker(&client, url).await;
+                    Some(marker.map_err(|e| format!("{} - {}", description, e)))
+                }
+                _ => None,
+            };
+            (markers, reference)
+        });
+
+        let (reference, reference_error) = match reference {
+            Some(Ok(reference)) => (Some(reference), None),
+            Some(Err(e)) => (None, Some(e)),
+            None => (None, None),
+        };
+        let reference = reference.or_else(|| markers.iter().flatten().max().copied());
+        for (result, marker) in results.iter_mut().zip(markers) {
+            let mut freshness = Freshness::from_marker(marker);
+            if let (Some(synced_at), Some(reference)) = (freshness.synced_at, reference) {
+                let lag = (reference - synced_at).max(0);
+                freshness.lag = Some(lag);
+                freshness.stale = lag > self.max_lag;
+            }
+            result.freshness = Some(freshness);
+        }
+        (reference, reference_error)
+    }
+}
+
+pub fn is_stale(result: &SpeedTestResult) -> bool {
+    result.freshness.as_ref().is_some_and(|f| f.stale)
+}
+
+/// Moves stale results after up-to-date ones, keeping the order otherwise
+pub fn demote_stale(results: &mut [SpeedTestResult]) {
+    results.sort_by_key(is_stale);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::speed_test::TestPhase;
+
+    fn result(url: &str, stale: Option<bool>) -> SpeedTestResult {
+        let url = Url::parse(url).unwrap();
+        let mut result = SpeedTestResult::new(
+            Mirror {
+                url_to_test: url.join("core/os/x86_64/core.db").unwrap(),
+                url,
+                country: None,
+                meta: Default::default(),
+            },
+            1000,
+            Duration::from_secs(1),
+            Duration::from_millis(10),
+            TestPhase::Retest,
+        );
+        result.freshness = stale.map(|stale| Freshness {
+            synced_at: Some(0),
+            lag: Some(0),
+            stale,
+            error: None,
+        });
+        result
+    }
+
+    #[test]
+    fn demotes_stale_results_keeping_order() {
+        let mut results = vec![
+            result("https://a.example.org/", Some(true)),
+            result("https://b.example.org/", Some(false)),
+            result("https://c.example.org/", None),
+            result("https://d.example.org/", Some(true)),
+        ];
+        demote_stale(&mut results);
+        let hosts: Vec<_> = results.iter().map(|r| r.item.url.host_str()).collect();
+        assert_eq!(
+            hosts,
+            vec![
+                Some("b.example.org"),
+                Some("c.example.org"),
+                Some("a.example.org"),
+                Some("d.example.org")
+            ]
+        );
+    }
+
+    #[test]
+    fn reference_marker_follows_tested_path() {
+        let check = FreshnessCheck {
+            path: None,
+            reference: None,
+            max_lag: 0,
+            action: StaleAction::Drop,
+            timeout: 1000,
+        };
+        let reference = Url::parse("https://reference.example.org/archlinux/").unwrap();
+        let mirror = result("https://a.example.org/arch/", None).item;
+        assert_eq!(
+            check.reference_url(&reference, &mirror).unwrap().as_str(),
+            "https://reference.example.org/archlinux/core/os/x86_64/core.db"
+        );
+        let check = FreshnessCheck {
+            path: Some("lastsync".to_string()),
+            ..check
+        };
+        assert_eq!(
+            check.marker_url(&mirror).unwrap().as_str(),
+            "https://a.example.org/arch/lastsync"
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use crate::config::{AppError, HistoryArgs};
+use crate::speed_test::{SpeedTestFailure, SpeedTestResult, format_speed};
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Number of most recent records kept per mirror
+const MAX_RECORDS_PER_MIRROR: usize = 32;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryRecord {
+    /// unix timestamp of the run
+    pub timestamp: i64,
+    /// bytes per second, 0 for failed tests
+    pub speed: f64,
+    pub connection_time_ms: u64,
+    pub bytes_downloaded: usize,
+    pub failed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MirrorHistory {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    pub records: Vec<HistoryRecord>,
+}
+
+impl MirrorHistory {
+    /// Exponentially decayed mean speed of past runs along with the sum of
+    /// weights; failed runs count as zero speed.
+    pub fn decayed_speed(&self, now: i64, half_life_hours: f64) -> Option<(f64, f64)> {
+        let half_life_secs = half_life_hours * 3600.;
+        let (weighted_sum, weights) =
+            self.records
+                .iter()
+                .fold((0., 0.), |(weighted_sum, weights), record| {
+                    let age = (now - record.timestamp).max(0) as f64;
+                    let weight = if half_life_secs > 0. {
+                        0.5_f64.powf(age / half_life_secs)
+                    } else {
+                        0.
+                    };
+                    (weighted_sum + weight * record.speed, weights + weight)
+                });
+        if weights > 0. {
+            Some((weighted_sum / weights, weights))
+        } else {
+            None
+        }
+    }
+
+    pub fn failures(&self) -> usize {
+        self.records.iter().filter(|r| r.failed).count()
+    }
+
+    pub fn last(&self) -> Option<&HistoryRecord> {
+        self.records.last()
+    }
+
+    fn push(&mut self, record: HistoryRecord) {
+        self.records.push(record);
+        if self.records.len() > MAX_RECORDS_PER_MIRROR {
+            let extra = self.records.len() - MAX_RECORDS_PER_MIRROR;
+            self.records.drain(..extra);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct HistoryData {
+    mirrors: HashMap<String, MirrorHistory>,
+}
+
+/// Per-mirror results of previous runs, keyed by mirror url
+pub struct History {
+    path: PathBuf,
+    data: HistoryData,
+}
+
+impl History {
+    /// Loads the history file; a missing file is an empty history.
+    pub fn load(path: PathBuf) -> Result<Self, AppError> {
+        let data = match fs::read_to_string(&path) {
+            Ok(content) => {
+                serde_json::from_str(&content).map_err(|e| AppError::InvalidHistory {
+                    path: path.display().to_string(),
+                    reason: e.to_string(),
+                })?
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HistoryData::default(),
+            Err(e) => {
+                return Err(AppError::InvalidHistory {
+                    path: path.display().to_string(),
+                    reason: e.to_string(),
+                });
+            }
+        };
+        Ok(Self { path, data })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn save(&self) -> Result<(), AppError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let content = serde_json::to_string(&self.data).map_err(io::Error::other)?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    pub fn get(&self, url: &str) -> Option<&MirrorHistory> {
+        self.data.mirrors.get(url)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &MirrorHistory)> {
+        self.data.mirrors.iter()
+    }
+
+    /// Sets `blended_speed` of each result which has history and re-sorts
+    /// results by it.
+    ///
+    /// The share of history grows with the total weight of past runs, so a
+    /// single old sample barely moves today's measurement, while a long
+    /// consistent record approaches `weight`.
+    pub fn blend(
+        &self,
+        results: &mut [SpeedTestResult],
+        now: i64,
+        half_life_hours: f64,
+        weight: f64,
+    ) {
+        let weight = weight.clamp(0., 1.);
+        for result in results.iter_mut() {
+            // today's measurement, the lower bound of `--samples` if any
+            result.blended_speed = None;
+            let speed = result.rank_speed();
+            result.blended_speed = self
+                .get(result.item.url.as_str())
+                .and_then(|history| history.decayed_speed(now, half_life_hours))
+                .map(|(past_speed, weights)| {
+                    let share = weight * weights / (weights + 1.);
+                    speed * (1. - share) + past_speed * share
+                });
+        }
+        results.sort_by(|a, b| b.rank_speed().partial_cmp(&a.rank_speed()).unwrap());
+    }
+
+    /// Records the outcome of a run and returns the number of mirrors
+    /// recorded. Mirrors which failed in one test but produced a result in
+    /// another one are recorded as successful.
+    pub fn record(
+        &mut self,
+        results: &[SpeedTestResult],
+        failures: &[SpeedTestFailure],
+        now: i64,
+    ) -> usize {
+        let mut recorded: HashSet<&str> = HashSet::new();
+        for result in results {
+            if !recorded.insert(result.item.url.as_str()) {
+                continue;
+            }
+            let entry = self
+                .data
+                .mirrors
+                .entry(result.item.url.to_string())
+                .or_default();
+            entry.country = result.item.country.map(|c| c.code.to_string());
+            entry.push(HistoryRecord {
+                timestamp: now,
+                speed: result.speed,
+                connection_time_ms: result.connection_time.as_millis() as u64,
+                bytes_downloaded: result.bytes_downloaded,
+                failed: false,
+                error: None,
+            });
+        }
+        for failure in failures {
+            if !recorded.insert(failure.item.url.as_str()) {
+                continue;
+            }
+            let entry = self
+                .data
+                .mirrors
+                .entry(failure.item.url.to_string())
+                .or_default();
+            entry.country = failure.item.country.map(|c| c.code.to_string());
+            entry.push(HistoryRecord {
+                timestamp: now,
+                speed: 0.,
+                connection_time_ms: 0,
+                bytes_downloaded: 0,
+                failed: true,
+                error: Some(failure.error.to_string()),
+            });
+        }
+        recorded.len()
+    }
+}
+
+/// Human-readable lines for the `history` subcommand, fastest first
+pub fn summary_lines(
+    history: &History,
+    args: &HistoryArgs,
+    now: i64,
+    half_life_hours: f64,
+) -> Vec<String> {
+    let mut summaries: Vec<_> = history
+        .iter()
+        .filter(|(url, _)| {
+            args.mirror
+                .as_ref()
+                .is_none_or(|pattern| url.contains(pattern.as_str()))
+        })
+        .map(|(url, mirror_history)| {
+            let speed = mirror_history
+                .decayed_speed(now, half_life_hours)
+                .map(|(speed, _)| speed)
+                .unwrap_or(0.);
+            (url, mirror_history, speed)
+        })
+        .collect();
+    summaries.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap().then_with(|| a.0.cmp(b.0)));
+
+    summaries
+        .into_iter()
+        .take(args.limit.unwrap_or(usize::MAX))
+        .enumerate()
+        .map(|(index, (url, mirror_history, speed))| {
+            let country = mirror_history
+                .country
+                .as_ref()
+                .map(|code| format!("[{}] ", code))
+                .unwrap_or_default();
+            let last_seen = mirror_history
+                .last()
+                .and_then(|record| Local.timestamp_opt(record.timestamp, 0).single())
+                .map(|ts| ts.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            format!(
+                "{:>3}. {}{} (runs: {}; failures: {}; last: {}) -> {}",
+                index + 1,
+                country,
+                format_speed(speed),
+                mirror_history.records.len(),
+                mirror_history.failures(),
+                last_seen,
+                url
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::countries::Country;
+    use crate::mirror::Mirror;
+    use crate::speed_test::{SpeedTestError, TestPhase};
+    use std::time::Duration;
+    use url::Url;
+
+    const HOUR: i64 = 3600;
+
+    fn mirror(url: &str) -> Mirror {
+        let url = Url::parse(url).unwrap();
+        Mirror {
+            country: Country::from_str("DE"),
+            url_to_test: url.join("test.file").unwrap(),
+            url,
+            meta: Default::default(),
+        }
+    }
+
+    fn result(url: &str, speed: f64) -> SpeedTestResult {
+        SpeedTestResult::new(
+            mirror(url),
+            speed as usize,
+            Duration::from_secs(1),
+            Duration::from_millis(50),
+            TestPhase::Jump(1),
+        )
+    }
+
+    fn empty_history() -> History {
+        History {
+            path: PathBuf::from("history.json"),
+            data: HistoryData::default(),
+        }
+    }
+
+    #[test]
+    fn decayed_speed_halves_weight_per_half_life() {
+        let mut history = MirrorHistory::default();
+        for (timestamp, speed) in [(0, 100.), (10 * HOUR, 300.)] {
+            history.push(HistoryRecord {
+                timestamp,
+                speed,
+                connection_time_ms: 0,
+                bytes_downloaded: 0,
+                failed: false,
+                error: None,
+            });
+        }
+
+        let (speed, weights) = history.decayed_speed(10 * HOUR, 10.).unwrap();
+        assert!((weights - 1.5).abs() < 1e-9);
+        assert!((speed - (100. * 0.5 + 300.) / 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn failures_count_as_zero_speed() {
+        let mut history = empty_history();
+        let failure = SpeedTestFailure {
+            item: mirror("https://a.example/"),
+            error: SpeedTestError::TooFewBytesDownloadedError,
+            phase: TestPhase::Jump(1),
+            family: None,
+        };
+        history.record(&[], &[failure], 0);
+
+        let record = history.get("https://a.example/").unwrap();
+        assert_eq!(record.failures(), 1);
+        assert_eq!(record.decayed_speed(0, 1.), Some((0., 1.)));
+    }
+
+    #[test]
+    fn success_wins_over_failure_within_one_run() {
+        let mut history = empty_history();
+        let failure = SpeedTestFailure {
+            item: mirror("https://a.example/"),
+            error: SpeedTestError::TooFewBytesDownloadedError,
+            phase: TestPhase::Jump(1),
+            family: None,
+        };
+        history.record(&[result("https://a.example/", 1000.)], &[failure], 0);
+
+        let record = history.get("https://a.example/").unwrap();
+        assert_eq!(record.records.len(), 1);
+        assert!(!record.records[0].failed);
+    }
+
+    #[test]
+    fn blend_pulls_lucky_sample_towards_history() {
+        let mut history = empty_history();
+        for run in 0..4 {
+            history.record(
+                &[
+                    result("https://steady.example/", 1000.),
+                    result("https://lucky.example/", 500.),
+                ],
+                &[],
+                run * HOUR,
+            );
+        }
+
+        let mut results = vec![
+            result("https://lucky.example/", 1200.),
+            result("https://steady.example/", 1000.),
+        ];
+        history.blend(&mut results, 4 * HOUR, 168., 0.5);
+
+        assert_eq!(results[0].item.url.as_str(), "https://steady.example/");
+        assert!(results[1].blended_speed.unwrap() < 1200.);
+    }
+
+    #[test]
+    fn keeps_only_recent_records() {
+        let mut history = empty_history();
+        for run in 0..(MAX_RECORDS_PER_MIRROR as i64 + 5) {
+            history.record(&[result("https://a.example/", 1000.)], &[], run);
+        }
+
+        let record = history.get("https://a.example/").unwrap();
+        assert_eq!(record.records.len(), MAX_RECORDS_PER_MIRROR);
+        assert_eq!(record.records[0].timestamp, 5);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::config::AppError;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::fs::{MetadataExt, chown};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// `<path>.rate-mirrors.<n>`, 1 being the most recent backup
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".rate-mirrors.{}", n));
+    PathBuf::from(name)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".rate-mirrors.{}.tmp", process::id()));
+    PathBuf::from(name)
+}
+
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+/// Follows `path` through symbolic links, so that the file they point to is
+/// replaced instead of the links; dangling links are followed too.
+fn resolve_links(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    // same limit as the kernel's for nested links
+    for _ in 0..40 {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let target = fs::read_link(&path)?;
+                path = match path.parent() {
+                    Some(dir) => dir.join(target),
+                    None => target,
+                };
+            }
+            _ => return Ok(path),
+        }
+    }
+    Err(io::Error::other(format!(
+        "too many levels of symbolic links: {}",
+        path.display()
+    )))
+}
+
+/// Gives `path` the owner of `metadata`; a user owning a file may not be
+/// allowed to, which leaves the file theirs.
+fn chown_as(path: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    match chown(path, Some(metadata.uid()), Some(metadata.gid())) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Ok(()),
+        result => result,
+    }
+}
+
+fn backup_temp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".rate-mirrors.{}.bak", process::id()));
+    PathBuf::from(name)
+}
+
+/// Keeps the current file aside, to become the `.1` backup once replaced.
+fn backup_current(path: &Path, backup: &Path) -> io::Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    fs::remove_file(backup).ok();
+    // a hard link keeps mode and owner; copy when linking is not possible
+    if fs::hard_link(path, backup).is_err() {
+        fs::copy(path, backup)?;
+        chown_as(backup, &fs::metadata(path)?)?;
+    }
+    Ok(true)
+}
+
+/// Shifts `.1..N-1` backups one position up, dropping the oldest one, and
+/// makes `backup` the `.1` one.
+fn rotate_backups(path: &Path, backup: &Path, backups: usize) -> io::Result<()> {
+    for n in (1..backups).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, n + 1))?;
+        }
+    }
+    fs::rename(backup, backup_path(path, 1))
+}
+
+fn write_temp(path: &Path, tmp_path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut file = File::create(tmp_path)?;
+    file.write_all(content)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+        chown_as(tmp_path, &metadata)?;
+    }
+    file.sync_all()
+}
+
+/// Replaces `path` with `content` atomically: writes a temp file next to it
+/// with mode and owner of the original and renames it over `path`. Only
+/// then are up to `backups` previous versions rotated, so that a failed
+/// install leaves them untouched. Symbolic links are kept, replacing the
+/// file they point to.
+pub fn install(path: &Path, content: &[u8], backups: usize) -> Result<(), AppError> {
+    let path = resolve_links(path)?;
+    let tmp_path = temp_path(&path);
+    let backup = backup_temp_path(&path);
+    let result = write_temp(&path, &tmp_path, content)
+        .and_then(|_| match backups {
+            0 => Ok(false),
+            _ => backup_current(&path, &backup),
+        })
+        .and_then(|backed_up| fs::rename(&tmp_path, &path).map(|_| backed_up));
+    let backed_up = match result {
+        Ok(backed_up) => backed_up,
+        Err(e) => {
+            fs::remove_file(&tmp_path).ok();
+            fs::remove_file(&backup).ok();
+            return Err(e.into());
+        }
+    };
+    if backed_up {
+        rotate_backups(&path, &backup, backups)?;
+    }
+    sync_parent_dir(&path)?;
+    Ok(())
+}
+
+/// Restores the most recent backup of `path` and shifts older backups down,
+/// so that repeated rollbacks go further back in time.
+pub fn rollback(path: &Path) -> Result<(), AppError> {
+    let path = &resolve_links(path)?;
+    let first = backup_path(path, 1);
+    if !first.exists() {
+        return Err(AppError::NoBackup(path.display().to_string()));
+    }
+    fs::rename(&first, path)?;
+    let mut n = 2;
+    while backup_path(path, n).exists() {
+        fs::rename(backup_path(path, n), backup_path(path, n - 1))?;
+        n += 1;
+    }
+    sync_parent_dir(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rate-mirrors-{}-{}", name, process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn rotates_backups_and_preserves_mode() {
+        let dir = temp_dir("install");
+        let path = dir.join("mirrorlist");
+        fs::write(&path, "v0").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        for version in ["v1", "v2", "v3"] {
+            install(&path, version.as_bytes(), 2).unwrap();
+        }
+
+        assert_eq!(read(&path), "v3");
+        assert_eq!(read(&backup_path(&path, 1)), "v2");
+        assert_eq!(read(&backup_path(&path, 2)), "v1");
+        assert!(!backup_path(&path, 3).exists());
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn rollback_restores_previous_versions_in_order() {
+        let dir = temp_dir("rollback");
+        let path = dir.join("mirrorlist");
+
+        for version in ["v1", "v2", "v3"] {
+            install(&path, version.as_bytes(), 3).unwrap();
+        }
+        rollback(&path).unwrap();
+        assert_eq!(read(&path), "v2");
+        rollback(&path).unwrap();
+        assert_eq!(read(&path), "v1");
+        assert!(matches!(rollback(&path), Err(AppError::NoBackup(_))));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn failed_install_keeps_backups() {
+        let dir = temp_dir("failed-install");
+        let path = dir.join("mirrorlist");
+        fs::write(backup_path(&path, 1), "v1").unwrap();
+        fs::write(backup_path(&path, 2), "v0").unwrap();
+        // a directory can be neither backed up nor replaced by a file
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("entry"), "").unwrap();
+
+        assert!(install(&path, b"v2", 2).is_err());
+        assert_eq!(read(&backup_path(&path, 1)), "v1");
+        assert_eq!(read(&backup_path(&path, 2)), "v0");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn replaces_targets_of_symlinks() {
+        let dir = temp_dir("symlink");
+        let path = dir.join("mirrorlist");
+        let link = dir.join("link");
+        fs::write(&path, "v0").unwrap();
+        std::os::unix::fs::symlink("mirrorlist", &link).unwrap();
+
+        install(&link, b"v1", 1).unwrap();
+        assert!(
+            fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert_eq!(read(&path), "v1");
+        assert_eq!(read(&backup_path(&path, 1)), "v0");
+
+        rollback(&link).unwrap();
+        assert_eq!(read(&path), "v0");
+        assert!(
+            fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        fs::remove_dir_all(&dir).ok();
+    }
+}
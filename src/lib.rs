@@ -1,7 +1,48 @@
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
-    }
-}
+//! Mirror ranking engine of `rate-mirrors`: fetches mirror lists of
+//! supported distributions and ranks mirrors by hopping between countries,
+//! guided by submarine cable and internet exchange data.
+//!
+//! ```no_run
+//! use futures::StreamExt;
+//! use rate_mirrors::ranker::{Event, RankerOptions, rank};
+//! use rate_mirrors::mirror::{Mirror, MirrorInfo};
+//!
+//! # async fn example() {
+//! let info = MirrorInfo::parse("US\thttps://mirror.example.org/archlinux/", "\t").unwrap();
+//! let mirrors = vec![Mirror {
+//!     country: info.country,
+//!     url_to_test: info.url.join("core/os/x86_64/core.db").unwrap(),
+//!     url: info.url,
+//!     meta: Default::default(),
+//! }];
+//! let mut events = Box::pin(rank(mirrors, RankerOptions::default()));
+//! while let Some(event) = events.next().await {
+//!     match event {
+//!         Event::Progress(event) => eprintln!("{}", event),
+//!         Event::Finished(results) => println!("{:?}", results),
+//!     }
+//! }
+//! # }
+//! ```
+#[macro_use]
+extern crate lazy_static;
+
+pub mod budget;
+pub mod config;
+pub mod config_file;
+pub mod countries;
+pub mod dns;
+pub mod freshness;
+pub mod history;
+pub mod http;
+pub mod install;
+pub mod mirror;
+pub mod net;
+pub mod progress;
+pub mod ranker;
+pub mod repo_check;
+pub mod report;
+pub mod score;
+pub mod speed_test;
+pub mod target_configs;
+pub mod targets;
@@ -1,33 +1,38 @@
-#[macro_use]
-extern crate lazy_static;
-
-mod config;
-mod countries;
-mod mirror;
-mod speed_test;
-mod target_configs;
-mod targets;
-
-use crate::config::{AppError, Config, FetchMirrors};
-use crate::speed_test::{SpeedTestResult, SpeedTestResults, test_speed_by_countries};
 use chrono::prelude::*;
-use config::LogFormatter;
 use itertools::Itertools;
-use mirror::Mirror;
 use nix::unistd::Uid;
+use rate_mirrors::config::{AppError, Command, Config, FetchMirrors, HistoryArgs, LogFormatter};
+use rate_mirrors::freshness::{self, StaleAction};
+use rate_mirrors::history::{self, History};
+use rate_mirrors::http;
+use rate_mirrors::install;
+use rate_mirrors::mirror::Mirror;
+use rate_mirrors::net;
+use rate_mirrors::progress::{MirrorFilter, ProgressEvent};
+use rate_mirrors::ranker::Event;
+use rate_mirrors::repo_check;
+use rate_mirrors::report::ReportBuilder;
+use rate_mirrors::speed_test::{
+    RankBy, SpeedTestError, SpeedTestResult, format_lag, test_speed_by_countries,
+};
 use std::env;
 use std::fmt::Display;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::mpsc;
 use std::thread;
 
 struct OutputSink<'a, T: LogFormatter> {
     filename: Option<String>,
+    /// number of backups to keep when installing over `filename` instead of
+    /// plainly saving to it
+    install_backups: Option<usize>,
     output_lines: Option<Vec<String>>,
     formatter: &'a T,
+    stdout_enabled: bool,
     comments_enabled: bool,
     comments_in_file_enabled: bool,
     mirror_count: usize,
@@ -37,6 +42,8 @@ impl<'a, T: LogFormatter> OutputSink<'a, T> {
     pub fn new(
         formatter: &'a T,
         filename: Option<&str>,
+        install_backups: Option<usize>,
+        stdout_enabled: bool,
         comments_enabled: bool,
         comments_in_file_enabled: bool,
     ) -> Result<Self, io::Error> {
@@ -44,7 +51,9 @@ impl<'a, T: LogFormatter> OutputSink<'a, T> {
             Some(filename) => Self {
                 formatter,
                 filename: Some(filename.to_string()),
+                install_backups,
                 output_lines: Some(Vec::new()),
+                stdout_enabled,
                 comments_enabled,
                 comments_in_file_enabled,
                 mirror_count: 0,
@@ -52,7 +61,9 @@ impl<'a, T: LogFormatter> OutputSink<'a, T> {
             None => Self {
                 formatter,
                 filename: None,
+                install_backups: None,
                 output_lines: None,
+                stdout_enabled,
                 comments_enabled,
                 comments_in_file_enabled,
                 mirror_count: 0,
@@ -61,21 +72,12 @@ impl<'a, T: LogFormatter> OutputSink<'a, T> {
         Ok(output)
     }
 
-    fn write_stdout_line(&mut self, line: &str) -> Result<(), AppError> {
-        let mut stdout = io::stdout().lock();
-        writeln!(stdout, "{}", line).map_err(|err| {
-            if err.kind() == io::ErrorKind::BrokenPipe {
-                AppError::StdoutBrokenPipe
-            } else {
-                AppError::IoError(err)
-            }
-        })
-    }
-
     pub fn display_comment(&mut self, line: impl Display) -> Result<(), AppError> {
         if self.comments_enabled {
             let s = self.formatter.format_comment(line);
-            self.write_stdout_line(&s)?;
+            if self.stdout_enabled {
+                write_stdout_line(&s)?;
+            }
             if self.comments_in_file_enabled {
                 if let Some(output_lines) = &mut self.output_lines {
                     output_lines.push(s);
@@ -85,28 +87,87 @@ impl<'a, T: LogFormatter> OutputSink<'a, T> {
         Ok(())
     }
 
-    pub fn display_mirror(&mut self, mirror: &Mirror) -> Result<(), AppError> {
-        let s = self.formatter.format_mirror(&mirror);
-        self.write_stdout_line(&s)?;
-        if let Some(output_lines) = &mut self.output_lines {
-            output_lines.push(s);
+    pub fn display_progress(&mut self, event: &ProgressEvent) -> Result<(), AppError> {
+        if event.starts_stage() {
+            self.display_comment("")?;
+        }
+        self.display_comment(event)
+    }
+
+    pub fn display_mirrors(&mut self, mirrors: &[Mirror]) -> Result<(), AppError> {
+        for s in self.formatter.format_mirrors(mirrors) {
+            if self.stdout_enabled {
+                write_stdout_line(&s)?;
+            }
+            if let Some(output_lines) = &mut self.output_lines {
+                output_lines.push(s);
+            }
         }
-        self.mirror_count += 1;
+        self.mirror_count += mirrors.len();
         Ok(())
     }
 
-    pub fn save_to_file(&mut self) -> Result<(), io::Error> {
+    pub fn save_to_file(&mut self) -> Result<(), AppError> {
         if let Some(output_lines) = &mut self.output_lines {
             if let Some(filename) = self.filename.as_ref() {
-                let mut f = File::create(filename)?;
-                f.write_all(output_lines.join("\n").as_bytes())?;
-                f.write_all("\n".as_bytes())?;
+                let mut content = output_lines.join("\n");
+                content.push('\n');
+                match self.install_backups {
+                    Some(backups) => {
+                        install::install(Path::new(filename), content.as_bytes(), backups)?
+                    }
+                    None => File::create(filename)?.write_all(content.as_bytes())?,
+                }
             }
         }
-        return Ok(());
+        Ok(())
     }
 }
 
+fn write_stdout_line(line: &str) -> Result<(), AppError> {
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{}", line).map_err(|err| {
+        if err.kind() == io::ErrorKind::BrokenPipe {
+            AppError::StdoutBrokenPipe
+        } else {
+            AppError::IoError(err)
+        }
+    })
+}
+
+fn show_history(config: &Config, args: &HistoryArgs) -> Result<(), AppError> {
+    let history = History::load(config.history_file()?)?;
+    let lines = history::summary_lines(
+        &history,
+        args,
+        Utc::now().timestamp(),
+        config.history_half_life,
+    );
+    if lines.is_empty() {
+        eprintln!("no history recorded in {}", history.path().display());
+    }
+    for line in lines {
+        write_stdout_line(&line)?;
+    }
+    Ok(())
+}
+
+/// `Vec::retain` which remembers dropped mirrors along with the filter
+fn retain_mirrors(
+    mirrors: &mut Vec<Mirror>,
+    filtered_out: &mut Vec<(Mirror, MirrorFilter)>,
+    filter: MirrorFilter,
+    mut keep: impl FnMut(&Mirror) -> bool,
+) {
+    mirrors.retain(|m| {
+        let kept = keep(m);
+        if !kept {
+            filtered_out.push((m.clone(), filter));
+        }
+        kept
+    });
+}
+
 fn main() -> Result<(), AppError> {
     match run() {
         Err(AppError::StdoutBrokenPipe) => Ok(()),
@@ -115,60 +176,104 @@ fn main() -> Result<(), AppError> {
 }
 
 fn run() -> Result<(), AppError> {
-    let config = Arc::new(Config::new());
-    if !config.allow_root && Uid::effective().is_root() {
+    let config = Arc::new(Config::new()?);
+    // restoring a backup makes no requests, and system files need root
+    let rollback = matches!(config.command, Command::Rollback(_));
+    if !config.allow_root && !rollback && Uid::effective().is_root() {
         return Err(AppError::Root);
     }
-    let max_mirrors_to_output = config.max_mirrors_to_output.clone();
+    http::set_http_settings(config.http_settings()?);
+    let shared_config = Arc::clone(&config);
+    let formatter = match &shared_config.command {
+        Command::Target(target) => target,
+        Command::History(args) => return show_history(&config, args),
+        Command::Rollback(args) => return install::rollback(Path::new(&args.file)),
+        Command::Config(_) => {
+            for line in config.effective_config.iter() {
+                write_stdout_line(line)?;
+            }
+            return Ok(());
+        }
+    };
+    let target = formatter.clone();
+    let max_mirrors_to_output = config.max_mirrors_to_output;
     let disable_untested_fallback = config.disable_untested_fallback;
+    // history keeps speeds, latency probes don't measure any
+    let mut history = if config.history && config.rank_by == RankBy::Speed {
+        Some(History::load(config.history_file()?)?)
+    } else {
+        None
+    };
+
+    let mut report = config.report.as_ref().map(|_| ReportBuilder::new());
+    let started_at = Local::now();
 
-    let ref formatter = Arc::clone(&config).target;
     let mut output = OutputSink::new(
         formatter,
-        config.save_to_file.as_deref(),
+        config.install.as_deref().or(config.save_to_file.as_deref()),
+        config.install.as_ref().map(|_| config.install_backups),
+        config.report.as_deref() != Some("-"),
         !config.disable_comments,
         !config.disable_comments_in_file,
     )?;
 
-    output.display_comment(format!("STARTED AT: {}", Local::now()))?;
+    output.display_comment(format!("STARTED AT: {}", started_at))?;
     output.display_comment(format!("VERSION: {}", env!("CARGO_PKG_VERSION")))?;
     output.display_comment(format!("ARGS: {}", env::args().join(" ")))?;
+    if config.history && history.is_none() {
+        output.display_comment("HISTORY: not used when ranking by latency")?;
+    }
 
-    let (tx_progress, rx_progress) = mpsc::channel::<String>();
-    let (tx_results, rx_results) = mpsc::channel::<SpeedTestResults>();
+    let (tx_events, rx_events) = mpsc::channel::<Event>();
     let (tx_mirrors, rx_mirrors) = mpsc::channel::<Mirror>();
 
-    let thread_handle = thread::spawn(move || -> Result<(), AppError> {
-        let mut mirrors = config.target.fetch_mirrors(tx_progress.clone())?;
+    let thread_handle = thread::spawn(move || -> Result<Vec<(Mirror, MirrorFilter)>, AppError> {
+        let (tx_fetch_progress, rx_fetch_progress) = mpsc::channel::<ProgressEvent>();
+        let fetched = target.fetch_mirrors(tx_fetch_progress);
+        for event in rx_fetch_progress.into_iter() {
+            tx_events.send(Event::Progress(event)).unwrap();
+        }
+        let mut mirrors = fetched?;
+        let mut filtered_out = Vec::new();
 
         // Centralized protocol filtering
         let before_protocol = mirrors.len();
-        mirrors.retain(|m| config.is_protocol_allowed_for_url(&m.url));
+        retain_mirrors(
+            &mut mirrors,
+            &mut filtered_out,
+            MirrorFilter::Protocol,
+            |m| config.is_protocol_allowed_for_url(&m.url),
+        );
         if mirrors.len() < before_protocol {
-            tx_progress
-                .send(format!(
-                    "PROTOCOL FILTER: {} -> {} mirrors",
-                    before_protocol,
-                    mirrors.len()
-                ))
+            tx_events
+                .send(Event::Progress(ProgressEvent::MirrorsFiltered {
+                    filter: MirrorFilter::Protocol,
+                    before: before_protocol,
+                    after: mirrors.len(),
+                }))
                 .unwrap();
         }
 
         // Country filtering before dedup so excluded-country duplicates
         // don't shadow valid mirrors from non-excluded countries
         let before_country = mirrors.len();
-        mirrors.retain(|m| {
-            m.country
-                .map(|c| !config.is_country_excluded(c.code))
-                .unwrap_or(!config.excluded_countries_set.contains("zz"))
-        });
+        retain_mirrors(
+            &mut mirrors,
+            &mut filtered_out,
+            MirrorFilter::Country,
+            |m| {
+                m.country
+                    .map(|c| !config.is_country_excluded(c.code))
+                    .unwrap_or(!config.excluded_countries_set.contains("zz"))
+            },
+        );
         if mirrors.len() < before_country {
-            tx_progress
-                .send(format!(
-                    "COUNTRY FILTER: {} -> {} mirrors",
-                    before_country,
-                    mirrors.len()
-                ))
+            tx_events
+                .send(Event::Progress(ProgressEvent::MirrorsFiltered {
+                    filter: MirrorFilter::Country,
+                    before: before_country,
+                    after: mirrors.len(),
+                }))
                 .unwrap();
         }
 
@@ -182,49 +287,223 @@ fn run() -> Result<(), AppError> {
         // Deduplicate mirrors by host+port+path (keeps first = preferred protocol)
         let before_dedup = mirrors.len();
         let mut seen = std::collections::HashSet::new();
-        mirrors.retain(|m| {
-            let key = format!(
-                "{}{}{}",
-                m.url.host_str().unwrap_or(""),
-                m.url.port().map(|p| format!(":{}", p)).unwrap_or_default(),
-                m.url.path()
-            );
-            seen.insert(key)
-        });
+        retain_mirrors(
+            &mut mirrors,
+            &mut filtered_out,
+            MirrorFilter::Duplicate,
+            |m| {
+                let key = format!(
+                    "{}{}{}",
+                    m.url.host_str().unwrap_or(""),
+                    m.url.port().map(|p| format!(":{}", p)).unwrap_or_default(),
+                    m.url.path()
+                );
+                seen.insert(key)
+            },
+        );
         if mirrors.len() < before_dedup {
-            tx_progress
-                .send(format!(
-                    "DEDUP: {} -> {} mirrors",
-                    before_dedup,
-                    mirrors.len()
-                ))
+            tx_events
+                .send(Event::Progress(ProgressEvent::MirrorsFiltered {
+                    filter: MirrorFilter::Duplicate,
+                    before: before_dedup,
+                    after: mirrors.len(),
+                }))
                 .unwrap();
         }
 
+        if let Some(family) = config.ip_family.and_then(|ip_family| ip_family.single()) {
+            let before_family = mirrors.len();
+            let mut reachable = net::reachable_over(&mirrors, family).into_iter();
+            retain_mirrors(
+                &mut mirrors,
+                &mut filtered_out,
+                MirrorFilter::Family,
+                |_| reachable.next().unwrap_or(true),
+            );
+            if mirrors.len() < before_family {
+                tx_events
+                    .send(Event::Progress(ProgressEvent::MirrorsFiltered {
+                        filter: MirrorFilter::Family,
+                        before: before_family,
+                        after: mirrors.len(),
+                    }))
+                    .unwrap();
+            }
+        }
+
         // sending filtered mirrors back so we have a fallback in case if all tests fail
         for mirror in mirrors.iter().cloned() {
             tx_mirrors.send(mirror).unwrap();
         }
 
-        tx_progress
-            .send(format!("MIRRORS LEFT AFTER FILTERING: {}", mirrors.len()))
+        tx_events
+            .send(Event::Progress(ProgressEvent::MirrorsLeft(mirrors.len())))
             .unwrap();
 
-        test_speed_by_countries(mirrors, config, tx_progress, tx_results);
-        Ok(())
+        test_speed_by_countries(mirrors, Arc::new(config.ranker_options()), tx_events);
+        Ok(filtered_out)
     });
 
-    for progress in rx_progress.into_iter() {
-        output.display_comment(progress)?;
+    let mut results = Vec::new();
+    let mut outcomes = Vec::new();
+    for event in rx_events.into_iter() {
+        match event {
+            Event::Progress(event) => {
+                output.display_progress(&event)?;
+                match event {
+                    ProgressEvent::MirrorTested(result) => outcomes.push(Ok(result)),
+                    ProgressEvent::MirrorFailed(failure) => outcomes.push(Err(failure)),
+                    _ => {}
+                }
+            }
+            Event::Finished(ranking) => results = ranking,
+        }
     }
 
-    thread_handle.join().unwrap()?;
+    let filtered_out = thread_handle.join().unwrap()?;
 
-    let results: Vec<_> = rx_results.iter().flatten().collect();
+    let untested_mirrors: Vec<Mirror> = rx_mirrors.into_iter().collect();
+    // tests skipped for the budget tell nothing about mirrors
+    let failures: Vec<_> = outcomes
+        .iter()
+        .filter_map(|outcome| outcome.as_ref().err().cloned())
+        .filter(|failure| !matches!(failure.error, SpeedTestError::OverBudget(_)))
+        .collect();
 
+    // tested mirrors which are not to be output, along with the reason
+    let mut excluded: Vec<(SpeedTestResult, &'static str)> = Vec::new();
+
+    let freshness_check = shared_config.freshness_check();
+    if let Some(check) = freshness_check.as_ref() {
+        let (reference, reference_error) = check.verify(&mut results);
+        if let Some(error) = reference_error {
+            output.display_progress(&ProgressEvent::FreshnessReferenceFailed { error })?;
+        }
+        if let Some(reference) = reference {
+            output.display_comment(format!(
+                "FRESHNESS: reference synced at {}",
+                DateTime::from_timestamp(reference, 0).unwrap_or_default()
+            ))?;
+        }
+        for result in results.iter().filter(|r| freshness::is_stale(r)) {
+            output.display_comment(format!(
+                "STALE: {} - {} behind",
+                result.item.url,
+                format_lag(result.freshness.as_ref().and_then(|f| f.lag).unwrap_or(0))
+            ))?;
+        }
+        if check.action == StaleAction::Drop {
+            let (stale, fresh): (Vec<_>, _) = results.into_iter().partition(freshness::is_stale);
+            results = fresh;
+            excluded.extend(stale.into_iter().map(|result| (result, "stale")));
+        }
+    }
+
+    let now = Utc::now().timestamp();
+    if let Some(history) = history.as_mut() {
+        history.blend(
+            &mut results,
+            now,
+            shared_config.history_half_life,
+            shared_config.history_weight,
+        );
+    }
+
+    if let Some(score) = shared_config.score.as_ref() {
+        score.apply(&mut results);
+    }
+    // after any re-sorting, so that stale mirrors stay last
+    if let Some(StaleAction::Demote) = freshness_check.as_ref().map(|check| check.action) {
+        freshness::demote_stale(&mut results);
+    }
+
+    // of the final order, so that only mirrors to be output are checked
+    if let Some(check) = shared_config.repo_check() {
+        let wanted = max_mirrors_to_output.unwrap_or(results.len());
+        if let Some(error) = check.verify(&mut results, wanted) {
+            output.display_progress(&ProgressEvent::RepoReferenceFailed { error })?;
+        }
+        let (failed, passed): (Vec<_>, _) = results.into_iter().partition(repo_check::failed);
+        results = passed;
+        for result in failed {
+            if let Some(repo) = result.repo.as_ref() {
+                output.display_comment(format!(
+                    "REPO CHECK FAILED: {} - {}",
+                    repo.db_url,
+                    repo.error.as_deref().unwrap_or_default()
+                ))?;
+            }
+            excluded.push((result, "repo"));
+        }
+    }
+
+    if let Some(history) = history.as_mut() {
+        let recorded = history.record(&results, &failures, now);
+        history.save()?;
+        output.display_comment(format!(
+            "HISTORY: {} mirrors recorded to {}",
+            recorded,
+            history.path().display()
+        ))?;
+    }
+
+    if let Some(report) = report.as_mut() {
+        for (mirror, filter) in filtered_out.iter() {
+            report.add_filtered(mirror, filter.as_str());
+        }
+        for mirror in untested_mirrors.iter() {
+            report.add_mirror(mirror);
+        }
+        for outcome in outcomes.iter() {
+            report.add_outcome(outcome);
+        }
+        for (result, reason) in excluded.iter() {
+            report.set_excluded(result, reason);
+        }
+    }
+    // excluded mirrors don't make it to the output as untested ones either
+    let untested_mirrors: Vec<Mirror> = untested_mirrors
+        .into_iter()
+        .filter(|mirror| {
+            !excluded
+                .iter()
+                .any(|(result, _)| result.item.url == mirror.url)
+        })
+        .collect();
+
+    let result = display_results(
+        &mut output,
+        results,
+        untested_mirrors,
+        max_mirrors_to_output,
+        disable_untested_fallback,
+        report.as_mut(),
+    );
+
+    if let (Some(report), Some(path)) = (report, shared_config.report.as_ref()) {
+        report
+            .build(started_at.to_rfc3339(), Local::now().to_rfc3339())
+            .write(path, shared_config.report_format)?;
+    }
+    result?;
+
+    if output.mirror_count == 0 {
+        return Err(AppError::BlankOutput);
+    }
+    output.save_to_file()?;
+    Ok(())
+}
+
+fn display_results<T: LogFormatter>(
+    output: &mut OutputSink<T>,
+    results: Vec<SpeedTestResult>,
+    untested_mirrors: Vec<Mirror>,
+    max_mirrors_to_output: Option<usize>,
+    disable_untested_fallback: bool,
+    report: Option<&mut ReportBuilder>,
+) -> Result<(), AppError> {
     if results.is_empty() {
-        let untested_mirrors: Vec<Mirror> = rx_mirrors.into_iter().collect();
-        if untested_mirrors.len() == 0 {
+        if untested_mirrors.is_empty() {
             output.display_comment("==== NO MIRRORS AFTER FILTERING ====")?;
             return Err(AppError::NoMirrorsAfterFiltering);
         }
@@ -233,9 +512,12 @@ fn run() -> Result<(), AppError> {
             return Err(AppError::SpeedTestsFailed);
         }
         output.display_comment("==== FAILED TO TEST SPEEDS, RETURNING UNTESTED MIRRORS ====")?;
-        for mirror in untested_mirrors.into_iter() {
-            output.display_mirror(&mirror)?;
+        if let Some(report) = report {
+            for mirror in untested_mirrors.iter() {
+                report.set_output(mirror);
+            }
         }
+        output.display_mirrors(&untested_mirrors)?;
     } else {
         output.display_comment("==== RESULTS (top re-tested) ====")?;
 
@@ -245,19 +527,16 @@ fn run() -> Result<(), AppError> {
 
         output.display_comment(format!("FINISHED AT: {}", Local::now()))?;
 
-        let it: Box<dyn Iterator<Item = SpeedTestResult>> = match max_mirrors_to_output {
-            Some(n) => Box::new(results.into_iter().take(n)),
-            None => Box::new(results.into_iter()),
-        };
-
-        for result in it {
-            output.display_mirror(&result.item)?;
+        if let Some(report) = report {
+            report.set_ranking(&results, max_mirrors_to_output.unwrap_or(results.len()));
         }
-    }
 
-    if output.mirror_count == 0 {
-        return Err(AppError::BlankOutput);
+        let mirrors: Vec<Mirror> = results
+            .into_iter()
+            .take(max_mirrors_to_output.unwrap_or(usize::MAX))
+            .map(|result| result.item)
+            .collect();
+        output.display_mirrors(&mirrors)?;
     }
-    output.save_to_file()?;
     Ok(())
 }
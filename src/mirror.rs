@@ -62,9 +62,19 @@ impl fmt::Display for MirrorParseError {
     }
 }
 
+/// What mirror lists tell about a mirror besides its url and country
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MirrorMeta {
+    /// seconds since the last sync
+    pub sync_delay: Option<i64>,
+    /// upstream mirror score, lower is better (e.g. of Arch Linux mirror status)
+    pub upstream_score: Option<f64>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Mirror {
     pub url: Url,
     pub url_to_test: Url,
     pub country: Option<&'static Country>,
+    pub meta: MirrorMeta,
 }
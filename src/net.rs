@@ -0,0 +1,181 @@
+use crate::http;
+use crate::mirror::Mirror;
+use futures::StreamExt;
+use futures::stream;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::Serialize;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use url::{Host, Url};
+
+/// Number of host names resolved at once by `reachable_over`
+const LOOKUP_CONCURRENCY: usize = 32;
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum AddressFamily {
+    #[serde(rename = "ipv4")]
+    V4,
+    #[serde(rename = "ipv6")]
+    V6,
+}
+
+impl AddressFamily {
+    pub fn matches(&self, ip: &IpAddr) -> bool {
+        match self {
+            AddressFamily::V4 => ip.is_ipv4(),
+            AddressFamily::V6 => ip.is_ipv6(),
+        }
+    }
+
+    /// Whether `url` is an IP literal of the other family; host names are
+    /// left to `FamilyResolver`
+    pub fn excludes(&self, url: &Url) -> bool {
+        match url.host() {
+            Some(Host::Ipv4(_)) => *self == AddressFamily::V6,
+            Some(Host::Ipv6(_)) => *self == AddressFamily::V4,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressFamily::V4 => write!(f, "IPv4"),
+            AddressFamily::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// `--ip-family`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpFamily {
+    V4,
+    V6,
+    /// each mirror is tested over both families separately
+    Both,
+}
+
+impl IpFamily {
+    pub fn families(&self) -> &'static [AddressFamily] {
+        match self {
+            IpFamily::V4 => &[AddressFamily::V4],
+            IpFamily::V6 => &[AddressFamily::V6],
+            IpFamily::Both => &[AddressFamily::V4, AddressFamily::V6],
+        }
+    }
+
+    /// The only family to use, unless both are
+    pub fn single(&self) -> Option<AddressFamily> {
+        match self {
+            IpFamily::V4 => Some(AddressFamily::V4),
+            IpFamily::V6 => Some(AddressFamily::V6),
+            IpFamily::Both => None,
+        }
+    }
+}
+
+impl FromStr for IpFamily {
+    type Err = &'static str;
+    fn from_str(family: &str) -> Result<Self, Self::Err> {
+        match family {
+            "4" => Ok(IpFamily::V4),
+            "6" => Ok(IpFamily::V6),
+            "both" => Ok(IpFamily::Both),
+            _ => Err("could not parse IP family, expected one of: 4, 6, both"),
+        }
+    }
+}
+
+/// Resolves host names by the system resolver (`getaddrinfo`)
+#[derive(Debug, Clone, Copy)]
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Keeps addresses of a single family of `inner`, so that clients never
+/// connect over the other one
+pub struct FamilyResolver {
+    pub family: AddressFamily,
+    pub inner: Arc<dyn Resolve>,
+}
+
+impl Resolve for FamilyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let family = self.family;
+        let host = name.as_str().to_string();
+        let resolving = self.inner.resolve(name);
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = resolving
+                .await?
+                .filter(|addr| family.matches(&addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("no {} address of {}", family, host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+async fn has_address(resolver: &dyn Resolve, url: &Url, family: AddressFamily) -> bool {
+    let name = match url.host() {
+        Some(Host::Domain(host)) => host.parse::<Name>(),
+        _ => return !family.excludes(url),
+    };
+    let Ok(name) = name else {
+        return true;
+    };
+    match tokio::time::timeout(LOOKUP_TIMEOUT, resolver.resolve(name)).await {
+        Ok(Ok(mut addrs)) => addrs.any(|addr| family.matches(&addr.ip())),
+        // the host may well be reachable, it's up to speed tests to tell
+        _ => true,
+    }
+}
+
+/// Whether each of `mirrors` has an address of `family`
+pub fn reachable_over(mirrors: &[Mirror], family: AddressFamily) -> Vec<bool> {
+    let Ok(resolver) = http::resolver() else {
+        return vec![true; mirrors.len()];
+    };
+    let resolver = resolver.as_ref();
+    http::runtime().block_on(
+        stream::iter(
+            mirrors
+                .iter()
+                .map(|m| has_address(resolver, &m.url_to_test, family)),
+        )
+        .buffered(LOOKUP_CONCURRENCY)
+        .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ip_literals_of_other_family_are_excluded() {
+        let v4 = Url::parse("http://127.0.0.1/mirror/").unwrap();
+        let v6 = Url::parse("http://[::1]/mirror/").unwrap();
+        let name = Url::parse("https://mirror.example.org/").unwrap();
+        assert!(AddressFamily::V6.excludes(&v4));
+        assert!(!AddressFamily::V4.excludes(&v4));
+        assert!(AddressFamily::V4.excludes(&v6));
+        assert!(!AddressFamily::V6.excludes(&name));
+        assert_eq!("both".parse(), Ok(IpFamily::Both));
+        assert!("ipv4".parse::<IpFamily>().is_err());
+    }
+}
@@ -0,0 +1,360 @@
+use crate::budget::BudgetLimit;
+use crate::speed_test::{
+    RateStrategy, SpeedTestError, SpeedTestFailure, SpeedTestResult, format_duration, format_speed,
+};
+use std::fmt;
+use std::time::Duration;
+use url::Url;
+
+/// Filter dropping fetched mirrors before testing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MirrorFilter {
+    Protocol,
+    Country,
+    Duplicate,
+    Family,
+}
+
+impl MirrorFilter {
+    /// name of the filter, e.g. as the filter reason of reports
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MirrorFilter::Protocol => "protocol",
+            MirrorFilter::Country => "country",
+            MirrorFilter::Duplicate => "duplicate",
+            MirrorFilter::Family => "family",
+        }
+    }
+}
+
+/// Why the update number of an EndeavourOS mirror is unknown
+#[derive(Debug, Clone, PartialEq)]
+pub enum MirrorStateError {
+    ClientBuild(String),
+    Connect,
+    Read,
+    Empty,
+    InvalidUpdateNumber,
+}
+
+/// Why country jumping stopped before `max_jumps`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EarlyStopReason {
+    ConnectionTimesGettingWorse,
+    SpeedsGettingWorse,
+    BudgetExhausted(BudgetLimit),
+}
+
+/// What happens while mirrors are being fetched and ranked; `Display`
+/// renders the familiar comment lines.
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum ProgressEvent {
+    /// an entry of a mirror list that isn't a valid server
+    ServerSkipped {
+        url: String,
+        error: String,
+    },
+    MetadataFetchFailed {
+        url: Url,
+        error: String,
+    },
+    /// no primary metadata of the repo, the fallback path is tested instead
+    PrimaryMetadataNotFound {
+        fallback: &'static str,
+    },
+    MirrorVersionFetched {
+        url: Url,
+        version: usize,
+    },
+    MirrorVersionFailed {
+        url: Url,
+        error: MirrorStateError,
+    },
+    /// only mirrors of this update number are kept
+    LatestVersionSelected(usize),
+    MirrorsFetched(usize),
+    MirrorsFiltered {
+        filter: MirrorFilter,
+        before: usize,
+        after: usize,
+    },
+    MirrorsLeft(usize),
+    UnknownEntryCountry {
+        fallback: &'static str,
+    },
+    /// 1-based jump number
+    JumpStarted(usize),
+    JumpFinished,
+    CountryExplored(&'static str),
+    CountryVisited(&'static str),
+    NeighborSelected {
+        country: &'static str,
+        strategy: RateStrategy,
+    },
+    MirrorTested(SpeedTestResult),
+    MirrorFailed(SpeedTestFailure),
+    TopConnectionTime {
+        country: &'static str,
+        /// DNS resolution excluded
+        connection_time: Duration,
+        neighbor: bool,
+    },
+    TopSpeed {
+        country: &'static str,
+        speed: f64,
+        neighbor: bool,
+    },
+    BlankIteration,
+    EarlyStop {
+        reason: EarlyStopReason,
+    },
+    /// country jumping yielded this few results, the rest is tested unlabeled
+    TooFewJumpResults(usize),
+    UnlabeledStarted,
+    NoResultsToRetest,
+    RetestStarted,
+    /// the sync marker of `--freshness-reference` couldn't be fetched, mirrors
+    /// are compared with the freshest one instead
+    FreshnessReferenceFailed {
+        error: String,
+    },
+    /// the `--verify-repo-reference` database couldn't be downloaded, mirrors
+    /// are compared with the majority instead
+    RepoReferenceFailed {
+        error: String,
+    },
+}
+
+impl ProgressEvent {
+    /// Whether the event begins a new stage, worth separating visually
+    pub fn starts_stage(&self) -> bool {
+        matches!(
+            self,
+            ProgressEvent::UnlabeledStarted
+                | ProgressEvent::NoResultsToRetest
+                | ProgressEvent::RetestStarted
+        )
+    }
+}
+
+impl fmt::Display for ProgressEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgressEvent::ServerSkipped { url, error } => {
+                write!(f, "skipping unparseable server {}: {}", url, error)
+            }
+            ProgressEvent::MetadataFetchFailed { url, error } => {
+                write!(f, "failed to fetch {}: {}", url, error)
+            }
+            ProgressEvent::PrimaryMetadataNotFound { fallback } => {
+                write!(f, "primary metadata not found, testing {}", fallback)
+            }
+            ProgressEvent::MirrorVersionFetched { url, version } => {
+                write!(f, "FETCHED MIRROR VERSION {}: {}", version, url)
+            }
+            ProgressEvent::MirrorVersionFailed { url, error } => match error {
+                MirrorStateError::ClientBuild(error) => write!(f, "{}: {}", error, url),
+                MirrorStateError::Connect => write!(f, "FAILED TO CONNECT: {}", url),
+                MirrorStateError::Read => write!(f, "FAILED TO READ STATE: {}", url),
+                MirrorStateError::Empty => write!(f, "EMPTY MIRROR STATE: {}", url),
+                MirrorStateError::InvalidUpdateNumber => {
+                    write!(f, "FAILED TO READ MIRROR UPDATE NUMBER: {}", url)
+                }
+            },
+            ProgressEvent::LatestVersionSelected(version) => {
+                write!(f, "TAKING MIRRORS WITH LATEST VERSION: {}", version)
+            }
+            ProgressEvent::MirrorsFetched(count) => write!(f, "FETCHED MIRRORS: {}", count),
+            ProgressEvent::MirrorsFiltered {
+                filter,
+                before,
+                after,
+            } => match filter {
+                MirrorFilter::Duplicate => write!(f, "DEDUP: {} -> {} mirrors", before, after),
+                _ => write!(
+                    f,
+                    "{} FILTER: {} -> {} mirrors",
+                    filter.as_str().to_ascii_uppercase(),
+                    before,
+                    after
+                ),
+            },
+            ProgressEvent::MirrorsLeft(count) => {
+                write!(f, "MIRRORS LEFT AFTER FILTERING: {}", count)
+            }
+            ProgressEvent::UnknownEntryCountry { fallback } => {
+                write!(f, "UNKNOWN entry_country, falling back to {}", fallback)
+            }
+            ProgressEvent::JumpStarted(jump) => write!(f, "JUMP #{}", jump),
+            ProgressEvent::JumpFinished => Ok(()),
+            ProgressEvent::CountryExplored(code) => write!(f, "EXPLORING {}", code),
+            ProgressEvent::CountryVisited(code) => write!(f, "VISITED {}", code),
+            ProgressEvent::NeighborSelected { country, strategy } => {
+                write!(f, "    + NEIGHBOR {} (by {:?})", country, strategy)
+            }
+            ProgressEvent::MirrorTested(result) => write!(f, "{:?}", result),
+            ProgressEvent::MirrorFailed(failure) => {
+                let item = &failure.item;
+                let mut country = item
+                    .country
+                    .map(|c| format!("[{}] ", c.code))
+                    .unwrap_or_default();
+                if let Some(family) = failure.family {
+                    country.push_str(&format!("[{}] ", family));
+                }
+                match failure.error {
+                    SpeedTestError::ClientBuildError(_) => write!(
+                        f,
+                        "{}FAILED TO BUILD HTTP CLIENT FOR {}",
+                        country, item.url_to_test
+                    ),
+                    SpeedTestError::ReqwestError(_) => {
+                        write!(f, "{}FAILED TO CONNECT TO {}", country, item.url_to_test)
+                    }
+                    SpeedTestError::HttpStatus(status) => {
+                        write!(f, "{}HTTP {} FROM {}", country, status, item.url_to_test)
+                    }
+                    SpeedTestError::NoAddress(family) => {
+                        write!(
+                            f,
+                            "{}NO {} ADDRESS OF {}",
+                            country, family, item.url_to_test
+                        )
+                    }
+                    SpeedTestError::TooFewBytesDownloadedError => {
+                        write!(f, "TOO FEW BYTES LOADED {}", item.url)
+                    }
+                    SpeedTestError::OverBudget(_) => {
+                        write!(f, "{}SKIPPED FOR BUDGET {}", country, item.url_to_test)
+                    }
+                }
+            }
+            ProgressEvent::TopConnectionTime {
+                country,
+                connection_time,
+                neighbor,
+            } => write!(
+                f,
+                "    TOP {}CONNECTION TIME: {} - {}",
+                if *neighbor { "NEIGHBOR - " } else { "" },
+                country,
+                format_duration(connection_time)
+            ),
+            ProgressEvent::TopSpeed {
+                country,
+                speed,
+                neighbor,
+            } => write!(
+                f,
+                "    TOP {}SPEED: {} - {}",
+                if *neighbor { "NEIGHBOR - " } else { "" },
+                country,
+                format_speed(*speed)
+            ),
+            ProgressEvent::BlankIteration => write!(f, "BLANK ITERATION"),
+            ProgressEvent::EarlyStop { reason } => match reason {
+                EarlyStopReason::ConnectionTimesGettingWorse => {
+                    write!(f, "CONNECTION TIMES ARE GETTING WORSE, STOPPING")
+                }
+                EarlyStopReason::SpeedsGettingWorse => {
+                    write!(f, "SPEEDS ARE GETTING WORSE, STOPPING")
+                }
+                EarlyStopReason::BudgetExhausted(limit) => write!(
+                    f,
+                    "{} BUDGET EXHAUSTED, STOPPING",
+                    limit.to_string().to_uppercase()
+                ),
+            },
+            ProgressEvent::TooFewJumpResults(count) => write!(
+                f,
+                "COUNTRY JUMPING YIELDED TOO FEW MIRRORS ({}), ADDING OTHERS TO UNLABELED",
+                count
+            ),
+            ProgressEvent::UnlabeledStarted => write!(f, "TESTING UNLABELED MIRRORS"),
+            ProgressEvent::NoResultsToRetest => write!(f, "NO RESULTS TO RE-TEST"),
+            ProgressEvent::RetestStarted => write!(f, "RE-TESTING TOP MIRRORS"),
+            ProgressEvent::FreshnessReferenceFailed { error } => write!(
+                f,
+                "FRESHNESS REFERENCE FAILED: {}, COMPARING WITH THE FRESHEST MIRROR",
+                error
+            ),
+            ProgressEvent::RepoReferenceFailed { error } => write!(
+                f,
+                "REPO CHECK REFERENCE FAILED: {}, COMPARING WITH THE MAJORITY",
+                error
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::countries::Country;
+    use crate::mirror::Mirror;
+    use crate::speed_test::TestPhase;
+
+    #[test]
+    fn renders_legacy_comment_lines() {
+        let url = Url::parse("https://mirror.example.org/arch/").unwrap();
+        let failure = SpeedTestFailure {
+            item: Mirror {
+                country: Country::from_str("DE"),
+                url_to_test: url.join("core.db").unwrap(),
+                url,
+                meta: Default::default(),
+            },
+            error: SpeedTestError::ReqwestError("timeout".to_string()),
+            phase: TestPhase::Retest,
+            family: None,
+        };
+        let cases = [
+            (
+                ProgressEvent::NeighborSelected {
+                    country: "FR",
+                    strategy: RateStrategy::HubsFirst,
+                },
+                "    + NEIGHBOR FR (by HubsFirst)",
+            ),
+            (
+                ProgressEvent::MirrorsFiltered {
+                    filter: MirrorFilter::Country,
+                    before: 5,
+                    after: 3,
+                },
+                "COUNTRY FILTER: 5 -> 3 mirrors",
+            ),
+            (
+                ProgressEvent::MirrorsFiltered {
+                    filter: MirrorFilter::Duplicate,
+                    before: 3,
+                    after: 2,
+                },
+                "DEDUP: 3 -> 2 mirrors",
+            ),
+            (
+                ProgressEvent::MirrorVersionFailed {
+                    url: Url::parse("https://mirror.example.org/endeavouros/").unwrap(),
+                    error: MirrorStateError::Empty,
+                },
+                "EMPTY MIRROR STATE: https://mirror.example.org/endeavouros/",
+            ),
+            (
+                ProgressEvent::TopConnectionTime {
+                    country: "DE",
+                    connection_time: Duration::from_millis(42),
+                    neighbor: true,
+                },
+                "    TOP NEIGHBOR - CONNECTION TIME: DE - 42ms",
+            ),
+            (
+                ProgressEvent::MirrorFailed(failure),
+                "[DE] FAILED TO CONNECT TO https://mirror.example.org/arch/core.db",
+            ),
+        ];
+        for (event, line) in cases {
+            assert_eq!(event.to_string(), line);
+        }
+    }
+}
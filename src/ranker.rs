@@ -0,0 +1,209 @@
+use crate::mirror::Mirror;
+use crate::net::IpFamily;
+use crate::progress::ProgressEvent;
+use crate::speed_test::{RankBy, SpeedTestResult, test_speed_by_countries};
+use futures::Stream;
+use futures::channel::mpsc as async_mpsc;
+use std::collections::HashSet;
+use std::sync::{Arc, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Speed test and country hopping settings, independent of the command line
+/// interface; defaults match the ones of `rate-mirrors`.
+#[derive(Debug, Clone)]
+pub struct RankerOptions {
+    /// Per-mirror speed test timeout in milliseconds
+    pub per_mirror_timeout: u64,
+    /// Minimum downloading time, required to measure mirror speed, in milliseconds
+    pub min_per_mirror: u64,
+    /// Maximum downloading time, required to measure mirror speed, in milliseconds
+    pub max_per_mirror: u64,
+    /// Minimum number of bytes to be downloaded, required to measure mirror speed
+    pub min_bytes_per_mirror: usize,
+    /// Relative speed-jitter threshold used for early stopping
+    pub eps: f64,
+    /// Number of recent chunk speeds checked against `eps`
+    pub eps_checks: usize,
+    /// Number of simultaneous speed tests
+    pub concurrency: usize,
+    /// Number of simultaneous speed tests for mirrors with unknown country
+    pub concurrency_for_unlabeled: usize,
+    /// Max number of jumps between countries
+    pub max_jumps: usize,
+    /// 2-letter code of the first country to test
+    pub entry_country: String,
+    /// Lowercase 2-letter codes of countries not to hop to
+    pub excluded_countries: HashSet<String>,
+    /// Neighbor countries to test per country
+    pub country_neighbors_per_country: usize,
+    /// Number of mirrors to test per country
+    pub country_test_mirrors_per_country: usize,
+    /// Number of top mirrors to retest
+    pub top_mirrors_number_to_retest: usize,
+    /// Whether to download from mirrors or to only measure their latency
+    pub rank_by: RankBy,
+    /// Number of requests per mirror when ranking by latency
+    pub latency_probes: usize,
+    /// Number of times each top mirror is re-tested
+    pub samples: usize,
+    /// Address families to test over; `None` leaves it to the system
+    pub ip_family: Option<IpFamily>,
+    /// Re-test top mirrors over kept-alive connections
+    pub warm_retest: bool,
+    /// `--max-total-bytes`, downloaded by all speed tests of the run
+    pub max_total_bytes: Option<u64>,
+    /// `--max-total-time`, spent by all speed tests of the run
+    pub max_total_time: Option<Duration>,
+    /// Measure speeds by ranged requests of a few hundred KB at most
+    pub lite: bool,
+    /// Number of ranged requests per mirror of `lite`; with fewer than 2,
+    /// nothing but the whole file is timed, up to the bytes of the probes
+    pub lite_probes: usize,
+    /// Size of the first ranged request of `lite`, each next one doubles it
+    pub lite_probe_bytes: usize,
+}
+
+impl Default for RankerOptions {
+    fn default() -> Self {
+        Self {
+            per_mirror_timeout: 8000,
+            min_per_mirror: 300,
+            max_per_mirror: 1000,
+            min_bytes_per_mirror: 70000,
+            eps: 0.0625,
+            eps_checks: 40,
+            concurrency: 16,
+            concurrency_for_unlabeled: 40,
+            max_jumps: 7,
+            entry_country: "US".to_string(),
+            excluded_countries: HashSet::new(),
+            country_neighbors_per_country: 3,
+            country_test_mirrors_per_country: 2,
+            top_mirrors_number_to_retest: 5,
+            rank_by: RankBy::Speed,
+            latency_probes: 5,
+            samples: 1,
+            ip_family: None,
+            warm_retest: false,
+            max_total_bytes: None,
+            max_total_time: None,
+            lite: false,
+            lite_probes: 4,
+            lite_probe_bytes: 16384,
+        }
+    }
+}
+
+impl RankerOptions {
+    pub fn is_country_excluded(&self, code: &str) -> bool {
+        self.excluded_countries.contains(&code.to_ascii_lowercase())
+    }
+}
+
+/// What happens while mirrors are being ranked
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum Event {
+    Progress(ProgressEvent),
+    /// final ranking, fastest first; always the last event
+    Finished(Vec<SpeedTestResult>),
+}
+
+/// Ranks mirrors in a background thread, yielding progress (including every
+/// speed test outcome) and finally the ranking.
+///
+/// ```no_run
+/// use futures::StreamExt;
+/// use rate_mirrors::mirror::Mirror;
+/// use rate_mirrors::ranker::{Event, RankerOptions, rank};
+///
+/// # async fn example(mirrors: Vec<Mirror>) {
+/// let mut events = Box::pin(rank(mirrors, RankerOptions::default()));
+/// while let Some(event) = events.next().await {
+///     if let Event::Finished(results) = event {
+///         for result in results {
+///             println!("{}", result.item.url);
+///         }
+///     }
+/// }
+/// # }
+/// ```
+///
+/// Dropping the stream stops the ranking once the tests in flight are done.
+pub fn rank(mirrors: Vec<Mirror>, options: RankerOptions) -> impl Stream<Item = Event> {
+    spawn_ranking(mirrors, options).0
+}
+
+/// `rank`, along with the thread testing mirrors
+fn spawn_ranking(
+    mirrors: Vec<Mirror>,
+    options: RankerOptions,
+) -> (async_mpsc::UnboundedReceiver<Event>, JoinHandle<()>) {
+    let (tx_stream, rx_stream) = async_mpsc::unbounded();
+    let (tx_events, rx_events) = mpsc::channel();
+    let handle =
+        thread::spawn(move || test_speed_by_countries(mirrors, Arc::new(options), tx_events));
+    thread::spawn(move || {
+        for event in rx_events {
+            if tx_stream.unbounded_send(event).is_err() {
+                break;
+            }
+        }
+    });
+    (rx_stream, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::countries::Country;
+    use futures::StreamExt;
+    use futures::executor::block_on;
+    use url::Url;
+
+    #[test]
+    fn rank_always_finishes() {
+        let events: Vec<Event> = block_on(rank(Vec::new(), RankerOptions::default()).collect());
+        assert!(matches!(events.last(), Some(Event::Finished(results)) if results.is_empty()));
+        assert!(
+            events
+                .iter()
+                .any(|e| matches!(e, Event::Progress(ProgressEvent::NoResultsToRetest)))
+        );
+    }
+
+    #[test]
+    fn dropping_the_stream_stops_ranking() {
+        // accepts connections, but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let mirrors = (0..4)
+            .map(|i| Mirror {
+                url: url.join(&format!("{}/", i)).unwrap(),
+                url_to_test: url.join(&format!("{}/file", i)).unwrap(),
+                country: Country::from_str("US"),
+                meta: Default::default(),
+            })
+            .collect();
+        let options = RankerOptions {
+            per_mirror_timeout: 200,
+            ..RankerOptions::default()
+        };
+
+        let (mut events, handle) = spawn_ranking(mirrors, options);
+        assert!(block_on(events.next()).is_some());
+        drop(events);
+        assert!(handle.join().is_ok());
+    }
+
+    #[test]
+    fn excluded_countries_are_case_insensitive() {
+        let options = RankerOptions {
+            excluded_countries: ["de".to_string()].into_iter().collect(),
+            ..RankerOptions::default()
+        };
+        assert!(options.is_country_excluded("DE"));
+        assert!(!options.is_country_excluded("FR"));
+    }
+}
@@ -0,0 +1,414 @@
+use crate::config::default_client_builder;
+use crate::http;
+use crate::mirror::Mirror;
+use crate::speed_test::SpeedTestResult;
+use flate2::read::GzDecoder;
+use futures::StreamExt;
+use futures::stream;
+use serde::Serialize;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::time::Duration;
+use url::Url;
+
+/// Share of the expected packages a mirror may miss, e.g. the ones added
+/// since its last sync
+const MAX_MISSING_SHARE: f64 = 0.01;
+
+/// Number of databases downloaded at once
+const CONCURRENCY: usize = 8;
+
+/// Fewest databases the expected packages are a majority of, when there is
+/// no reference
+const MIN_MAJORITY: usize = 3;
+
+/// Largest database downloaded, several times the biggest of Arch Linux
+const MAX_DB_SIZE: usize = 64 * 1024 * 1024;
+
+/// Package names of a pacman repository database along with their file names
+pub type Packages = BTreeMap<String, Option<String>>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RepoVerification {
+    pub db_url: String,
+    pub packages: usize,
+    /// expected packages (of the majority or the reference) the mirror lacks
+    pub missing: usize,
+    /// whether the signature of a sample package is present, when checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<bool>,
+    /// why the mirror failed the check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RepoVerification {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Settings of `--verify-repo`, see `Config::repo_check`
+#[derive(Debug, Clone)]
+pub struct RepoCheck {
+    /// mirror whose database is trusted instead of the majority
+    pub reference: Option<Url>,
+    pub signatures: bool,
+    pub timeout: u64,
+}
+
+/// `$repo.db` next to the tested `$repo.files`; `None` for other targets
+pub fn db_url(mirror: &Mirror) -> Option<Url> {
+    let path = mirror.url_to_test.path();
+    if path.ends_with(".db") {
+        return Some(mirror.url_to_test.clone());
+    }
+    let db_path = format!("{}.db", path.strip_suffix(".files")?);
+    let mut url = mirror.url_to_test.clone();
+    url.set_path(&db_path);
+    Some(url)
+}
+
+/// Reads package names and file names of a (gzip or zstd compressed) tar
+/// database
+pub fn parse_database(bytes: &[u8]) -> Result<Packages, String> {
+    let reader: Box<dyn Read + '_> = match bytes {
+        [0x1f, 0x8b, ..] => Box::new(GzDecoder::new(bytes)),
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Box::new(
+            zstd::stream::read::Decoder::new(bytes).map_err(|e| format!("invalid zstd: {}", e))?,
+        ),
+        _ => Box::new(bytes),
+    };
+    let mut archive = tar::Archive::new(reader);
+    let mut packages = Packages::new();
+    let entries = archive
+        .entries()
+        .map_err(|e| format!("invalid archive: {}", e))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| format!("invalid archive: {}", e))?;
+        let is_desc = entry
+            .path()
+            .map(|path| path.ends_with("desc"))
+            .unwrap_or(false);
+        if !is_desc {
+            continue;
+        }
+        let mut desc = String::new();
+        entry
+            .read_to_string(&mut desc)
+            .map_err(|e| format!("invalid archive: {}", e))?;
+        let field = |name: &str| {
+            desc.lines()
+                .skip_while(|line| *line != name)
+                .nth(1)
+                .map(str::to_string)
+        };
+        if let Some(name) = field("%NAME%") {
+            packages.insert(name, field("%FILENAME%"));
+        }
+    }
+    if packages.is_empty() {
+        return Err("no packages in the database".to_string());
+    }
+    Ok(packages)
+}
+
+/// Names present in more than half of `databases`
+pub fn majority<'a>(databases: impl IntoIterator<Item = &'a Packages>) -> BTreeSet<&'a str> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut total = 0;
+    for packages in databases {
+        total += 1;
+        for name in packages.keys() {
+            *counts.entry(name).or_default() += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .filter(|(_, count)| count * 2 > total)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+impl RepoCheck {
+    async fn download(&self, client: &reqwest::Client, url: &Url) -> Result<Packages, String> {
+        let mut response = client
+            .get(url.as_str())
+            .timeout(Duration::from_millis(self.timeout))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status().as_u16()));
+        }
+        let too_large = || format!("database larger than {} MiB", MAX_DB_SIZE >> 20);
+        if response
+            .content_length()
+            .is_some_and(|length| length > MAX_DB_SIZE as u64)
+        {
+            return Err(too_large());
+        }
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+            bytes.extend_from_slice(&chunk);
+            if bytes.len() > MAX_DB_SIZE {
+                return Err(too_large());
+            }
+        }
+        parse_database(&bytes)
+    }
+
+    async fn has_signature(&self, client: &reqwest::Client, db_url: &Url, filename: &str) -> bool {
+        let Ok(url) = db_url.join(&format!("{}.sig", filename)) else {
+            return false;
+        };
+        client
+            .head(url)
+            .timeout(Duration::from_millis(self.timeout))
+            .send()
+            .await
+            .is_ok_and(|response| response.status().is_success())
+    }
+
+    /// Requires the signature of the first expected package, so that mirrors
+    /// are compared alike
+    async fn check_signature(
+        &self,
+        client: &reqwest::Client,
+        url: &Url,
+        packages: &Packages,
+        expected: &BTreeSet<&str>,
+        verification: &mut RepoVerification,
+    ) {
+        let filename = expected
+            .iter()
+            .find_map(|name| packages.get(*name).cloned().flatten());
+        let present = match &filename {
+            Some(filename) => self.has_signature(client, url, filename).await,
+            None => false,
+        };
+        verification.signature = Some(present);
+        if !present {
+            verification.error = Some(match filename {
+                Some(filename) => format!("no signature of {}", filename),
+                None => "no package file names in the database".to_string(),
+            });
+        }
+    }
+
+    fn compare(
+        &self,
+        url: &Url,
+        database: &Result<Packages, String>,
+        expected: &BTreeSet<&str>,
+    ) -> RepoVerification {
+        let mut verification = RepoVerification {
+            db_url: url.to_string(),
+            packages: 0,
+            missing: 0,
+            signature: None,
+            error: None,
+        };
+        match database {
+            Ok(packages) => {
+                verification.packages = packages.len();
+                verification.missing = expected
+                    .iter()
+                    .filter(|name| !packages.contains_key(**name))
+                    .count();
+                if verification.missing as f64 > expected.len() as f64 * MAX_MISSING_SHARE {
+                    verification.error = Some(format!(
+                        "missing {} of {} packages",
+                        verification.missing,
+                        expected.len()
+                    ));
+                }
+            }
+            Err(e) => verification.error = Some(e.clone()),
+        }
+        verification
+    }
+
+    /// Checks databases of `results` in their order until `wanted` of them
+    /// pass (or at least `MIN_MAJORITY` are checked to take a majority of)
+    /// and sets their `repo`; the rest, and mirrors of targets without a
+    /// pacman database, are left unchecked. Returns why the reference
+    /// database couldn't be used, the majority being compared with instead.
+    pub fn verify(&self, results: &mut [SpeedTestResult], wanted: usize) -> Option<String> {
+        let client = match default_client_builder() {
+            Ok(client) => client,
+            Err(e) => {
+                for result in results.iter_mut() {
+                    result.repo = db_url(&result.item).map(|url| RepoVerification {
+                        db_url: url.to_string(),
+                        packages: 0,
+                        missing: 0,
+                        signature: None,
+                        error: Some(e.to_string()),
+                    });
+                }
+                return None;
+            }
+        };
+        let db_urls: Vec<Option<Url>> = results.iter().map(|r| db_url(&r.item)).collect();
+        let reference_url = self.reference.as_ref().and_then(|reference| {
+            results
+                .iter()
+                .zip(db_urls.iter())
+                .find_map(|(result, url)| {
+                    let path = result.item.url.make_relative(url.as_ref()?)?;
+                    reference.join(&path).ok()
+                })
+        });
+
+        let runtime = http::runtime();
+        let mut reference_error = None;
+        let reference =
+            reference_url.and_then(|url| match runtime.block_on(self.download(&client, &url)) {
+                Ok(packages) => Some(packages),
+                Err(e) => {
+                    reference_error = Some(format!("{} - {}", url, e));
+                    None
+                }
+            });
+
+        let candidates: Vec<usize> = (0..results.len())
+            .filter(|index| db_urls[*index].is_some())
+            .collect();
+        let mut databases: Vec<Option<Result<Packages, String>>> = vec![None; results.len()];
+        let mut verifications: Vec<Option<RepoVerification>> = vec![None; results.len()];
+        let mut checked = 0;
+        loop {
+            let passed = verifications
+                .iter()
+                .flatten()
+                .filter(|v| v.passed())
+                .count();
+            if passed >= wanted || checked == candidates.len() {
+                break;
+            }
+            let mut batch_size = wanted - passed;
+            if reference.is_none() {
+                batch_size = batch_size.max(MIN_MAJORITY.saturating_sub(checked));
+            }
+            let batch = &candidates[checked..cmp::min(checked + batch_size, candidates.len())];
+            checked += batch.len();
+            let downloaded: Vec<Result<Packages, String>> = runtime.block_on(
+                stream::iter(batch.iter().map(|index| {
+                    let url = db_urls[*index].as_ref().unwrap();
+                    self.download(&client, url)
+                }))
+                .buffered(CONCURRENCY)
+                .collect(),
+            );
+            for (index, database) in batch.iter().zip(downloaded) {
+                databases[*index] = Some(database);
+            }
+
+            // verdicts of earlier batches too, as the majority may change
+            let expected = match &reference {
+                Some(packages) => packages.keys().map(String::as_str).collect(),
+                None => majority(databases.iter().flatten().flatten()),
+            };
+            for index in candidates[..checked].iter().copied() {
+                let (Some(url), Some(database)) = (&db_urls[index], &databases[index]) else {
+                    continue;
+                };
+                let previous = verifications[index].take();
+                let mut verification = self.compare(url, database, &expected);
+                if let (true, Ok(packages)) = (self.signatures && verification.passed(), database) {
+                    match previous.filter(|previous| previous.signature.is_some()) {
+                        Some(previous) => {
+                            verification.signature = previous.signature;
+                            verification.error = previous.error;
+                        }
+                        None => runtime.block_on(self.check_signature(
+                            &client,
+                            url,
+                            packages,
+                            &expected,
+                            &mut verification,
+                        )),
+                    }
+                }
+                verifications[index] = Some(verification);
+            }
+        }
+
+        for (result, verification) in results.iter_mut().zip(verifications) {
+            result.repo = verification;
+        }
+        reference_error
+    }
+}
+
+pub fn failed(result: &SpeedTestResult) -> bool {
+    result.repo.as_ref().is_some_and(|repo| !repo.passed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    fn database(packages: &[&str]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::fast()));
+        for name in packages {
+            let desc = format!(
+                "%FILENAME%\n{0}-1.0-1-x86_64.pkg.tar.zst\n\n%NAME%\n{0}\n\n%VERSION%\n1.0-1\n",
+                name
+            );
+            let mut header = tar::Header::new_gnu();
+            header.set_size(desc.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, format!("{}-1.0-1/desc", name), desc.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn parses_gzipped_database() {
+        let packages = parse_database(&database(&["bash", "glibc"])).unwrap();
+        assert_eq!(
+            packages.get("bash").cloned().flatten().as_deref(),
+            Some("bash-1.0-1-x86_64.pkg.tar.zst")
+        );
+        assert_eq!(packages.len(), 2);
+        assert!(parse_database(&[0; 1024]).is_err());
+        assert!(parse_database(b"<html>not found</html>").is_err());
+    }
+
+    #[test]
+    fn majority_ignores_partial_mirrors() {
+        let full = parse_database(&database(&["bash", "glibc", "linux"])).unwrap();
+        let partial = parse_database(&database(&["bash"])).unwrap();
+        let expected = majority([&full, &partial, &full]);
+        assert_eq!(
+            expected.into_iter().collect::<Vec<_>>(),
+            ["bash", "glibc", "linux"]
+        );
+    }
+
+    #[test]
+    fn db_url_is_next_to_files() {
+        let url = Url::parse("https://mirror.example.org/archlinux/").unwrap();
+        let mirror = |path: &str| Mirror {
+            url_to_test: url.join(path).unwrap(),
+            url: url.clone(),
+            country: None,
+            meta: Default::default(),
+        };
+        assert_eq!(
+            db_url(&mirror("extra/os/x86_64/extra.files"))
+                .unwrap()
+                .as_str(),
+            "https://mirror.example.org/archlinux/extra/os/x86_64/extra.db"
+        );
+        assert!(db_url(&mirror("dists/stable/Release")).is_none());
+    }
+}
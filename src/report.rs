@@ -0,0 +1,446 @@
+use crate::freshness::Freshness;
+use crate::http::Timings;
+use crate::mirror::Mirror;
+use crate::net::AddressFamily;
+use crate::repo_check::RepoVerification;
+use crate::score::Score;
+use crate::speed_test::{SpeedStats, SpeedTestError, SpeedTestOutcome, SpeedTestResult, TestPhase};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Json,
+    Ndjson,
+}
+
+impl FromStr for ReportFormat {
+    type Err = &'static str;
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "json" => Ok(ReportFormat::Json),
+            "ndjson" => Ok(ReportFormat::Ndjson),
+            _ => Err("could not parse report format"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MirrorStatus {
+    /// dropped by protocol/country filters or deduplication
+    Filtered,
+    /// passed filters, but country hopping never picked it
+    Untested,
+    /// every speed test of the mirror failed
+    Failed,
+    /// speed tests of the mirror were skipped as a budget ran out, see
+    /// `--max-total-bytes` and `--max-total-time`
+    Skipped,
+    /// has at least one successful speed test
+    Tested,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TestReport {
+    pub phase: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jump: Option<usize>,
+    /// address family of `--ip-family`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_family: Option<AddressFamily>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_downloaded: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elapsed_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_time_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_ip: Option<IpAddr>,
+    /// phases of the request, see `http::Timings`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttfb_ms: Option<f64>,
+    /// ranged requests of `--lite`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range_probes: Option<usize>,
+    /// HEAD request opening the connection of `--warm-retest`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warmup_ms: Option<f64>,
+    /// bytes per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f64>,
+    /// median latency of `--rank-by=latency` probes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl TestReport {
+    fn from_outcome(outcome: &SpeedTestOutcome) -> Self {
+        let phase = match outcome {
+            Ok(result) => result.phase,
+            Err(failure) => failure.phase,
+        };
+        let ip_family = match outcome {
+            Ok(result) => result.family,
+            Err(failure) => failure.family,
+        };
+        let jump = match phase {
+            TestPhase::Jump(jump) => Some(jump),
+            _ => None,
+        };
+        match outcome {
+            Ok(result) if result.latency.is_some() => TestReport {
+                phase: phase.to_string(),
+                jump,
+                ip_family,
+                bytes_downloaded: None,
+                elapsed_ms: None,
+                connection_time_ms: None,
+                remote_ip: result.remote_ip,
+                dns_ms: timing(result, |t| t.dns),
+                tcp_ms: timing(result, |t| t.tcp),
+                tls_ms: timing(result, |t| t.tls),
+                ttfb_ms: timing(result, |t| t.ttfb),
+                range_probes: None,
+                warmup_ms: None,
+                speed: None,
+                latency_ms: result.latency.as_ref().map(duration_ms),
+                error: None,
+            },
+            Ok(result) => TestReport {
+                phase: phase.to_string(),
+                jump,
+                ip_family,
+                bytes_downloaded: Some(result.bytes_downloaded),
+                elapsed_ms: Some(duration_ms(&result.elapsed)),
+                connection_time_ms: Some(duration_ms(&result.connection_time)),
+                remote_ip: result.remote_ip,
+                dns_ms: timing(result, |t| t.dns),
+                tcp_ms: timing(result, |t| t.tcp),
+                tls_ms: timing(result, |t| t.tls),
+                ttfb_ms: timing(result, |t| t.ttfb),
+                range_probes: result.range_probes,
+                warmup_ms: result.warmup_time.as_ref().map(duration_ms),
+                speed: Some(result.speed),
+                latency_ms: None,
+                error: None,
+            },
+            Err(failure) => TestReport {
+                phase: phase.to_string(),
+                jump,
+                ip_family,
+                bytes_downloaded: None,
+                elapsed_ms: None,
+                connection_time_ms: None,
+                remote_ip: None,
+                dns_ms: None,
+                tcp_ms: None,
+                tls_ms: None,
+                ttfb_ms: None,
+                range_probes: None,
+                warmup_ms: None,
+                speed: None,
+                latency_ms: None,
+                error: Some(failure.error.to_string()),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct MirrorReport {
+    pub url: String,
+    pub url_to_test: String,
+    pub country: Option<&'static str>,
+    pub status: MirrorStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter_reason: Option<&'static str>,
+    pub tests: Vec<TestReport>,
+    /// 1-based position in the final ranking
+    pub rank: Option<usize>,
+    /// speed statistics of `--samples` re-tests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_stats: Option<SpeedStats>,
+    /// sync marker and lag of `--verify-freshness`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub freshness: Option<Freshness>,
+    /// repository database check of `--verify-repo`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<RepoVerification>,
+    /// composite score of `--score` with contributions of each metric
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<Score>,
+    /// whether the mirror made it into the output mirrorlist
+    pub in_output: bool,
+}
+
+impl MirrorReport {
+    fn new(mirror: &Mirror, status: MirrorStatus) -> Self {
+        Self {
+            url: mirror.url.to_string(),
+            url_to_test: mirror.url_to_test.to_string(),
+            country: mirror.country.map(|c| c.code),
+            status,
+            filter_reason: None,
+            tests: Vec::new(),
+            rank: None,
+            speed_stats: None,
+            freshness: None,
+            repo: None,
+            score: None,
+            in_output: false,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct RunReport {
+    pub version: &'static str,
+    pub started_at: String,
+    pub finished_at: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+    #[serde(flatten)]
+    pub run: RunReport,
+    pub mirrors: Vec<MirrorReport>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum NdjsonLine<'a> {
+    Run(&'a RunReport),
+    Mirror(&'a MirrorReport),
+}
+
+impl Report {
+    pub fn write(&self, path: &str, format: ReportFormat) -> io::Result<()> {
+        if path == "-" {
+            self.write_to(io::stdout().lock(), format)
+        } else {
+            self.write_to(BufWriter::new(File::create(path)?), format)
+        }
+    }
+
+    pub fn write_to(&self, mut writer: impl Write, format: ReportFormat) -> io::Result<()> {
+        match format {
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(&mut writer, self)?;
+                writeln!(writer)?;
+            }
+            ReportFormat::Ndjson => {
+                serde_json::to_writer(&mut writer, &NdjsonLine::Run(&self.run))?;
+                writeln!(writer)?;
+                for mirror in self.mirrors.iter() {
+                    serde_json::to_writer(&mut writer, &NdjsonLine::Mirror(mirror))?;
+                    writeln!(writer)?;
+                }
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// Collects what happened to every fetched mirror during a run
+#[derive(Default)]
+pub struct ReportBuilder {
+    mirrors: Vec<MirrorReport>,
+    index: HashMap<String, usize>,
+}
+
+impl ReportBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_filtered(&mut self, mirror: &Mirror, reason: &'static str) {
+        let mut report = MirrorReport::new(mirror, MirrorStatus::Filtered);
+        report.filter_reason = Some(reason);
+        self.mirrors.push(report);
+    }
+
+    /// Adds a mirror which passed filtering and was handed to speed tests
+    pub fn add_mirror(&mut self, mirror: &Mirror) {
+        self.index
+            .insert(mirror.url_to_test.to_string(), self.mirrors.len());
+        self.mirrors
+            .push(MirrorReport::new(mirror, MirrorStatus::Untested));
+    }
+
+    fn get_mut(&mut self, mirror: &Mirror) -> Option<&mut MirrorReport> {
+        let index = *self.index.get(mirror.url_to_test.as_str())?;
+        self.mirrors.get_mut(index)
+    }
+
+    pub fn add_outcome(&mut self, outcome: &SpeedTestOutcome) {
+        let mirror = match outcome {
+            Ok(result) => &result.item,
+            Err(failure) => &failure.item,
+        };
+        if let Some(report) = self.get_mut(mirror) {
+            report.tests.push(TestReport::from_outcome(outcome));
+            report.status = match (report.status, outcome) {
+                (_, Ok(_)) | (MirrorStatus::Tested, Err(_)) => MirrorStatus::Tested,
+                (MirrorStatus::Failed, Err(_)) => MirrorStatus::Failed,
+                (_, Err(failure)) if matches!(failure.error, SpeedTestError::OverBudget(_)) => {
+                    MirrorStatus::Skipped
+                }
+                _ => MirrorStatus::Failed,
+            };
+        }
+    }
+
+    /// Sets final ranks; the first `output_count` results are the ones output
+    pub fn set_ranking(&mut self, results: &[SpeedTestResult], output_count: usize) {
+        for (index, result) in results.iter().enumerate() {
+            if let Some(report) = self.get_mut(&result.item) {
+                report.rank = Some(index + 1);
+                report.speed_stats = result.stats.clone();
+                report.freshness = result.freshness.clone();
+                report.repo = result.repo.clone();
+                report.score = result.score.clone();
+                report.in_output = index < output_count;
+            }
+        }
+    }
+
+    /// Marks a tested mirror which was excluded from the ranking afterwards
+    pub fn set_excluded(&mut self, result: &SpeedTestResult, reason: &'static str) {
+        if let Some(report) = self.get_mut(&result.item) {
+            report.filter_reason = Some(reason);
+            report.freshness = result.freshness.clone();
+            report.repo = result.repo.clone();
+        }
+    }
+
+    /// Marks untested mirrors which were output as a fallback
+    pub fn set_output(&mut self, mirror: &Mirror) {
+        if let Some(report) = self.get_mut(mirror) {
+            report.in_output = true;
+        }
+    }
+
+    pub fn build(self, started_at: String, finished_at: String) -> Report {
+        Report {
+            run: RunReport {
+                version: env!("CARGO_PKG_VERSION"),
+                started_at,
+                finished_at,
+            },
+            mirrors: self.mirrors,
+        }
+    }
+}
+
+fn duration_ms(d: &Duration) -> f64 {
+    d.as_secs_f64() * 1000.
+}
+
+fn timing(result: &SpeedTestResult, phase: fn(Timings) -> Option<Duration>) -> Option<f64> {
+    result.timings.and_then(phase).as_ref().map(duration_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::countries::Country;
+    use crate::speed_test::{SpeedTestError, SpeedTestFailure};
+    use url::Url;
+
+    fn mirror(url: &str) -> Mirror {
+        let url = Url::parse(url).unwrap();
+        Mirror {
+            country: Country::from_str("DE"),
+            url_to_test: url.join("test.file").unwrap(),
+            url,
+            meta: Default::default(),
+        }
+    }
+
+    #[test]
+    fn tracks_mirror_lifecycle() {
+        let tested = mirror("https://tested.example/");
+        let failed = mirror("https://failed.example/");
+        let untested = mirror("https://untested.example/");
+        let filtered = mirror("ftp://filtered.example/");
+
+        let mut builder = ReportBuilder::new();
+        builder.add_filtered(&filtered, "protocol");
+        for m in [&tested, &failed, &untested] {
+            builder.add_mirror(m);
+        }
+        let result = SpeedTestResult::new(
+            tested.clone(),
+            1000,
+            Duration::from_secs(1),
+            Duration::from_millis(20),
+            TestPhase::Jump(2),
+        );
+        builder.add_outcome(&Ok(result.clone()));
+        builder.add_outcome(&Err(SpeedTestFailure {
+            item: tested.clone(),
+            error: SpeedTestError::TooFewBytesDownloadedError,
+            phase: TestPhase::Retest,
+            family: None,
+        }));
+        builder.add_outcome(&Err(SpeedTestFailure {
+            item: failed.clone(),
+            error: SpeedTestError::TooFewBytesDownloadedError,
+            phase: TestPhase::Unlabeled,
+            family: None,
+        }));
+        builder.set_ranking(&[result], 1);
+
+        let report = builder.build("start".to_string(), "finish".to_string());
+        let statuses: Vec<_> = report.mirrors.iter().map(|m| m.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                MirrorStatus::Filtered,
+                MirrorStatus::Tested,
+                MirrorStatus::Failed,
+                MirrorStatus::Untested
+            ]
+        );
+        assert_eq!(report.mirrors[0].filter_reason, Some("protocol"));
+        assert_eq!(report.mirrors[1].rank, Some(1));
+        assert!(report.mirrors[1].in_output);
+        assert_eq!(report.mirrors[1].tests.len(), 2);
+        assert_eq!(report.mirrors[1].tests[0].jump, Some(2));
+    }
+
+    #[test]
+    fn writes_ndjson_run_line_first() {
+        let mut builder = ReportBuilder::new();
+        builder.add_mirror(&mirror("https://a.example/"));
+        let report = builder.build("start".to_string(), "finish".to_string());
+
+        let mut buf = Vec::new();
+        report.write_to(&mut buf, ReportFormat::Ndjson).unwrap();
+        let lines: Vec<serde_json::Value> = String::from_utf8(buf)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["type"], "run");
+        assert_eq!(lines[1]["type"], "mirror");
+        assert_eq!(lines[1]["status"], "untested");
+        assert_eq!(lines[1]["country"], "DE");
+    }
+}
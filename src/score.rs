@@ -0,0 +1,295 @@
+use crate::speed_test::SpeedTestResult;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// Presets of `--score`
+const PRESETS: [(&str, &str); 2] = [
+    (
+        "balanced",
+        "speed*0.5 + latency*0.3 + freshness*0.1 + reliability*0.1",
+    ),
+    ("fresh", "speed*0.5 + freshness*0.5"),
+];
+
+/// Value of missing metrics, after normalisation
+const MISSING: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// bytes per second, history-blended when available
+    Speed,
+    /// latency of latency probes or connection time, in milliseconds
+    Latency,
+    /// seconds since the last sync, as reported by the mirror list or
+    /// measured by `--verify-freshness`
+    Freshness,
+    /// upstream mirror score, as reported by the mirror list
+    Reliability,
+}
+
+impl Metric {
+    fn value(&self, result: &SpeedTestResult) -> Option<f64> {
+        match self {
+            Metric::Speed => result.latency.is_none().then(|| result.rank_speed()),
+            Metric::Latency => Some(
+                result
+                    .latency
+                    .unwrap_or_else(|| result.connection_time_less_dns())
+                    .as_secs_f64()
+                    * 1000.,
+            ),
+            // lag behind other mirrors of `--verify-freshness`, unless the
+            // mirror list tells the sync delay
+            Metric::Freshness => result
+                .item
+                .meta
+                .sync_delay
+                .or_else(|| result.freshness.as_ref().and_then(|f| f.lag))
+                .map(|delay| delay as f64),
+            Metric::Reliability => result.item.meta.upstream_score,
+        }
+    }
+
+    fn higher_is_better(&self) -> bool {
+        matches!(self, Metric::Speed)
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+    fn from_str(metric: &str) -> Result<Self, Self::Err> {
+        match metric {
+            "speed" => Ok(Metric::Speed),
+            "latency" => Ok(Metric::Latency),
+            "freshness" => Ok(Metric::Freshness),
+            "reliability" => Ok(Metric::Reliability),
+            _ => Err(format!("unknown score metric: {}", metric)),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Metric::Speed => write!(f, "speed"),
+            Metric::Latency => write!(f, "latency"),
+            Metric::Freshness => write!(f, "freshness"),
+            Metric::Reliability => write!(f, "reliability"),
+        }
+    }
+}
+
+/// Contribution of a single metric to a mirror's score
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoreComponent {
+    pub metric: Metric,
+    /// raw value; `None` when the mirror list doesn't provide it
+    pub value: Option<f64>,
+    /// 0-1, 1 being the best value of the run
+    pub normalized: f64,
+    pub weight: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Score {
+    /// weighted mean of normalized metrics, 0-1
+    pub total: f64,
+    pub components: Vec<ScoreComponent>,
+}
+
+/// Weighted sum of metrics, e.g. `speed*0.6 + latency*0.2 + freshness*0.2`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreFunction {
+    pub terms: Vec<(Metric, f64)>,
+}
+
+impl FromStr for ScoreFunction {
+    type Err = String;
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let expression = PRESETS
+            .iter()
+            .find(|(name, _)| *name == expression.trim())
+            .map(|(_, preset)| *preset)
+            .unwrap_or(expression);
+        let mut terms = Vec::new();
+        for term in expression.split('+') {
+            let factors: Vec<_> = term.split('*').map(str::trim).collect();
+            let (metric, weight) = match factors[..] {
+                [metric] => (metric, "1"),
+                [a, b] if a.parse::<f64>().is_ok() => (b, a),
+                [a, b] => (a, b),
+                _ => return Err(format!("could not parse score term: {}", term.trim())),
+            };
+            let weight: f64 = weight
+                .parse()
+                .map_err(|_| format!("could not parse score weight: {}", weight))?;
+            if weight < 0. || !weight.is_finite() {
+                return Err(format!("invalid score weight: {}", weight));
+            }
+            terms.push((metric.parse()?, weight));
+        }
+        if terms.iter().map(|(_, weight)| weight).sum::<f64>() <= 0. {
+            return Err("score weights sum up to zero".to_string());
+        }
+        Ok(Self { terms })
+    }
+}
+
+impl ScoreFunction {
+    /// Scores results, normalising each metric over the run by min-max (so
+    /// that 1 is the best value; missing values count as 0.5) and sorts them
+    /// best first.
+    pub fn apply(&self, results: &mut [SpeedTestResult]) {
+        let weights: f64 = self.terms.iter().map(|(_, weight)| weight).sum();
+        let values: Vec<Vec<Option<f64>>> = self
+            .terms
+            .iter()
+            .map(|(metric, _)| results.iter().map(|r| metric.value(r)).collect())
+            .collect();
+        let ranges: Vec<Option<(f64, f64)>> = values
+            .iter()
+            .map(|values| {
+                values
+                    .iter()
+                    .flatten()
+                    .fold(None, |range, &value| match range {
+                        None => Some((value, value)),
+                        Some((min, max)) => Some((f64::min(min, value), f64::max(max, value))),
+                    })
+            })
+            .collect();
+
+        for (index, result) in results.iter_mut().enumerate() {
+            let components: Vec<_> = self
+                .terms
+                .iter()
+                .zip(values.iter().zip(ranges.iter()))
+                .map(|((metric, weight), (values, range))| {
+                    let value = values[index];
+                    let normalized = match (value, range) {
+                        (Some(value), Some((min, max))) if max > min => {
+                            let share = (value - min) / (max - min);
+                            if metric.higher_is_better() {
+                                share
+                            } else {
+                                1. - share
+                            }
+                        }
+                        (Some(_), _) => 1.,
+                        (None, _) => MISSING,
+                    };
+                    ScoreComponent {
+                        metric: *metric,
+                        value,
+                        normalized,
+                        weight: *weight,
+                    }
+                })
+                .collect();
+            let total = components
+                .iter()
+                .map(|c| c.normalized * c.weight)
+                .sum::<f64>()
+                / weights;
+            result.score = Some(Score { total, components });
+        }
+        results.sort_by(|a, b| b.rank_score().partial_cmp(&a.rank_score()).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::freshness::Freshness;
+    use crate::mirror::{Mirror, MirrorMeta};
+    use crate::speed_test::TestPhase;
+    use std::time::Duration;
+    use url::Url;
+
+    fn result(url: &str, speed: usize, connection_ms: u64, delay: Option<i64>) -> SpeedTestResult {
+        let url = Url::parse(url).unwrap();
+        SpeedTestResult::new(
+            Mirror {
+                url_to_test: url.clone(),
+                url,
+                country: None,
+                meta: MirrorMeta {
+                    sync_delay: delay,
+                    upstream_score: None,
+                },
+            },
+            speed,
+            Duration::from_secs(1),
+            Duration::from_millis(connection_ms),
+            TestPhase::Retest,
+        )
+    }
+
+    #[test]
+    fn parses_expressions_and_presets() {
+        let score: ScoreFunction = "speed*0.6 + 0.2*latency + freshness".parse().unwrap();
+        assert_eq!(
+            score.terms,
+            vec![
+                (Metric::Speed, 0.6),
+                (Metric::Latency, 0.2),
+                (Metric::Freshness, 1.)
+            ]
+        );
+        assert_eq!("balanced".parse::<ScoreFunction>().unwrap().terms.len(), 4);
+        assert!("speed*fast".parse::<ScoreFunction>().is_err());
+        assert!("throughput".parse::<ScoreFunction>().is_err());
+        assert!("speed*0".parse::<ScoreFunction>().is_err());
+    }
+
+    #[test]
+    fn normalizes_metrics_per_run() {
+        let score: ScoreFunction = "speed*0.5 + freshness*0.5".parse().unwrap();
+        let mut results = vec![
+            result("https://fast.example.org/", 3000, 10, Some(7200)),
+            result("https://fresh.example.org/", 2000, 10, Some(0)),
+            result("https://slow.example.org/", 1000, 10, None),
+        ];
+        score.apply(&mut results);
+
+        let urls: Vec<_> = results.iter().map(|r| r.item.url.host_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                Some("fresh.example.org"),
+                Some("fast.example.org"),
+                Some("slow.example.org")
+            ]
+        );
+        let fresh = results[0].score.as_ref().unwrap();
+        assert!((fresh.total - 0.75).abs() < 1e-9);
+        assert_eq!(fresh.components[1].value, Some(0.));
+        assert_eq!(fresh.components[1].normalized, 1.);
+        let slow = results[2].score.as_ref().unwrap();
+        assert_eq!(slow.components[1].normalized, MISSING);
+    }
+
+    #[test]
+    fn falls_back_to_lag_behind_other_mirrors() {
+        let score: ScoreFunction = "freshness".parse().unwrap();
+        let mut lagging = result("https://lagging.example.org/", 1000, 10, None);
+        lagging.freshness = Some(Freshness {
+            synced_at: Some(0),
+            lag: Some(3600),
+            stale: false,
+            error: None,
+        });
+        let mut results = vec![
+            lagging,
+            result("https://fresh.example.org/", 1000, 10, Some(60)),
+        ];
+        score.apply(&mut results);
+
+        assert_eq!(results[0].item.url.host_str(), Some("fresh.example.org"));
+        let lagging = results[1].score.as_ref().unwrap();
+        assert_eq!(lagging.components[0].value, Some(3600.));
+        assert_eq!(results[1].item.meta.sync_delay, None);
+    }
+}
@@ -1,29 +1,104 @@
 extern crate byte_unit;
 extern crate reqwest;
-use crate::config::{Config, default_client_builder};
+use crate::budget::{Budget, BudgetLimit};
 use crate::countries::{Country, LinkTo, LinkType};
+use crate::freshness::Freshness;
+use crate::http::{self, Timings};
 use crate::mirror::Mirror;
+use crate::net::AddressFamily;
+use crate::progress::{EarlyStopReason, ProgressEvent};
+use crate::ranker::{Event, RankerOptions};
+use crate::repo_check::RepoVerification;
+use crate::score::Score;
 use byte_unit::{Byte, UnitType};
 use futures::future::join_all;
 use itertools::Itertools;
 use reqwest::Error as ReqwestError;
+use reqwest::header::RANGE;
+use reqwest::{Response, StatusCode};
+use serde::Serialize;
 use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::convert::From;
 use std::fmt;
 use std::fmt::Debug;
-use std::sync::mpsc::Sender;
+use std::net::IpAddr;
+use std::str::FromStr;
 use std::sync::{Arc, mpsc};
 use std::time::{Duration, Instant};
 
 use tokio::runtime::Runtime;
 use tokio::sync::Semaphore;
 
+/// Stage of `test_speed_by_countries` a speed test was run in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TestPhase {
+    /// 1-based jump number
+    Jump(usize),
+    Unlabeled,
+    Retest,
+}
+
+impl fmt::Display for TestPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestPhase::Jump(_) => write!(f, "jump"),
+            TestPhase::Unlabeled => write!(f, "unlabeled"),
+            TestPhase::Retest => write!(f, "retest"),
+        }
+    }
+}
+
+/// What mirrors are ranked by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RankBy {
+    Speed,
+    Latency,
+}
+
+impl FromStr for RankBy {
+    type Err = &'static str;
+    fn from_str(rank_by: &str) -> Result<Self, Self::Err> {
+        match rank_by {
+            "speed" => Ok(RankBy::Speed),
+            "latency" => Ok(RankBy::Latency),
+            _ => Err("could not parse rank-by"),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct SpeedTestResult {
     pub bytes_downloaded: usize,
     pub elapsed: Duration,
     pub speed: f64,
     pub connection_time: Duration,
+    /// Speed blended with the decayed speeds of previous runs, see `History::blend`
+    pub blended_speed: Option<f64>,
+    /// Median time to first byte of latency probes, nothing is downloaded
+    /// when set
+    pub latency: Option<Duration>,
+    /// Composite score of `--score`, see `ScoreFunction::apply`
+    pub score: Option<Score>,
+    /// Statistics of repeated re-tests, see `--samples`
+    pub stats: Option<SpeedStats>,
+    /// Sync marker compared with other mirrors, see `--verify-freshness`
+    pub freshness: Option<Freshness>,
+    /// Check of the repository database, see `--verify-repo`
+    pub repo: Option<RepoVerification>,
+    /// Address family the mirror was tested over, see `--ip-family`
+    pub family: Option<AddressFamily>,
+    /// Duration of the HEAD request opening the connection of a warm
+    /// re-test, see `--warm-retest`; `connection_time` excludes it then
+    pub warmup_time: Option<Duration>,
+    /// Phases of the request, `connection_time` is the sum of them
+    pub timings: Option<Timings>,
+    /// Address the mirror was reached at (the proxy's one behind a proxy)
+    pub remote_ip: Option<IpAddr>,
+    /// Number of ranged requests of `--lite`; `None` when the mirror ignored
+    /// ranges, or without `--lite`
+    pub range_probes: Option<usize>,
+    pub phase: TestPhase,
     pub item: Mirror,
 }
 impl SpeedTestResult {
@@ -32,35 +107,182 @@ impl SpeedTestResult {
         bytes_downloaded: usize,
         elapsed: Duration,
         connection_time: Duration,
+        phase: TestPhase,
     ) -> SpeedTestResult {
         SpeedTestResult {
             item,
             bytes_downloaded,
             elapsed,
             connection_time,
+            phase,
             speed: bytes_downloaded as f64 / elapsed.as_secs_f64(),
+            blended_speed: None,
+            latency: None,
+            score: None,
+            stats: None,
+            freshness: None,
+            repo: None,
+            family: None,
+            warmup_time: None,
+            timings: None,
+            remote_ip: None,
+            range_probes: None,
         }
     }
 
-    pub fn fmt_speed(&self) -> String {
-        let speed = Byte::from_f64(self.speed).unwrap();
-        format!("{:.1}/s", speed.get_appropriate_unit(UnitType::Decimal))
+    pub fn from_latency(item: Mirror, latency: Duration, phase: TestPhase) -> SpeedTestResult {
+        SpeedTestResult {
+            item,
+            bytes_downloaded: 0,
+            elapsed: latency,
+            connection_time: latency,
+            phase,
+            speed: 0.,
+            blended_speed: None,
+            latency: Some(latency),
+            score: None,
+            stats: None,
+            freshness: None,
+            repo: None,
+            family: None,
+            warmup_time: None,
+            timings: None,
+            remote_ip: None,
+            range_probes: None,
+        }
     }
 
-    fn fmt_duration(d: &Duration) -> String {
-        if d.as_secs() == 0 {
-            format!("{}ms", d.as_millis())
-        } else {
-            format!("{:.2}s", d.as_secs_f64())
+    /// Combines repeated tests of a mirror, failed ones being `None`, into
+    /// the result with the median speed along with `stats` of all of them.
+    pub fn from_samples(samples: Vec<Option<SpeedTestResult>>) -> Option<SpeedTestResult> {
+        let speeds: Vec<f64> = samples
+            .iter()
+            .map(|sample| sample.as_ref().map_or(0., |s| s.speed))
+            .collect();
+        let mut results: Vec<_> = samples.into_iter().flatten().collect();
+        if speeds.len() < 2 {
+            return results.pop();
         }
+        let stats = SpeedStats::new(&speeds);
+        let mut result = results.into_iter().min_by(|a, b| {
+            let a = (a.speed - stats.median).abs();
+            let b = (b.speed - stats.median).abs();
+            a.partial_cmp(&b).unwrap()
+        })?;
+        result.stats = Some(stats);
+        Some(result)
+    }
+
+    /// Speed to rank by: the history-blended one when available, otherwise
+    /// the lower bound of the confidence interval of repeated tests.
+    pub fn rank_speed(&self) -> f64 {
+        self.blended_speed
+            .or(self.stats.as_ref().map(|stats| stats.ci_low))
+            .unwrap_or(self.speed)
+    }
+
+    /// Higher ranks first: composite score when scored, negated latency of
+    /// latency probes, otherwise `rank_speed`
+    pub fn rank_score(&self) -> f64 {
+        match (&self.score, self.latency) {
+            (Some(score), _) => score.total,
+            (None, Some(latency)) => -latency.as_secs_f64(),
+            (None, None) => self.rank_speed(),
+        }
+    }
+
+    pub fn fmt_speed(&self) -> String {
+        format_speed(self.speed)
     }
 
     pub fn fmt_elapsed(&self) -> String {
-        Self::fmt_duration(&self.elapsed)
+        format_duration(&self.elapsed)
+    }
+
+    /// Connection time without DNS resolution, which tells the resolver's
+    /// delay rather than the mirror's distance
+    pub fn connection_time_less_dns(&self) -> Duration {
+        let dns = self.timings.and_then(|t| t.dns).unwrap_or_default();
+        self.connection_time.saturating_sub(dns)
     }
 
     pub fn fmt_connection_time(&self) -> String {
-        Self::fmt_duration(&self.connection_time)
+        format_duration(&self.connection_time)
+    }
+}
+
+/// Two-sided 95% critical values of Student's t-distribution by degrees of
+/// freedom, 1-30
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+/// Speeds of repeated tests of a mirror, failed tests counting as zero
+#[derive(Debug, Clone, Serialize)]
+pub struct SpeedStats {
+    pub samples: usize,
+    pub failures: usize,
+    pub median: f64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// t-based 95% confidence interval of the mean
+    pub ci_low: f64,
+    pub ci_high: f64,
+}
+
+impl SpeedStats {
+    pub fn new(speeds: &[f64]) -> Self {
+        let mut sorted = speeds.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let n = sorted.len();
+        let median = if n % 2 == 0 {
+            (sorted[n / 2 - 1] + sorted[n / 2]) / 2.
+        } else {
+            sorted[n / 2]
+        };
+        let mean = sorted.iter().sum::<f64>() / n as f64;
+        let margin = if n > 1 {
+            let variance = sorted.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+            let t = T_95.get(n - 2).copied().unwrap_or(1.96);
+            t * (variance / n as f64).sqrt()
+        } else {
+            0.
+        };
+        Self {
+            samples: n,
+            failures: speeds.iter().filter(|s| **s == 0.).count(),
+            median,
+            min: sorted[0],
+            max: sorted[n - 1],
+            mean,
+            ci_low: (mean - margin).max(0.),
+            ci_high: mean + margin,
+        }
+    }
+}
+
+pub fn format_speed(speed: f64) -> String {
+    let speed = Byte::from_f64(speed).unwrap();
+    format!("{:.1}/s", speed.get_appropriate_unit(UnitType::Decimal))
+}
+
+/// Sync lag in seconds, in hours beyond an hour
+pub fn format_lag(lag: i64) -> String {
+    if lag < 3600 {
+        format!("{}s", lag)
+    } else {
+        format!("{:.1}h", lag as f64 / 3600.)
+    }
+}
+
+pub fn format_duration(d: &Duration) -> String {
+    if d.as_secs() == 0 {
+        format!("{}ms", d.as_millis())
+    } else {
+        format!("{:.2}s", d.as_secs_f64())
     }
 }
 
@@ -69,13 +291,69 @@ impl fmt::Debug for SpeedTestResult {
         if let Some(country) = self.item.country {
             write!(f, "[{}] ", country.code)?;
         }
-        write!(
-            f,
-            "SpeedTestResult {{ speed: {}; elapsed: {}; connection_time: {} }}",
-            self.fmt_speed(),
-            self.fmt_elapsed(),
-            self.fmt_connection_time(),
-        )
+        if let Some(family) = self.family {
+            write!(f, "[{}] ", family)?;
+        }
+        if let Some(latency) = self.latency {
+            write!(
+                f,
+                "SpeedTestResult {{ latency: {}",
+                format_duration(&latency)
+            )?;
+        } else {
+            write!(
+                f,
+                "SpeedTestResult {{ speed: {}; elapsed: {}; connection_time: {}",
+                self.fmt_speed(),
+                self.fmt_elapsed(),
+                self.fmt_connection_time(),
+            )?;
+        }
+        if let Some(remote_ip) = self.remote_ip {
+            write!(f, "; ip: {}", remote_ip)?;
+        }
+        if let Some(timings) = self.timings {
+            let phases = [
+                ("dns", timings.dns),
+                ("tcp", timings.tcp),
+                ("tls", timings.tls),
+                ("ttfb", timings.ttfb),
+            ];
+            for (phase, duration) in phases {
+                if let Some(duration) = duration {
+                    write!(f, "; {}: {}", phase, format_duration(&duration))?;
+                }
+            }
+        }
+        if let Some(range_probes) = self.range_probes {
+            write!(f, "; range probes: {}", range_probes)?;
+        }
+        if let Some(warmup_time) = self.warmup_time {
+            write!(f, "; warm-up: {}", format_duration(&warmup_time))?;
+        }
+        if let Some(stats) = &self.stats {
+            write!(
+                f,
+                "; samples: {}; failed: {}; median: {}; min: {}; max: {}; 95% CI: {} - {}",
+                stats.samples,
+                stats.failures,
+                format_speed(stats.median),
+                format_speed(stats.min),
+                format_speed(stats.max),
+                format_speed(stats.ci_low),
+                format_speed(stats.ci_high),
+            )?;
+        }
+        if let Some(blended_speed) = self.blended_speed {
+            write!(f, "; blended: {}", format_speed(blended_speed))?;
+        }
+        if let Some(score) = &self.score {
+            write!(f, "; score: {:.3}", score.total)?;
+        }
+        if let Some(lag) = self.freshness.as_ref().and_then(|f| f.lag) {
+            write!(f, "; sync lag: {}", format_lag(lag))?;
+        }
+        write!(f, " }}")
     }
 }
 
@@ -87,10 +365,16 @@ impl fmt::Display for SpeedTestResult {
 
 pub type SpeedTestResults = Vec<SpeedTestResult>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SpeedTestError {
+    ClientBuildError(String),
     ReqwestError(String),
+    HttpStatus(u16),
     TooFewBytesDownloadedError,
+    /// the URL is an IP literal of the other family
+    NoAddress(AddressFamily),
+    /// the test was skipped, see `Budget`
+    OverBudget(BudgetLimit),
 }
 impl From<ReqwestError> for SpeedTestError {
     fn from(error: ReqwestError) -> Self {
@@ -98,71 +382,440 @@ impl From<ReqwestError> for SpeedTestError {
     }
 }
 
-#[derive(Debug)]
-enum RateStrategy {
+impl fmt::Display for SpeedTestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpeedTestError::ClientBuildError(s) | SpeedTestError::ReqwestError(s) => {
+                write!(f, "{}", s)
+            }
+            SpeedTestError::HttpStatus(status) => write!(f, "HTTP {}", status),
+            SpeedTestError::TooFewBytesDownloadedError => write!(f, "too few bytes downloaded"),
+            SpeedTestError::NoAddress(family) => write!(f, "no {} address", family),
+            SpeedTestError::OverBudget(limit) => write!(f, "skipped, {} budget exhausted", limit),
+        }
+    }
+}
+
+/// A mirror which was tested, but didn't produce a speed measurement
+#[derive(Debug, Clone)]
+pub struct SpeedTestFailure {
+    pub item: Mirror,
+    pub error: SpeedTestError,
+    pub phase: TestPhase,
+    /// Address family of `--ip-family`, if any
+    pub family: Option<AddressFamily>,
+}
+
+/// Outcome of every single speed test, including the ones superseded by
+/// re-tests
+pub type SpeedTestOutcome = Result<SpeedTestResult, SpeedTestFailure>;
+
+/// How neighbor countries are ranked: by cable connections and internet
+/// exchanges or by distance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateStrategy {
     HubsFirst,
     DistanceFirst,
 }
 
 async fn test_single_mirror(
     mirror: Mirror,
-    config: Arc<Config>,
+    phase: TestPhase,
+    options: Arc<RankerOptions>,
     semaphore: Arc<Semaphore>,
-    tx_progress: Sender<String>,
-) -> Result<SpeedTestResult, SpeedTestError> {
-    let mut bytes_downloaded: usize = 0;
+    budget: Arc<Budget>,
+    tx_events: mpsc::Sender<Event>,
+) -> SpeedTestOutcome {
+    let families: Vec<Option<AddressFamily>> = match options.ip_family {
+        Some(ip_family) => ip_family.families().iter().copied().map(Some).collect(),
+        None => vec![None],
+    };
+    let mut best: Option<SpeedTestOutcome> = None;
+    // with `--ip-family=both` each family is tested separately, the better
+    // one counts
+    for family in families {
+        let mut outcome = match family {
+            Some(family) if family.excludes(&mirror.url_to_test) => Err(SpeedTestFailure {
+                item: mirror.clone(),
+                error: SpeedTestError::NoAddress(family),
+                phase,
+                family: None,
+            }),
+            _ => {
+                let mirror = mirror.clone();
+                let options = Arc::clone(&options);
+                let semaphore = Arc::clone(&semaphore);
+                let budget = Arc::clone(&budget);
+                match options.rank_by {
+                    RankBy::Speed if options.lite => {
+                        measure_lite(mirror, phase, family, options, semaphore, budget).await
+                    }
+                    RankBy::Speed => {
+                        measure_mirror(mirror, phase, family, options, semaphore, budget).await
+                    }
+                    RankBy::Latency => {
+                        measure_latency(mirror, phase, family, options, semaphore, budget).await
+                    }
+                }
+            }
+        };
+        match &mut outcome {
+            Ok(result) => result.family = family,
+            Err(failure) => failure.family = family,
+        }
+        let event = match &outcome {
+            Ok(result) => ProgressEvent::MirrorTested(result.clone()),
+            Err(failure) => ProgressEvent::MirrorFailed(failure.clone()),
+        };
+        tx_events.send(Event::Progress(event)).ok();
+        best = match (best, outcome) {
+            (Some(Ok(best)), Ok(result)) if best.rank_score() >= result.rank_score() => {
+                Some(Ok(best))
+            }
+            (Some(Ok(best)), Err(_)) => Some(Ok(best)),
+            (_, outcome) => Some(outcome),
+        };
+    }
+    best.unwrap()
+}
+
+/// Median duration of `latency_probes` range requests until response headers,
+/// each one over a new connection, so that connecting and TLS handshake count
+async fn measure_latency(
+    mirror: Mirror,
+    phase: TestPhase,
+    family: Option<AddressFamily>,
+    options: Arc<RankerOptions>,
+    semaphore: Arc<Semaphore>,
+    budget: Arc<Budget>,
+) -> SpeedTestOutcome {
+    let _permit = semaphore.acquire().await;
+    if let Some(limit) = budget.exhausted() {
+        return Err(SpeedTestFailure {
+            item: mirror,
+            error: SpeedTestError::OverBudget(limit),
+            phase,
+            family: None,
+        });
+    }
+
+    let mut latencies = Vec::with_capacity(options.latency_probes);
+    let mut probe_timings = Vec::with_capacity(options.latency_probes);
+    let mut remote_ip = None;
+    let mut error = None;
+    for _ in 0..options.latency_probes {
+        let client = match http::client(family, false) {
+            Ok(c) => c,
+            Err(e) => {
+                error = Some(SpeedTestError::ClientBuildError(format!("{}", e)));
+                break;
+            }
+        };
+        let started = Instant::now();
+        let request = client
+            .get(mirror.url_to_test.as_str())
+            .header(RANGE, "bytes=0-0")
+            .timeout(Duration::from_millis(options.per_mirror_timeout))
+            .send();
+        let (response, timings) = http::timed(request).await;
+        // the resolver's delay isn't the mirror's
+        let latency = started
+            .elapsed()
+            .saturating_sub(timings.dns.unwrap_or_default());
+        match response {
+            Ok(response) if response.status().is_success() => {
+                remote_ip = response.remote_addr().map(|addr| addr.ip());
+                latencies.push(latency);
+                probe_timings.push(timings);
+            }
+            Ok(response) => error = Some(SpeedTestError::HttpStatus(response.status().as_u16())),
+            Err(e) => error = Some(e.into()),
+        }
+    }
+    drop(_permit);
 
+    if latencies.is_empty() {
+        return Err(SpeedTestFailure {
+            item: mirror,
+            error: error.unwrap_or(SpeedTestError::TooFewBytesDownloadedError),
+            phase,
+            family: None,
+        });
+    }
+    latencies.sort();
+    let mut result = SpeedTestResult::from_latency(mirror, latencies[latencies.len() / 2], phase);
+    result.remote_ip = remote_ip;
+    result.timings = Some(median_timings(&probe_timings));
+    Ok(result)
+}
+
+/// Median of each phase over the probes it happened in
+fn median_timings(probes: &[Timings]) -> Timings {
+    let median = |phase: fn(&Timings) -> Option<Duration>| {
+        let mut durations: Vec<_> = probes.iter().filter_map(phase).collect();
+        durations.sort();
+        durations.get(durations.len() / 2).copied()
+    };
+    Timings {
+        dns: median(|t| t.dns),
+        tcp: median(|t| t.tcp),
+        tls: median(|t| t.tls),
+        ttfb: median(|t| t.ttfb),
+    }
+}
+
+async fn measure_mirror(
+    mirror: Mirror,
+    phase: TestPhase,
+    family: Option<AddressFamily>,
+    options: Arc<RankerOptions>,
+    semaphore: Arc<Semaphore>,
+    budget: Arc<Budget>,
+) -> SpeedTestOutcome {
     let _permit = semaphore.acquire().await;
+    if let Some(limit) = budget.exhausted() {
+        return Err(SpeedTestFailure {
+            item: mirror,
+            error: SpeedTestError::OverBudget(limit),
+            phase,
+            family: None,
+        });
+    }
 
-    let client = match default_client_builder() {
+    // re-tests over a kept-alive connection, opened by a HEAD request first
+    let warm = options.warm_retest && phase == TestPhase::Retest;
+    let client = match http::client(family, warm) {
         Ok(c) => c,
         Err(e) => {
-            tx_progress
-                .send(format!(
-                    "{}FAILED TO BUILD HTTP CLIENT FOR {}",
-                    mirror
-                        .country
-                        .map(|c| format!("[{}] ", c.code))
-                        .unwrap_or("".to_string())
-                        .as_str(),
-                    mirror.url_to_test.as_str(),
-                ))
-                .unwrap();
-            return Err(SpeedTestError::ReqwestError(format!("{}", e)));
+            return Err(SpeedTestFailure {
+                item: mirror,
+                error: SpeedTestError::ClientBuildError(format!("{}", e)),
+                phase,
+                family: None,
+            });
         }
     };
+    let mut warmup_time = None;
+    if warm {
+        let started_warming_up = Instant::now();
+        let response = client
+            .head(mirror.url_to_test.as_str())
+            .timeout(Duration::from_millis(options.per_mirror_timeout))
+            .send()
+            .await;
+        if response.is_ok() {
+            warmup_time = Some(started_warming_up.elapsed());
+        }
+    }
     let started_connecting = Instant::now();
-    let response = client
+    let request = client
         .get(mirror.url_to_test.as_str())
-        .timeout(Duration::from_millis(config.per_mirror_timeout))
-        .send()
-        .await;
+        .timeout(Duration::from_millis(options.per_mirror_timeout))
+        .send();
+    let (response, timings) = http::timed(request).await;
     let mut response = match response {
         Ok(r) => r,
         Err(e) => {
-            tx_progress
-                .send(format!(
-                    "{}FAILED TO CONNECT TO {}",
-                    mirror
-                        .country
-                        .map(|c| format!("[{}] ", c.code))
-                        .unwrap_or("".to_string())
-                        .as_str(),
-                    mirror.url_to_test.as_str(),
-                ))
-                .unwrap();
-            return Err(e.into());
+            return Err(SpeedTestFailure {
+                item: mirror,
+                error: e.into(),
+                phase,
+                family: None,
+            });
         }
     };
     let connection_time = started_connecting.elapsed();
+    let remote_ip = response.remote_addr().map(|addr| addr.ip());
+    let (bytes_downloaded, elapsed) = time_chunks(&mut response, &options, &budget, None).await;
+    drop(_permit);
+
+    if bytes_downloaded < options.min_bytes_per_mirror {
+        return Err(SpeedTestFailure {
+            item: mirror,
+            error: SpeedTestError::TooFewBytesDownloadedError,
+            phase,
+            family: None,
+        });
+    }
+
+    let mut result =
+        SpeedTestResult::new(mirror, bytes_downloaded, elapsed, connection_time, phase);
+    result.warmup_time = warmup_time;
+    result.timings = Some(timings);
+    result.remote_ip = remote_ip;
+    Ok(result)
+}
+
+/// Speed of `lite_probes` ranged requests of doubling sizes over a kept-alive
+/// connection. The first one, sent while TCP slow start still holds back
+/// the throughput, is left out, as is the time to the first byte of the
+/// others, so that only transfer times count. Mirrors ignoring ranges are
+/// timed like `measure_mirror`, up to the bytes the probes would take; so
+/// are files too short to time any probe but the first.
+async fn measure_lite(
+    mirror: Mirror,
+    phase: TestPhase,
+    family: Option<AddressFamily>,
+    options: Arc<RankerOptions>,
+    semaphore: Arc<Semaphore>,
+    budget: Arc<Budget>,
+) -> SpeedTestOutcome {
+    let _permit = semaphore.acquire().await;
+    let failure = |mirror: Mirror, error: SpeedTestError| SpeedTestFailure {
+        item: mirror,
+        error,
+        phase,
+        family: None,
+    };
+    if let Some(limit) = budget.exhausted() {
+        return Err(failure(mirror, SpeedTestError::OverBudget(limit)));
+    }
+    let client = match http::client(family, true) {
+        Ok(c) => c,
+        Err(e) => {
+            return Err(failure(
+                mirror,
+                SpeedTestError::ClientBuildError(format!("{}", e)),
+            ));
+        }
+    };
+
+    let max_bytes = u32::try_from(options.lite_probes)
+        .ok()
+        .and_then(|probes| 1usize.checked_shl(probes))
+        .map_or(usize::MAX, |n| n - 1)
+        .saturating_mul(options.lite_probe_bytes);
+    let timeout = Duration::from_millis(options.per_mirror_timeout);
+    let url = &mirror.url_to_test;
+    let mut connection_time = Duration::ZERO;
+    let mut timings = None;
+    let mut remote_ip = None;
+    let mut bytes_downloaded = 0;
+    let (mut transfer_bytes, mut transfer_time) = (0, Duration::ZERO);
+    let mut range_probes = 0;
+    // response of the whole file, timed instead of the probes
+    let mut whole = None;
+    let (mut offset, mut size) = (0usize, options.lite_probe_bytes);
+    for probe in 0..options.lite_probes {
+        let started = Instant::now();
+        let last_byte = offset.saturating_add(size).saturating_sub(1);
+        let request = client
+            .get(url.as_str())
+            .header(RANGE, format!("bytes={}-{}", offset, last_byte))
+            .timeout(timeout)
+            .send();
+        let (response, probe_timings) = http::timed(request).await;
+        let mut response = match response {
+            Ok(response) => response,
+            Err(e) => return Err(failure(mirror, e.into())),
+        };
+        let response_time = started.elapsed();
+        if probe == 0 {
+            connection_time = response_time;
+            timings = Some(probe_timings);
+            remote_ip = response.remote_addr().map(|addr| addr.ip());
+        }
+        let ranges_ignored = match response.status() {
+            StatusCode::PARTIAL_CONTENT => false,
+            // the whole file is on its way
+            StatusCode::OK if probe == 0 => {
+                whole = Some(response);
+                break;
+            }
+            // no more ranges, yet a probe size of the file is as good
+            StatusCode::OK => true,
+            // the file ends here
+            StatusCode::RANGE_NOT_SATISFIABLE => break,
+            status => return Err(failure(mirror, SpeedTestError::HttpStatus(status.as_u16()))),
+        };
+        let mut received = 0;
+        while received < size {
+            match response.chunk().await {
+                Ok(Some(chunk)) => received += chunk.len(),
+                _ => break,
+            }
+        }
+        budget.add_bytes(received);
+        bytes_downloaded += received;
+        range_probes += 1;
+        if probe > 0 {
+            transfer_bytes += received;
+            transfer_time += started.elapsed().saturating_sub(response_time);
+        }
+        if received < size || ranges_ignored || budget.exhausted().is_some() {
+            break;
+        }
+        offset = offset.saturating_add(size);
+        size = size.saturating_mul(2);
+    }
+
+    if whole.is_none() && transfer_bytes == 0 && budget.exhausted().is_none() {
+        let request = client.get(url.as_str()).timeout(timeout).send();
+        let (response, fallback_timings) = http::timed(request).await;
+        match response {
+            Ok(response) if response.status().is_success() => {
+                timings.get_or_insert(fallback_timings);
+                whole = Some(response);
+            }
+            Ok(response) => {
+                return Err(failure(
+                    mirror,
+                    SpeedTestError::HttpStatus(response.status().as_u16()),
+                ));
+            }
+            Err(e) => return Err(failure(mirror, e.into())),
+        }
+    }
+    if let Some(mut response) = whole {
+        let (bytes, elapsed) = time_chunks(&mut response, &options, &budget, Some(max_bytes)).await;
+        drop(_permit);
+        if bytes < options.min_bytes_per_mirror.min(max_bytes) {
+            return Err(failure(mirror, SpeedTestError::TooFewBytesDownloadedError));
+        }
+        let mut result = SpeedTestResult::new(mirror, bytes, elapsed, connection_time, phase);
+        result.bytes_downloaded += bytes_downloaded;
+        result.timings = timings;
+        result.remote_ip = remote_ip;
+        return Ok(result);
+    }
+    drop(_permit);
+
+    if transfer_bytes == 0 || transfer_time.is_zero() {
+        return Err(failure(mirror, SpeedTestError::TooFewBytesDownloadedError));
+    }
+    let mut result = SpeedTestResult::new(
+        mirror,
+        transfer_bytes,
+        transfer_time,
+        connection_time,
+        phase,
+    );
+    // the first probe isn't timed, yet its bytes went over the wire
+    result.bytes_downloaded += bytes_downloaded - transfer_bytes;
+    result.timings = timings;
+    result.remote_ip = remote_ip;
+    result.range_probes = Some(range_probes);
+    Ok(result)
+}
+
+/// Reads `response` until chunk speeds settle (see `eps`), `max_per_mirror`
+/// passes, the budget runs out or `max_bytes` are read; returns the number
+/// of bytes read and the time it took
+async fn time_chunks(
+    response: &mut Response,
+    options: &RankerOptions,
+    budget: &Budget,
+    max_bytes: Option<usize>,
+) -> (usize, Duration) {
+    let mut bytes_downloaded: usize = 0;
     let started_ts = Instant::now();
     let mut prev_ts = started_ts;
-    let mut speeds: Vec<f64> = Vec::with_capacity(config.eps_checks);
+    let mut speeds: Vec<f64> = Vec::with_capacity(options.eps_checks);
     let mut index = 0;
-    let eps_checks_f64 = config.eps_checks as f64;
+    let eps_checks_f64 = options.eps_checks as f64;
     let mut filling_up = true;
-    let min_per_mirror_duration = Duration::from_millis(config.min_per_mirror);
-    let max_per_mirror_duration = Duration::from_millis(config.max_per_mirror);
+    let min_per_mirror_duration = Duration::from_millis(options.min_per_mirror);
+    let max_per_mirror_duration = Duration::from_millis(options.max_per_mirror);
 
     let mut now = Instant::now();
 
@@ -181,6 +834,7 @@ async fn test_single_mirror(
     {
         let chunk_size = chunk.len();
         bytes_downloaded += chunk_size;
+        budget.add_bytes(chunk_size);
 
         now = Instant::now();
         let chunk_speed = chunk_size as f64 / now.duration_since(prev_ts).as_secs_f64();
@@ -188,18 +842,22 @@ async fn test_single_mirror(
 
         if filling_up {
             speeds.push(chunk_speed);
-            index = (index + 1) % config.eps_checks;
+            index = (index + 1) % options.eps_checks;
             if index == 0 {
                 filling_up = false;
             }
         } else {
             speeds[index] = chunk_speed;
-            index = (index + 1) % config.eps_checks;
+            index = (index + 1) % options.eps_checks;
         }
         let total_download_time = now.duration_since(started_ts);
-        if bytes_downloaded >= config.min_bytes_per_mirror
+        // a measurement cut short still counts, given enough bytes
+        if budget.exhausted().is_some() || max_bytes.is_some_and(|max| bytes_downloaded >= max) {
+            break;
+        }
+        if bytes_downloaded >= options.min_bytes_per_mirror
             && total_download_time > min_per_mirror_duration
-            && speeds.len() == config.eps_checks
+            && speeds.len() == options.eps_checks
         {
             let mean = speeds.iter().sum::<f64>() / eps_checks_f64;
             let variance = speeds
@@ -212,63 +870,60 @@ async fn test_single_mirror(
                 / eps_checks_f64;
             let std_deviation = variance.sqrt();
 
-            if std_deviation / mean <= config.eps || total_download_time >= max_per_mirror_duration
+            if std_deviation / mean <= options.eps || total_download_time >= max_per_mirror_duration
             {
                 break;
             }
         }
     }
-    drop(_permit);
-
-    if bytes_downloaded < config.min_bytes_per_mirror {
-        tx_progress
-            .send(format!("TOO FEW BYTES LOADED {}", mirror.url.as_str()))
-            .unwrap();
-        return Err(SpeedTestError::TooFewBytesDownloadedError);
-    }
-
-    let speed_test_result = SpeedTestResult::new(
-        mirror,
-        bytes_downloaded,
-        prev_ts.duration_since(started_ts),
-        connection_time,
-    );
-
-    tx_progress
-        .send(format!("{:?}", speed_test_result))
-        .unwrap();
-
-    Ok(speed_test_result)
+    (bytes_downloaded, prev_ts.duration_since(started_ts))
 }
 
 fn test_mirrors<T: IntoIterator<Item = Mirror>>(
     mirrors: T,
-    config: Arc<Config>,
+    phase: TestPhase,
+    options: Arc<RankerOptions>,
     runtime: &Runtime,
     semaphore: Arc<Semaphore>,
-    tx_progress: mpsc::Sender<String>,
+    budget: Arc<Budget>,
+    tx_events: mpsc::Sender<Event>,
 ) -> SpeedTestResults {
+    test_mirrors_in_order(
+        mirrors, phase, options, runtime, semaphore, budget, tx_events,
+    )
+    .into_iter()
+    .flatten()
+    .filter_map(Result::ok)
+    .collect()
+}
+
+/// Like `test_mirrors`, but keeps the order of mirrors and their failures,
+/// `None` being a test which panicked
+fn test_mirrors_in_order<T: IntoIterator<Item = Mirror>>(
+    mirrors: T,
+    phase: TestPhase,
+    options: Arc<RankerOptions>,
+    runtime: &Runtime,
+    semaphore: Arc<Semaphore>,
+    budget: Arc<Budget>,
+    tx_events: mpsc::Sender<Event>,
+) -> Vec<Option<SpeedTestOutcome>> {
     let mut handles = Vec::new();
     for mirror in mirrors.into_iter() {
         handles.push(runtime.spawn(test_single_mirror(
             mirror,
-            Arc::clone(&config),
+            phase,
+            Arc::clone(&options),
             Arc::clone(&semaphore),
-            mpsc::Sender::clone(&tx_progress),
+            Arc::clone(&budget),
+            mpsc::Sender::clone(&tx_events),
         )));
     }
 
     runtime
         .block_on(join_all(handles))
         .into_iter()
-        .filter_map(|r| r.ok())
-        .filter_map(|r| {
-            // // USEFUL FOR DEBUGGING
-            // if let Err(e) = r.as_ref() {
-            //     println!("DEBUG => {:#?}", e);
-            // }
-            r.ok()
-        })
+        .map(|r| r.ok())
         .collect()
 }
 
@@ -296,26 +951,43 @@ fn rate_country_link<T>(
     }
 }
 
+/// Hops between countries testing their mirrors, then re-tests the top ones;
+/// `Event::Finished` is always sent last, unless nothing receives events
+/// anymore, which stops the tests early.
 pub fn test_speed_by_countries(
     mirrors: Vec<Mirror>,
-    config: Arc<Config>,
-    tx_progress: mpsc::Sender<String>,
-    tx_results: mpsc::Sender<SpeedTestResults>,
+    options: Arc<RankerOptions>,
+    tx_events: mpsc::Sender<Event>,
 ) {
     let mut map: HashMap<&'static Country, Vec<Mirror>> = HashMap::with_capacity(mirrors.len());
     let mut unlabeled_mirrors: Vec<Mirror> = Vec::new();
     for mirror in mirrors.into_iter() {
         match mirror.country {
             Some(country) => {
-                map.entry(country).or_insert_with(Vec::new).push(mirror);
+                map.entry(country).or_default().push(mirror);
             }
             None => {
                 unlabeled_mirrors.push(mirror);
             }
         }
     }
-    let runtime = tokio::runtime::Runtime::new().unwrap();
-    let semaphore = Arc::new(tokio::sync::Semaphore::new(config.concurrency));
+    let runtime = http::runtime();
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(options.concurrency));
+    let budget = Arc::new(Budget::new(options.max_total_bytes, options.max_total_time));
+    // reported once, whichever stage first finds the budget exhausted
+    let over_budget = || match budget.exhausted() {
+        Some(limit) => {
+            if budget.newly_exhausted().is_some() {
+                tx_events
+                    .send(Event::Progress(ProgressEvent::EarlyStop {
+                        reason: EarlyStopReason::BudgetExhausted(limit),
+                    }))
+                    .ok();
+            }
+            true
+        }
+        None => false,
+    };
 
     let mut countries_to_check: Vec<&Country> = Vec::new();
     let mut speed_test_results: Vec<SpeedTestResult> = Vec::new();
@@ -324,50 +996,63 @@ pub fn test_speed_by_countries(
     let mut explored_countries: HashSet<&'static str> = HashSet::new();
     let mut jumps_number: usize = 0;
 
-    let country = match Country::from_str(&config.entry_country) {
+    let country = match Country::from_str(&options.entry_country) {
         Some(country) => country,
         None => {
-            tx_progress
-                .send("UNKNOWN entry_country, falling back to US".to_string())
-                .unwrap();
+            tx_events
+                .send(Event::Progress(ProgressEvent::UnknownEntryCountry {
+                    fallback: "US",
+                }))
+                .ok();
             Country::from_str("US").unwrap()
         }
     };
     countries_to_check.push(country);
 
-    let mut latest_top_speeds: Vec<f64> = Vec::with_capacity(config.max_jumps);
-    let mut latest_top_connection_times: Vec<Duration> = Vec::with_capacity(config.max_jumps);
+    let mut latest_top_speeds: Vec<f64> = Vec::with_capacity(options.max_jumps);
+    let mut latest_top_connection_times: Vec<Duration> = Vec::with_capacity(options.max_jumps);
 
     while !countries_to_check.is_empty() {
-        tx_progress
-            .send(format!("JUMP #{}", jumps_number + 1))
-            .unwrap();
+        if over_budget() {
+            break;
+        }
+        // nobody listens anymore, see `rank`
+        if tx_events
+            .send(Event::Progress(ProgressEvent::JumpStarted(
+                jumps_number + 1,
+            )))
+            .is_err()
+        {
+            return;
+        }
         let current_countries = countries_to_check;
         countries_to_check = Vec::new();
 
         let mirrors_to_check: Vec<Mirror> = current_countries
             .into_iter()
-            .map(|country| {
+            .flat_map(|country| {
                 let explored = explored_countries.contains(country.code);
                 let visited = visited_countries.contains(country.code);
                 if !explored {
-                    tx_progress
-                        .send(format!("EXPLORING {}", country.code))
-                        .unwrap();
+                    tx_events
+                        .send(Event::Progress(ProgressEvent::CountryExplored(
+                            country.code,
+                        )))
+                        .ok();
                     explored_countries.insert(country.code);
                 }
                 let mirrors_of_country = if visited {
                     Vec::new()
                 } else {
-                    tx_progress
-                        .send(format!("VISITED {}", country.code))
-                        .unwrap();
+                    tx_events
+                        .send(Event::Progress(ProgressEvent::CountryVisited(country.code)))
+                        .ok();
                     visited_countries.insert(country.code);
                     map.get(country)
                         .map(|mirrors| {
                             mirrors
                                 .iter()
-                                .take(config.country_test_mirrors_per_country)
+                                .take(options.country_test_mirrors_per_country)
                                 .cloned()
                         })
                         .into_iter()
@@ -379,7 +1064,7 @@ pub fn test_speed_by_countries(
                     country
                         .links
                         .iter()
-                        .filter(|link| !config.is_country_excluded(link.code))
+                        .filter(|link| !options.is_country_excluded(link.code))
                         .collect()
                 } else {
                     Vec::new()
@@ -408,111 +1093,115 @@ pub fn test_speed_by_countries(
                                     .map(|mirrors| {
                                         mirrors
                                             .iter()
-                                            .take(config.country_test_mirrors_per_country)
+                                            .take(options.country_test_mirrors_per_country)
                                             .cloned()
                                     })
                                     .filter(|mirrors| mirrors.len() > 0);
                                 if mirrors.is_some() {
-                                    tx_progress
-                                        .send(format!(
-                                            "    + NEIGHBOR {} (by {:?})",
-                                            link.code, strategy
-                                        ))
-                                        .unwrap();
+                                    tx_events
+                                        .send(Event::Progress(ProgressEvent::NeighborSelected {
+                                            country: neighbor.code,
+                                            strategy: *strategy,
+                                        }))
+                                        .ok();
                                     return mirrors;
                                 }
                             }
                             None
                         })
-                        .take(config.country_neighbors_per_country)
+                        .take(options.country_neighbors_per_country)
                         .flatten();
                     for mirror in mirrors {
                         mirrors_of_neighbors.push(mirror);
                     }
                 }
-                mirrors_of_country
-                    .into_iter()
-                    .chain(mirrors_of_neighbors.into_iter())
+                mirrors_of_country.into_iter().chain(mirrors_of_neighbors)
             })
-            .flatten()
             .collect();
 
         tested_urls.extend(mirrors_to_check.iter().map(|m| m.url_to_test.to_string()));
 
         let mut results = test_mirrors(
             mirrors_to_check,
-            Arc::clone(&config),
-            &runtime,
+            TestPhase::Jump(jumps_number + 1),
+            Arc::clone(&options),
+            runtime,
             Arc::clone(&semaphore),
-            mpsc::Sender::clone(&tx_progress),
+            Arc::clone(&budget),
+            mpsc::Sender::clone(&tx_events),
         );
         jumps_number += 1;
 
         if results.is_empty() {
-            tx_progress.send("BLANK ITERATION".to_string()).unwrap();
+            tx_events
+                .send(Event::Progress(ProgressEvent::BlankIteration))
+                .ok();
             break;
         }
 
-        results.sort_unstable_by(|a, b| a.connection_time.partial_cmp(&b.connection_time).unwrap());
+        results.sort_unstable_by_key(|result| result.connection_time_less_dns());
         for (index, result) in results.iter().enumerate() {
             let top_country = result.item.country.unwrap();
             let is_neighbor = !explored_countries.contains(top_country.code);
             if is_neighbor {
-                tx_progress
-                    .send(format!(
-                        "    TOP NEIGHBOR - CONNECTION TIME: {} - {}",
-                        top_country.code,
-                        result.fmt_connection_time(),
-                    ))
-                    .unwrap();
+                tx_events
+                    .send(Event::Progress(ProgressEvent::TopConnectionTime {
+                        country: top_country.code,
+                        connection_time: result.connection_time_less_dns(),
+                        neighbor: true,
+                    }))
+                    .ok();
                 countries_to_check.push(top_country);
-                latest_top_connection_times.push(result.connection_time);
+                latest_top_connection_times.push(result.connection_time_less_dns());
                 break;
             } else if index == 0 {
-                tx_progress
-                    .send(format!(
-                        "    TOP CONNECTION TIME: {} - {}",
-                        top_country.code,
-                        result.fmt_connection_time(),
-                    ))
-                    .unwrap();
-                latest_top_connection_times.push(result.connection_time);
+                tx_events
+                    .send(Event::Progress(ProgressEvent::TopConnectionTime {
+                        country: top_country.code,
+                        connection_time: result.connection_time_less_dns(),
+                        neighbor: false,
+                    }))
+                    .ok();
+                latest_top_connection_times.push(result.connection_time_less_dns());
             }
         }
 
-        results.sort_unstable_by(|a, b| b.speed.partial_cmp(&a.speed).unwrap());
-        for (index, result) in results.iter().enumerate() {
-            let top_country = result.item.country.unwrap();
-            let is_neighbor = !explored_countries.contains(top_country.code);
-            if is_neighbor {
-                tx_progress
-                    .send(format!(
-                        "    TOP NEIGHBOR - SPEED: {} - {}",
-                        top_country.code,
-                        result.fmt_speed(),
-                    ))
-                    .unwrap();
-                countries_to_check.push(top_country);
-                latest_top_speeds.push(result.speed);
-                break;
-            } else if index == 0 {
-                tx_progress
-                    .send(format!(
-                        "    TOP SPEED: {} - {}",
-                        top_country.code,
-                        result.fmt_speed(),
-                    ))
-                    .unwrap();
-                latest_top_speeds.push(result.speed);
+        results.sort_unstable_by(|a, b| b.rank_score().partial_cmp(&a.rank_score()).unwrap());
+        // when ranking by latency, neighbors are picked by connection time only
+        if options.rank_by == RankBy::Speed {
+            for (index, result) in results.iter().enumerate() {
+                let top_country = result.item.country.unwrap();
+                let is_neighbor = !explored_countries.contains(top_country.code);
+                if is_neighbor {
+                    tx_events
+                        .send(Event::Progress(ProgressEvent::TopSpeed {
+                            country: top_country.code,
+                            speed: result.speed,
+                            neighbor: true,
+                        }))
+                        .ok();
+                    countries_to_check.push(top_country);
+                    latest_top_speeds.push(result.speed);
+                    break;
+                } else if index == 0 {
+                    tx_events
+                        .send(Event::Progress(ProgressEvent::TopSpeed {
+                            country: top_country.code,
+                            speed: result.speed,
+                            neighbor: false,
+                        }))
+                        .ok();
+                    latest_top_speeds.push(result.speed);
+                }
             }
         }
 
         speed_test_results = speed_test_results
             .into_iter()
-            .merge_by(results.into_iter(), |a, b| a.speed > b.speed)
+            .merge_by(results, |a, b| a.rank_score() > b.rank_score())
             .collect();
 
-        if jumps_number == config.max_jumps {
+        if jumps_number == options.max_jumps {
             break;
         }
 
@@ -534,9 +1223,11 @@ pub fn test_speed_by_countries(
         if connection_times_state.len() == connection_time_checks
             && connection_times_state.iter().all(|b| *b)
         {
-            tx_progress
-                .send("CONNECTION TIMES ARE GETTING WORSE, STOPPING".to_string())
-                .unwrap();
+            tx_events
+                .send(Event::Progress(ProgressEvent::EarlyStop {
+                    reason: EarlyStopReason::ConnectionTimesGettingWorse,
+                }))
+                .ok();
             break;
         }
 
@@ -545,31 +1236,35 @@ pub fn test_speed_by_countries(
             .iter()
             .rev()
             .zip(latest_top_speeds.iter().rev().skip(1))
-            .map(|(next, prev)| *next as f64 * speed_check_sensitivity < *prev as f64)
+            .map(|(next, prev)| *next * speed_check_sensitivity < *prev)
             .take(speed_checks)
             .collect();
         if speeds_state.len() == speed_checks && speeds_state.iter().all(|b| *b) {
-            tx_progress
-                .send("SPEEDS ARE GETTING WORSE, STOPPING".to_string())
-                .unwrap();
+            tx_events
+                .send(Event::Progress(ProgressEvent::EarlyStop {
+                    reason: EarlyStopReason::SpeedsGettingWorse,
+                }))
+                .ok();
             break;
         }
 
-        tx_progress.send(format!("")).unwrap();
+        tx_events
+            .send(Event::Progress(ProgressEvent::JumpFinished))
+            .ok();
     }
 
-    if speed_test_results.len()
-        < ((config.max_jumps
-            * config.country_test_mirrors_per_country
-            * config.country_neighbors_per_country) as f64
-            * 0.7) as usize
+    if !over_budget()
+        && speed_test_results.len()
+            < ((options.max_jumps
+                * options.country_test_mirrors_per_country
+                * options.country_neighbors_per_country) as f64
+                * 0.7) as usize
     {
-        tx_progress
-            .send(format!(
-                "COUNTRY JUMPING YIELDED TOO FEW MIRRORS ({}), ADDING OTHERS TO UNLABELED",
-                speed_test_results.len()
-            ))
-            .unwrap();
+        tx_events
+            .send(Event::Progress(ProgressEvent::TooFewJumpResults(
+                speed_test_results.len(),
+            )))
+            .ok();
         for mirrors in map.into_values() {
             let mut untested_mirrors: Vec<Mirror> = mirrors
                 .into_iter()
@@ -579,63 +1274,167 @@ pub fn test_speed_by_countries(
         }
     }
 
-    if !unlabeled_mirrors.is_empty() {
-        tx_progress.send("\n".to_string()).unwrap();
-        tx_progress
-            .send("TESTING UNLABELED MIRRORS".to_string())
-            .unwrap();
+    if !unlabeled_mirrors.is_empty() && !over_budget() {
+        tx_events
+            .send(Event::Progress(ProgressEvent::UnlabeledStarted))
+            .ok();
 
         let semaphore_for_unlabeled = Arc::new(tokio::sync::Semaphore::new(
-            config.concurrency_for_unlabeled,
+            options.concurrency_for_unlabeled,
         ));
         let mut results = test_mirrors(
             unlabeled_mirrors,
-            Arc::clone(&config),
-            &runtime,
+            TestPhase::Unlabeled,
+            Arc::clone(&options),
+            runtime,
             Arc::clone(&semaphore_for_unlabeled),
-            mpsc::Sender::clone(&tx_progress),
+            Arc::clone(&budget),
+            mpsc::Sender::clone(&tx_events),
         );
 
-        results.sort_unstable_by(|a, b| b.speed.partial_cmp(&a.speed).unwrap());
+        results.sort_unstable_by(|a, b| b.rank_score().partial_cmp(&a.rank_score()).unwrap());
         speed_test_results = speed_test_results
             .into_iter()
-            .merge_by(results.into_iter(), |a, b| a.speed > b.speed)
+            .merge_by(results, |a, b| a.rank_score() > b.rank_score())
             .collect();
     }
-
-    tx_progress.send("\n".to_string()).unwrap();
     if speed_test_results.is_empty() {
-        tx_progress
-            .send("NO RESULTS TO RE-TEST".to_string())
-            .unwrap();
+        tx_events
+            .send(Event::Progress(ProgressEvent::NoResultsToRetest))
+            .ok();
+        tx_events.send(Event::Finished(Vec::new())).ok();
+        return;
+    } else if over_budget() {
+        // the best-known ranking, as is
+        tx_events.send(Event::Finished(speed_test_results)).ok();
+        return;
+    } else if tx_events
+        .send(Event::Progress(ProgressEvent::RetestStarted))
+        .is_err()
+    {
         return;
-    } else {
-        tx_progress
-            .send("RE-TESTING TOP MIRRORS".to_string())
-            .unwrap();
     }
 
     let semaphore = Arc::new(tokio::sync::Semaphore::new(1));
     let mut other_results = speed_test_results.split_off(cmp::min(
-        config.top_mirrors_number_to_retest,
+        options.top_mirrors_number_to_retest,
         speed_test_results.len(),
     ));
-    let top_mirrors = speed_test_results.into_iter().map(|result| result.item);
-
-    let mut top_mirror_results = test_mirrors(
-        top_mirrors,
-        config,
-        &runtime,
-        Arc::clone(&semaphore),
-        mpsc::Sender::clone(&tx_progress),
-    );
-    top_mirror_results.sort_by(|a, b| b.speed.partial_cmp(&a.speed).unwrap());
+    let top_mirrors: Vec<Mirror> = speed_test_results
+        .iter()
+        .map(|result| result.item.clone())
+        .collect();
+
+    // rounds of samples, so that a hiccup of the connection doesn't hit a
+    // single mirror only
+    let samples_number = match options.rank_by {
+        RankBy::Speed => options.samples.max(1),
+        RankBy::Latency => 1,
+    };
+    let mut samples: Vec<Vec<Option<SpeedTestOutcome>>> = vec![Vec::new(); top_mirrors.len()];
+    for _ in 0..samples_number {
+        if over_budget() {
+            break;
+        }
+        let round = test_mirrors_in_order(
+            top_mirrors.iter().cloned(),
+            TestPhase::Retest,
+            Arc::clone(&options),
+            runtime,
+            Arc::clone(&semaphore),
+            Arc::clone(&budget),
+            mpsc::Sender::clone(&tx_events),
+        );
+        for (mirror_samples, sample) in samples.iter_mut().zip(round) {
+            mirror_samples.push(sample);
+        }
+    }
+    let mut top_mirror_results: Vec<_> = samples
+        .into_iter()
+        .zip(speed_test_results)
+        .filter_map(|(samples, previous)| {
+            // mirrors whose re-tests were all skipped keep their first result
+            let skipped = samples.iter().all(|sample| {
+                matches!(sample, Some(Err(failure)) if matches!(failure.error, SpeedTestError::OverBudget(_)))
+            });
+            if skipped {
+                return Some(previous);
+            }
+            SpeedTestResult::from_samples(
+                samples
+                    .into_iter()
+                    .map(|sample| sample.and_then(Result::ok))
+                    .collect(),
+            )
+        })
+        .collect();
+    top_mirror_results.sort_by(|a, b| b.rank_score().partial_cmp(&a.rank_score()).unwrap());
     top_mirror_results.append(&mut other_results);
-    tx_results.send(top_mirror_results).unwrap();
+    tx_events.send(Event::Finished(top_mirror_results)).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use url::Url;
+
+    fn sample(speed: usize) -> Option<SpeedTestResult> {
+        let url = Url::parse("https://mirror.example.org/").unwrap();
+        Some(SpeedTestResult::new(
+            Mirror {
+                url_to_test: url.clone(),
+                url,
+                country: None,
+                meta: Default::default(),
+            },
+            speed,
+            Duration::from_secs(1),
+            Duration::from_millis(10),
+            TestPhase::Retest,
+        ))
+    }
 
-    // Drop channels before shutting down the runtime. Without this runtime drop can block
-    // indefinitely (e.g. reqwest connection-pool cleanup)
-    drop(tx_progress);
-    drop(tx_results);
-    runtime.shutdown_timeout(Duration::from_secs(1));
+    #[test]
+    fn flaky_mirror_ranks_below_steady_one() {
+        let steady =
+            SpeedTestResult::from_samples(vec![sample(900), sample(1000), sample(1100)]).unwrap();
+        let flaky = SpeedTestResult::from_samples(vec![sample(3000), None, sample(2000)]).unwrap();
+
+        let stats = steady.stats.as_ref().unwrap();
+        assert_eq!((stats.median, stats.min, stats.max), (1000., 900., 1100.));
+        // mean 1000, standard error 100/sqrt(3), t = 4.303
+        assert!((stats.ci_low - (1000. - 4.303 * 100. / 3f64.sqrt())).abs() < 1e-6);
+        assert_eq!(flaky.speed, 2000.);
+        assert_eq!(flaky.stats.as_ref().unwrap().failures, 1);
+        assert!(flaky.speed > steady.speed);
+        assert!(flaky.rank_speed() < steady.rank_speed());
+    }
+
+    #[test]
+    fn single_sample_has_no_stats() {
+        let result = SpeedTestResult::from_samples(vec![sample(1000)]).unwrap();
+        assert!(result.stats.is_none());
+        assert_eq!(result.rank_speed(), 1000.);
+        assert!(SpeedTestResult::from_samples(vec![None, None]).is_none());
+    }
+
+    #[test]
+    fn phases_take_medians_of_probes_they_happened_in() {
+        let ms = |ms| Some(Duration::from_millis(ms));
+        let probe = |dns, tcp, ttfb| Timings {
+            dns,
+            tcp: ms(tcp),
+            tls: None,
+            ttfb: ms(ttfb),
+        };
+        let timings = median_timings(&[
+            probe(ms(40), 30, 100),
+            probe(None, 10, 300),
+            probe(None, 20, 200),
+        ]);
+        assert_eq!(timings.dns, ms(40));
+        assert_eq!(timings.tcp, ms(20));
+        assert_eq!(timings.tls, None);
+        assert_eq!(timings.ttfb, ms(200));
+    }
 }
@@ -0,0 +1,47 @@
+use clap::Args;
+
+#[derive(Debug, Clone, Args)]
+pub struct AlpineTarget {
+    /// Fetch list of mirrors timeout in milliseconds
+    #[arg(
+        env = "RATE_MIRRORS_FETCH_MIRRORS_TIMEOUT",
+        long,
+        default_value = "15000"
+    )]
+    pub fetch_mirrors_timeout: u64,
+
+    /// Either url or path to Alpine mirrors.yaml
+    #[arg(
+        env = "RATE_MIRRORS_MIRROR_LIST_FILE",
+        long,
+        default_value = "https://mirrors.alpinelinux.org/mirrors.yaml",
+        verbatim_doc_comment
+    )]
+    pub mirror_list_file: String,
+
+    /// Branch to output and test, e.g. edge, v3.22 or latest-stable
+    #[arg(env = "RATE_MIRRORS_BRANCH", long, default_value = "latest-stable")]
+    pub branch: String,
+
+    /// Comma-separated repositories to output
+    #[arg(env = "RATE_MIRRORS_REPOS", long, default_value = "main,community")]
+    pub repos: String,
+
+    /// Architecture to substitute for $arch in --path-to-test
+    #[arg(env = "RATE_MIRRORS_ARCH", long, default_value = "x86_64")]
+    pub arch: String,
+
+    /// Path to be joined to a mirror url and used for speed testing,
+    ///   $branch and $arch are substituted
+    #[arg(
+        env = "RATE_MIRRORS_PATH_TO_TEST",
+        long,
+        default_value = "$branch/main/$arch/APKINDEX.tar.gz",
+        verbatim_doc_comment
+    )]
+    pub path_to_test: String,
+
+    /// comment prefix to use when outputting
+    #[arg(env = "RATE_MIRRORS_COMMENT_PREFIX", long, default_value = "# ")]
+    pub comment_prefix: String,
+}
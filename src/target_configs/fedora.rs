@@ -0,0 +1,53 @@
+use crate::targets::fedora::FedoraFormat;
+use clap::Args;
+
+#[derive(Debug, Clone, Args)]
+pub struct FedoraTarget {
+    /// Fetch list of mirrors timeout in milliseconds
+    #[arg(
+        env = "RATE_MIRRORS_FETCH_MIRRORS_TIMEOUT",
+        long,
+        default_value = "15000"
+    )]
+    pub fetch_mirrors_timeout: u64,
+
+    /// Either url or path to a MirrorManager metalink or mirrorlist,
+    ///   $releasever and $basearch are substituted; e.g. for Rocky Linux:
+    ///   https://mirrors.rockylinux.org/mirrorlist?arch=$basearch&repo=BaseOS-$releasever
+    #[arg(
+        env = "RATE_MIRRORS_MIRROR_LIST_FILE",
+        long,
+        default_value = "https://mirrors.fedoraproject.org/metalink?repo=fedora-$releasever&arch=$basearch",
+        verbatim_doc_comment
+    )]
+    pub mirror_list_file: String,
+
+    /// Release to substitute for $releasever
+    #[arg(env = "RATE_MIRRORS_RELEASEVER", long, default_value = "42")]
+    pub releasever: String,
+
+    /// Architecture to substitute for $basearch
+    #[arg(env = "RATE_MIRRORS_BASEARCH", long, default_value = "x86_64")]
+    pub basearch: String,
+
+    /// Path to be joined to a mirror url and used for speed testing;
+    ///   by default the primary metadata file referenced by repomd.xml
+    ///   of the first reachable mirror
+    #[arg(env = "RATE_MIRRORS_PATH_TO_TEST", long, verbatim_doc_comment)]
+    pub path_to_test: Option<String>,
+
+    /// Output format, one of:
+    ///   baseurl - a baseurl= option for a .repo file
+    ///   metalink - a local metalink file to point metalink= to
+    #[arg(
+        env = "RATE_MIRRORS_OUTPUT_FORMAT",
+        long,
+        default_value = "baseurl",
+        verbatim_doc_comment
+    )]
+    pub output_format: FedoraFormat,
+
+    /// comment prefix to use when outputting
+    #[arg(env = "RATE_MIRRORS_COMMENT_PREFIX", long, default_value = "# ")]
+    pub comment_prefix: String,
+}
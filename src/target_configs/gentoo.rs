@@ -0,0 +1,36 @@
+use clap::Args;
+
+#[derive(Debug, Clone, Args)]
+pub struct GentooTarget {
+    /// Fetch list of mirrors timeout in milliseconds
+    #[arg(
+        env = "RATE_MIRRORS_FETCH_MIRRORS_TIMEOUT",
+        long,
+        default_value = "15000"
+    )]
+    pub fetch_mirrors_timeout: u64,
+
+    /// Either url or path to Gentoo distfiles mirrors3.xml
+    #[arg(
+        env = "RATE_MIRRORS_MIRROR_LIST_FILE",
+        long,
+        default_value = "https://api.gentoo.org/mirrors/distfiles.xml",
+        verbatim_doc_comment
+    )]
+    pub mirror_list_file: String,
+
+    /// Path to be joined to a mirror url and used for speed testing
+    ///   the file should be big enough to allow for testing high
+    ///   speed connections
+    #[arg(
+        env = "RATE_MIRRORS_PATH_TO_TEST",
+        long,
+        default_value = "snapshots/portage-latest.tar.xz",
+        verbatim_doc_comment
+    )]
+    pub path_to_test: String,
+
+    /// comment prefix to use when outputting
+    #[arg(env = "RATE_MIRRORS_COMMENT_PREFIX", long, default_value = "# ")]
+    pub comment_prefix: String,
+}
@@ -0,0 +1,54 @@
+use clap::Args;
+
+#[derive(Debug, Clone, Args)]
+pub struct OpenSUSETarget {
+    /// Fetch list of mirrors timeout in milliseconds
+    #[arg(
+        env = "RATE_MIRRORS_FETCH_MIRRORS_TIMEOUT",
+        long,
+        default_value = "15000"
+    )]
+    pub fetch_mirrors_timeout: u64,
+
+    /// Either url or path to MirrorCache server list (JSON) or to a
+    ///   MirrorCache/MirrorBrain mirror list page (HTML)
+    #[arg(
+        env = "RATE_MIRRORS_MIRROR_LIST_FILE",
+        long,
+        default_value = "https://mirrorcache.opensuse.org/rest/server",
+        verbatim_doc_comment
+    )]
+    pub mirror_list_file: String,
+
+    /// Repository path relative to the openSUSE root of a mirror,
+    ///   e.g. distribution/leap/15.6/repo/oss/
+    #[arg(
+        env = "RATE_MIRRORS_REPO_PATH",
+        long,
+        default_value = "tumbleweed/repo/oss/",
+        verbatim_doc_comment
+    )]
+    pub repo_path: String,
+
+    /// Path to be joined to the repository url and used for speed testing;
+    ///   by default the primary metadata file referenced by repomd.xml
+    ///   of the first reachable mirror
+    #[arg(env = "RATE_MIRRORS_PATH_TO_TEST", long, verbatim_doc_comment)]
+    pub path_to_test: Option<String>,
+
+    /// Alias (section name) of the output .repo stanza
+    #[arg(env = "RATE_MIRRORS_REPO_ALIAS", long, default_value = "repo-oss")]
+    pub repo_alias: String,
+
+    /// Name of the output .repo stanza
+    #[arg(
+        env = "RATE_MIRRORS_REPO_NAME",
+        long,
+        default_value = "openSUSE-Tumbleweed-Oss"
+    )]
+    pub repo_name: String,
+
+    /// comment prefix to use when outputting
+    #[arg(env = "RATE_MIRRORS_COMMENT_PREFIX", long, default_value = "# ")]
+    pub comment_prefix: String,
+}
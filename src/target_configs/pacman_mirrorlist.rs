@@ -0,0 +1,44 @@
+use clap::Args;
+
+#[derive(Debug, Clone, Args)]
+pub struct PacmanMirrorlistTarget {
+    /// Either url or path to a pacman mirrorlist file
+    #[arg(
+        env = "RATE_MIRRORS_MIRROR_LIST_FILE",
+        long,
+        default_value = "/etc/pacman.d/mirrorlist"
+    )]
+    pub mirror_list_file: String,
+
+    /// Fetch list of mirrors timeout in milliseconds
+    #[arg(
+        env = "RATE_MIRRORS_FETCH_MIRRORS_TIMEOUT",
+        long,
+        default_value = "15000"
+    )]
+    pub fetch_mirrors_timeout: u64,
+
+    /// Value of $repo in server templates used for speed testing
+    #[arg(env = "RATE_MIRRORS_REPO", long, default_value = "extra")]
+    pub repo: String,
+
+    /// Value of $arch in server templates used for speed testing
+    #[arg(env = "RATE_MIRRORS_ARCH", long, default_value = "x86_64")]
+    pub arch: String,
+
+    /// Path to be joined to a server template with $repo and $arch
+    ///   substituted and used for speed testing; may contain $repo and $arch
+    ///   too, the file should be big enough to allow for testing high
+    ///   speed connections
+    #[arg(
+        env = "RATE_MIRRORS_PATH_TO_TEST",
+        long,
+        default_value = "$repo.files",
+        verbatim_doc_comment
+    )]
+    pub path_to_test: String,
+
+    /// comment prefix to use when outputting
+    #[arg(env = "RATE_MIRRORS_COMMENT_PREFIX", long, default_value = "# ")]
+    pub comment_prefix: String,
+}
@@ -0,0 +1,73 @@
+use crate::targets::apt::AptFormat;
+use clap::Args;
+
+#[derive(Debug, Clone, Args)]
+pub struct UbuntuTarget {
+    /// Fetch list of mirrors timeout in milliseconds
+    #[arg(
+        env = "RATE_MIRRORS_FETCH_MIRRORS_TIMEOUT",
+        long,
+        default_value = "15000"
+    )]
+    pub fetch_mirrors_timeout: u64,
+
+    /// Either url or path to Ubuntu mirror list: Launchpad archive mirrors
+    ///   page or lines of urls, optionally tab-separated with countries
+    #[arg(
+        env = "RATE_MIRRORS_MIRROR_LIST_FILE",
+        long,
+        default_value = "https://launchpad.net/ubuntu/+archivemirrors",
+        verbatim_doc_comment
+    )]
+    pub mirror_list_file: String,
+
+    /// Suite to output and to substitute for $suite in --path-to-test
+    #[arg(env = "RATE_MIRRORS_SUITE", long, default_value = "noble")]
+    pub suite: String,
+
+    /// Space-separated components to output
+    #[arg(
+        env = "RATE_MIRRORS_COMPONENTS",
+        long,
+        default_value = "main restricted universe multiverse"
+    )]
+    pub components: String,
+
+    /// Architecture to substitute for $arch in --path-to-test
+    #[arg(env = "RATE_MIRRORS_ARCH", long, default_value = "amd64")]
+    pub arch: String,
+
+    /// Path to be joined to a mirror url and used for speed testing
+    ///   the file should be big enough to allow for testing high
+    ///   speed connections
+    #[arg(
+        env = "RATE_MIRRORS_PATH_TO_TEST",
+        long,
+        default_value = "dists/$suite/Contents-$arch.gz",
+        verbatim_doc_comment
+    )]
+    pub path_to_test: String,
+
+    /// Output format, one of:
+    ///   sources-list - deb lines for /etc/apt/sources.list
+    ///   deb822 - a stanza for /etc/apt/sources.list.d/ubuntu.sources
+    #[arg(
+        env = "RATE_MIRRORS_OUTPUT_FORMAT",
+        long,
+        default_value = "sources-list",
+        verbatim_doc_comment
+    )]
+    pub output_format: AptFormat,
+
+    /// Signed-By keyring of deb822 output, empty to omit it
+    #[arg(
+        env = "RATE_MIRRORS_SIGNED_BY",
+        long,
+        default_value = "/usr/share/keyrings/ubuntu-archive-keyring.gpg"
+    )]
+    pub signed_by: String,
+
+    /// comment prefix to use when outputting
+    #[arg(env = "RATE_MIRRORS_COMMENT_PREFIX", long, default_value = "# ")]
+    pub comment_prefix: String,
+}
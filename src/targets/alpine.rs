@@ -0,0 +1,190 @@
+use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
+use crate::countries::Country;
+use crate::mirror::Mirror;
+use crate::progress::ProgressEvent;
+use crate::target_configs::alpine::AlpineTarget;
+use std::fmt::Display;
+use std::sync::mpsc;
+use url::Url;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct AlpineMirror {
+    pub location: Option<String>,
+    pub urls: Vec<String>,
+}
+
+fn unquote(value: &str) -> &str {
+    value.trim().trim_matches(|c| c == '"' || c == '\'')
+}
+
+/// Reads the fixed layout of mirrors.yaml: a sequence of mappings with
+/// `name`, `location` and a `urls` sequence, among other scalar keys.
+pub fn parse_mirrors_yaml(text: &str) -> Vec<AlpineMirror> {
+    let mut mirrors = Vec::new();
+    let mut current: Option<AlpineMirror> = None;
+    let mut in_urls = false;
+
+    for line in text.lines() {
+        let content = line.split(" #").next().unwrap_or_default().trim_end();
+        if content.trim().is_empty() || content.trim_start().starts_with('#') || content == "---" {
+            continue;
+        }
+        let indent = content.len() - content.trim_start().len();
+        let trimmed = content.trim_start();
+
+        if indent == 0 && trimmed.starts_with('-') {
+            mirrors.extend(current.take());
+            current = Some(AlpineMirror::default());
+            in_urls = false;
+        }
+        let Some(mirror) = current.as_mut() else {
+            continue;
+        };
+        let item = trimmed.trim_start_matches('-').trim_start();
+        if in_urls && trimmed.starts_with('-') && indent > 0 {
+            mirror.urls.push(unquote(item).to_string());
+            continue;
+        }
+        in_urls = false;
+        if let Some((key, value)) = item.split_once(':') {
+            match key.trim() {
+                "location" => mirror.location = Some(unquote(value).to_string()),
+                "urls" => in_urls = true,
+                _ => {}
+            }
+        }
+    }
+    mirrors.extend(current);
+    mirrors
+}
+
+/// Locations are country names, sometimes preceded by a city or region,
+/// e.g. `Germany`, `Tokyo, Japan`
+fn location_country(location: &str) -> Option<&'static Country> {
+    Country::from_str(location.trim()).or_else(|| {
+        location
+            .rsplit(',')
+            .map(|part| part.split('(').next().unwrap_or_default().trim())
+            .find_map(Country::from_str)
+    })
+}
+
+impl AlpineTarget {
+    fn repos(&self) -> impl Iterator<Item = &str> {
+        self.repos
+            .split(',')
+            .map(str::trim)
+            .filter(|r| !r.is_empty())
+    }
+}
+
+impl LogFormatter for AlpineTarget {
+    fn format_comment(&self, message: impl Display) -> String {
+        format!("{}{}", self.comment_prefix, message)
+    }
+
+    fn format_mirror(&self, mirror: &Mirror) -> String {
+        self.repos()
+            .map(|repo| format!("{}{}/{}", mirror.url, self.branch, repo))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Repositories of the fastest mirror; the runners-up are listed as
+    /// commented out alternatives
+    fn format_mirrors(&self, mirrors: &[Mirror]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (index, mirror) in mirrors.iter().enumerate() {
+            for repo in self.repos() {
+                let prefix = if index == 0 { "" } else { "#" };
+                lines.push(format!("{}{}{}/{}", prefix, mirror.url, self.branch, repo));
+            }
+        }
+        lines
+    }
+}
+
+impl FetchMirrors for AlpineTarget {
+    fn fetch_mirrors(
+        &self,
+        _tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
+        let output = fetch_text_or_file(&self.mirror_list_file, self.fetch_mirrors_timeout)?;
+        let path_to_test = self
+            .path_to_test
+            .replace("$branch", &self.branch)
+            .replace("$arch", &self.arch);
+
+        let mut mirrors = Vec::new();
+        for mirror in parse_mirrors_yaml(&output) {
+            let country = mirror.location.as_deref().and_then(location_country);
+            for url in mirror.urls.iter() {
+                let url = if url.ends_with('/') {
+                    url.clone()
+                } else {
+                    format!("{}/", url)
+                };
+                if let Ok(url) = Url::parse(&url) {
+                    mirrors.push(Mirror {
+                        country,
+                        url_to_test: url
+                            .join(&path_to_test)
+                            .expect("failed to join path_to_test"),
+                        url,
+                        meta: Default::default(),
+                    });
+                }
+            }
+        }
+        Ok(mirrors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mirrors_yaml() {
+        let raw = r#"---
+- name: dl-cdn.alpinelinux.org
+  location: Fastly
+  bandwidth: 10 Gbps
+  urls:
+    - http://dl-cdn.alpinelinux.org/alpine/
+    - https://dl-cdn.alpinelinux.org/alpine/
+- name: mirror.example.jp
+  location: "Tokyo, Japan"
+  urls:
+  - https://mirror.example.jp/alpine
+  owner: Example # comment
+- name: no-urls.example.org
+  location: Germany
+"#;
+        let mirrors = parse_mirrors_yaml(raw);
+
+        assert_eq!(
+            mirrors,
+            [
+                AlpineMirror {
+                    location: Some("Fastly".to_string()),
+                    urls: vec![
+                        "http://dl-cdn.alpinelinux.org/alpine/".to_string(),
+                        "https://dl-cdn.alpinelinux.org/alpine/".to_string()
+                    ],
+                },
+                AlpineMirror {
+                    location: Some("Tokyo, Japan".to_string()),
+                    urls: vec!["https://mirror.example.jp/alpine".to_string()],
+                },
+                AlpineMirror {
+                    location: Some("Germany".to_string()),
+                    urls: vec![],
+                },
+            ]
+        );
+        assert!(location_country("Fastly").is_none());
+        assert_eq!(location_country("Tokyo, Japan").map(|c| c.code), Some("JP"));
+        assert_eq!(location_country("Germany").map(|c| c.code), Some("DE"));
+    }
+}
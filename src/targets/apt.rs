@@ -0,0 +1,114 @@
+use crate::mirror::Mirror;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AptFormat {
+    /// one-line `deb URL SUITE COMPONENTS` entries
+    SourcesList,
+    /// a single `.sources` stanza listing all mirrors
+    Deb822,
+}
+
+impl FromStr for AptFormat {
+    type Err = &'static str;
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "sources-list" => Ok(AptFormat::SourcesList),
+            "deb822" => Ok(AptFormat::Deb822),
+            _ => Err("could not parse apt output format"),
+        }
+    }
+}
+
+/// What apt should fetch from the ranked mirrors
+pub struct AptSource<'a> {
+    pub format: AptFormat,
+    pub suite: &'a str,
+    pub components: &'a str,
+    pub signed_by: &'a str,
+}
+
+impl AptSource<'_> {
+    pub fn format_line(&self, mirror: &Mirror) -> String {
+        format!("deb {} {} {}", mirror.url, self.suite, self.components)
+    }
+
+    pub fn format_mirrors(&self, mirrors: &[Mirror]) -> Vec<String> {
+        match self.format {
+            AptFormat::SourcesList => mirrors.iter().map(|m| self.format_line(m)).collect(),
+            AptFormat::Deb822 => {
+                let mut lines = vec![
+                    "Types: deb".to_string(),
+                    format!(
+                        "URIs: {}",
+                        mirrors
+                            .iter()
+                            .map(|m| m.url.as_str())
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    ),
+                    format!("Suites: {}", self.suite),
+                    format!("Components: {}", self.components),
+                ];
+                if !self.signed_by.is_empty() {
+                    lines.push(format!("Signed-By: {}", self.signed_by));
+                }
+                lines
+            }
+        }
+    }
+}
+
+/// Substitutes `$suite` and `$arch` in a path to test
+pub fn expand_path(path: &str, suite: &str, arch: &str) -> String {
+    path.replace("$suite", suite).replace("$arch", arch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use url::Url;
+
+    fn mirror(url: &str) -> Mirror {
+        let url = Url::parse(url).unwrap();
+        Mirror {
+            country: None,
+            url_to_test: url.clone(),
+            url,
+            meta: Default::default(),
+        }
+    }
+
+    #[test]
+    fn formats_sources_list_and_deb822() {
+        let mirrors = [
+            mirror("http://a.example/debian/"),
+            mirror("https://b.example/debian/"),
+        ];
+        let mut source = AptSource {
+            format: AptFormat::SourcesList,
+            suite: "stable",
+            components: "main contrib",
+            signed_by: "/usr/share/keyrings/debian-archive-keyring.gpg",
+        };
+        assert_eq!(
+            source.format_mirrors(&mirrors),
+            [
+                "deb http://a.example/debian/ stable main contrib",
+                "deb https://b.example/debian/ stable main contrib"
+            ]
+        );
+
+        source.format = AptFormat::Deb822;
+        assert_eq!(
+            source.format_mirrors(&mirrors),
+            [
+                "Types: deb",
+                "URIs: http://a.example/debian/ https://b.example/debian/",
+                "Suites: stable",
+                "Components: main contrib",
+                "Signed-By: /usr/share/keyrings/debian-archive-keyring.gpg"
+            ]
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
 use crate::mirror::Mirror;
+use crate::progress::ProgressEvent;
 use crate::target_configs::arch4edu::Arch4eduTarget;
 use std::fmt::Display;
 use std::sync::mpsc;
@@ -41,7 +42,10 @@ impl LogFormatter for Arch4eduTarget {
 }
 
 impl FetchMirrors for Arch4eduTarget {
-    fn fetch_mirrors(&self, _tx_progress: mpsc::Sender<String>) -> Result<Vec<Mirror>, AppError> {
+    fn fetch_mirrors(
+        &self,
+        _tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
         let output = fetch_text_or_file(&self.mirror_list_file, self.fetch_mirrors_timeout)?;
 
         let mirrors = output
@@ -55,6 +59,7 @@ impl FetchMirrors for Arch4eduTarget {
                     country: None,
                     url,
                     url_to_test,
+                    meta: Default::default(),
                 }
             })
             .collect();
@@ -104,6 +109,7 @@ mod tests {
             url: Url::parse("https://mirror.example/arch4edu/").unwrap(),
             url_to_test: Url::parse("https://mirror.example/arch4edu/x86_64/arch4edu.files")
                 .unwrap(),
+            meta: Default::default(),
         };
 
         assert_eq!(
@@ -126,6 +132,7 @@ mod tests {
             url: Url::parse("https://mirror.example/arch4edu/").unwrap(),
             url_to_test: Url::parse("https://mirror.example/arch4edu/x86_64/arch4edu.files")
                 .unwrap(),
+            meta: Default::default(),
         };
 
         assert_eq!(
@@ -1,5 +1,6 @@
 use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
 use crate::mirror::Mirror;
+use crate::progress::ProgressEvent;
 use crate::target_configs::archarm::ArcharmTarget;
 use std::fmt::Display;
 use std::sync::mpsc;
@@ -22,7 +23,10 @@ impl LogFormatter for ArcharmTarget {
 }
 
 impl FetchMirrors for ArcharmTarget {
-    fn fetch_mirrors(&self, _tx_progress: mpsc::Sender<String>) -> Result<Vec<Mirror>, AppError> {
+    fn fetch_mirrors(
+        &self,
+        _tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
         let output = fetch_text_or_file(&self.mirror_list_file, self.fetch_mirrors_timeout)?;
 
         let urls = output
@@ -46,6 +50,7 @@ impl FetchMirrors for ArcharmTarget {
                     country: None,
                     url,
                     url_to_test,
+                    meta: Default::default(),
                 }
             })
             .collect();
@@ -1,6 +1,7 @@
 use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_json_or_file};
 use crate::countries::Country;
-use crate::mirror::Mirror;
+use crate::mirror::{Mirror, MirrorMeta};
+use crate::progress::ProgressEvent;
 use crate::target_configs::archlinux::{ArchMirrorsSortingStrategy, ArchTarget};
 use rand::prelude::SliceRandom;
 use rand::rng;
@@ -48,12 +49,15 @@ pub(crate) fn selected_mirror_source(target: &ArchTarget) -> &str {
 }
 
 impl FetchMirrors for ArchTarget {
-    fn fetch_mirrors(&self, tx_progress: mpsc::Sender<String>) -> Result<Vec<Mirror>, AppError> {
+    fn fetch_mirrors(
+        &self,
+        tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
         let mirrors_data: ArchMirrorsData =
             fetch_json_or_file(selected_mirror_source(self), self.fetch_mirrors_timeout)?;
 
         tx_progress
-            .send(format!("FETCHED MIRRORS: {}", mirrors_data.urls.len()))
+            .send(ProgressEvent::MirrorsFetched(mirrors_data.urls.len()))
             .unwrap();
 
         let mut mirrors: Vec<_> = mirrors_data
@@ -97,6 +101,10 @@ impl FetchMirrors for ArchTarget {
                             country: Country::from_str(&m.country_code),
                             url,
                             url_to_test,
+                            meta: MirrorMeta {
+                                sync_delay: m.delay,
+                                upstream_score: m.score,
+                            },
                         });
                     }
                 };
@@ -1,5 +1,6 @@
 use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
 use crate::mirror::Mirror;
+use crate::progress::ProgressEvent;
 use crate::target_configs::arcolinux::ArcoLinuxTarget;
 use std::fmt::Display;
 use std::sync::mpsc;
@@ -16,7 +17,10 @@ impl LogFormatter for ArcoLinuxTarget {
 }
 
 impl FetchMirrors for ArcoLinuxTarget {
-    fn fetch_mirrors(&self, _tx_progress: mpsc::Sender<String>) -> Result<Vec<Mirror>, AppError> {
+    fn fetch_mirrors(
+        &self,
+        _tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
         let output = fetch_text_or_file(&self.mirror_list_file, self.fetch_mirrors_timeout)?;
 
         let urls = output
@@ -31,7 +35,7 @@ impl FetchMirrors for ArcoLinuxTarget {
                 let raw_url = url.to_string();
                 const GITLAB_URL_SUFFIX: &str = "$repo/-/raw/main/$arch";
                 const OTHER_URL_SUFFIX: &str = "$repo/$arch";
-                if let Some(_) = raw_url.find(GITLAB_URL_SUFFIX) {
+                if raw_url.find(GITLAB_URL_SUFFIX).is_some() {
                     Url::parse(
                         (raw_url.replace(GITLAB_URL_SUFFIX, "")
                             + self.gitlab_path_to_test.as_str())
@@ -42,6 +46,7 @@ impl FetchMirrors for ArcoLinuxTarget {
                         country: None,
                         url,
                         url_to_test,
+                        meta: Default::default(),
                     })
                     // https://gitlab.com/arcolinux/$repo/-/raw/main/$arch
                     // https://gitlab.com/arcolinux/arcolinux_repo_3party/-/raw/main/x86_64/arcolinux_repo_3party.files
@@ -55,6 +60,7 @@ impl FetchMirrors for ArcoLinuxTarget {
                         country: None,
                         url,
                         url_to_test,
+                        meta: Default::default(),
                     })
                     // https://mirror.aarnet.edu.au/pub/arcolinux/$repo/$arch
                     // https://mirror.aarnet.edu.au/pub/arcolinux/arcolinux_repo_3party/x86_64/arcolinux_repo_3party.files
@@ -1,6 +1,7 @@
 use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
 use crate::countries::Country;
 use crate::mirror::Mirror;
+use crate::progress::ProgressEvent;
 use crate::target_configs::artix::ArtixTarget;
 use std::fmt::Display;
 use std::sync::mpsc;
@@ -17,7 +18,10 @@ impl LogFormatter for ArtixTarget {
 }
 
 impl FetchMirrors for ArtixTarget {
-    fn fetch_mirrors(&self, _tx_progress: mpsc::Sender<String>) -> Result<Vec<Mirror>, AppError> {
+    fn fetch_mirrors(
+        &self,
+        _tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
         let output = fetch_text_or_file(&self.mirror_list_file, self.fetch_mirrors_timeout)?;
 
         let mut current_country = None;
@@ -55,6 +59,7 @@ impl FetchMirrors for ArtixTarget {
                         .join(&self.path_to_test)
                         .expect("failed to join path_to_test"),
                     url,
+                    meta: Default::default(),
                 });
             }
         }
@@ -1,6 +1,7 @@
 use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
 use crate::countries::Country;
 use crate::mirror::Mirror;
+use crate::progress::ProgressEvent;
 use crate::target_configs::blackarch::BlackArchTarget;
 use std::fmt::Display;
 use std::sync::mpsc;
@@ -23,7 +24,10 @@ impl LogFormatter for BlackArchTarget {
 }
 
 impl FetchMirrors for BlackArchTarget {
-    fn fetch_mirrors(&self, _tx_progress: mpsc::Sender<String>) -> Result<Vec<Mirror>, AppError> {
+    fn fetch_mirrors(
+        &self,
+        _tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
         // RU|http://mirror.surf/blackarch/$repo/os/$arch|mirror.surf
         //
         // http://mirror.surf/blackarch/blackarch/os/x86_64/blackarch.files
@@ -41,10 +45,9 @@ impl FetchMirrors for BlackArchTarget {
                     return None;
                 }
                 let country = Country::from_str(pieces[0]);
-                match Url::parse(&pieces[1].replace("$repo/os/$arch", "")).ok() {
-                    Some(url) => Some((url, country)),
-                    None => None,
-                }
+                Url::parse(&pieces[1].replace("$repo/os/$arch", ""))
+                    .ok()
+                    .map(|url| (url, country))
             })
             .map(|(url, country)| {
                 let url_to_test = url
@@ -54,6 +57,7 @@ impl FetchMirrors for BlackArchTarget {
                     country,
                     url,
                     url_to_test,
+                    meta: Default::default(),
                 }
             })
             .collect();
@@ -1,6 +1,7 @@
 use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
 use crate::countries::Country;
-use crate::mirror::Mirror;
+use crate::mirror::{Mirror, MirrorMeta};
+use crate::progress::ProgressEvent;
 use crate::target_configs::cachyos::CachyOSTarget;
 use serde::Deserialize;
 use std::fmt::Display;
@@ -71,6 +72,10 @@ impl CachyOSTarget {
                     country: resolve_country(&m.country_code),
                     url,
                     url_to_test,
+                    meta: MirrorMeta {
+                        sync_delay: m.delay_seconds,
+                        upstream_score: None,
+                    },
                 })
             })
             .collect();
@@ -107,6 +112,7 @@ impl CachyOSTarget {
                         country: current_country,
                         url,
                         url_to_test,
+                        meta: Default::default(),
                     });
                 }
             }
@@ -117,7 +123,10 @@ impl CachyOSTarget {
 }
 
 impl FetchMirrors for CachyOSTarget {
-    fn fetch_mirrors(&self, tx_progress: mpsc::Sender<String>) -> Result<Vec<Mirror>, AppError> {
+    fn fetch_mirrors(
+        &self,
+        tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
         let output = fetch_text_or_file(&self.mirror_list_file, self.fetch_mirrors_timeout)?;
 
         let result = match self.parse_api_json(&output) {
@@ -126,7 +135,7 @@ impl FetchMirrors for CachyOSTarget {
         };
 
         tx_progress
-            .send(format!("FETCHED MIRRORS: {}", result.len()))
+            .send(ProgressEvent::MirrorsFetched(result.len()))
             .unwrap();
 
         Ok(result)
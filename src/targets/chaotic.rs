@@ -1,6 +1,7 @@
 use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
 use crate::countries::Country;
 use crate::mirror::Mirror;
+use crate::progress::ProgressEvent;
 use crate::target_configs::chaotic::ChaoticTarget;
 use std::fmt::Display;
 use std::sync::mpsc;
@@ -33,7 +34,10 @@ impl LogFormatter for ChaoticTarget {
 }
 
 impl FetchMirrors for ChaoticTarget {
-    fn fetch_mirrors(&self, tx_progress: mpsc::Sender<String>) -> Result<Vec<Mirror>, AppError> {
+    fn fetch_mirrors(
+        &self,
+        tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
         let output = fetch_text_or_file(&self.mirror_list_file, self.fetch_mirrors_timeout)?;
 
         let mut current_country = None;
@@ -63,13 +67,14 @@ impl FetchMirrors for ChaoticTarget {
                         .join(&self.path_to_test)
                         .expect("failed to join path_to_test"),
                     url,
+                    meta: Default::default(),
                 }),
                 Err(e) => {
                     tx_progress
-                        .send(format!(
-                            "chaotic: skipping unparseable URL {}: {}",
-                            cleaned, e
-                        ))
+                        .send(ProgressEvent::ServerSkipped {
+                            url: cleaned.to_string(),
+                            error: e.to_string(),
+                        })
                         .ok();
                 }
             }
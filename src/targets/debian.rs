@@ -0,0 +1,134 @@
+use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
+use crate::countries::Country;
+use crate::mirror::Mirror;
+use crate::progress::ProgressEvent;
+use crate::target_configs::debian::DebianTarget;
+use crate::targets::apt::{AptSource, expand_path};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::mpsc;
+use url::Url;
+
+/// Archive urls of `Mirrors.masterlist` stanzas along with their countries,
+/// e.g. `Site: ftp.de.debian.org`, `Archive-http: /debian/`, `Country: DE Germany`
+pub fn parse_masterlist(text: &str) -> Vec<(Url, Option<&'static Country>)> {
+    let mut mirrors = Vec::new();
+    for stanza in text.split("\n\n") {
+        let fields: HashMap<&str, &str> = stanza
+            .lines()
+            .filter(|line| !line.starts_with([' ', '\t', '#']))
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .collect();
+        let Some(site) = fields.get("Site") else {
+            continue;
+        };
+        let country = fields
+            .get("Country")
+            .and_then(|value| value.split_whitespace().next())
+            .and_then(Country::from_str);
+        for (field, scheme) in [("Archive-https", "https"), ("Archive-http", "http")] {
+            let Some(path) = fields.get(field) else {
+                continue;
+            };
+            let path = path.trim_matches('/');
+            let url = if path.is_empty() {
+                format!("{}://{}/", scheme, site)
+            } else {
+                format!("{}://{}/{}/", scheme, site, path)
+            };
+            if let Ok(url) = Url::parse(&url) {
+                mirrors.push((url, country));
+            }
+        }
+    }
+    mirrors
+}
+
+impl DebianTarget {
+    fn source(&self) -> AptSource<'_> {
+        AptSource {
+            format: self.output_format,
+            suite: &self.suite,
+            components: &self.components,
+            signed_by: &self.signed_by,
+        }
+    }
+}
+
+impl LogFormatter for DebianTarget {
+    fn format_comment(&self, message: impl Display) -> String {
+        format!("{}{}", self.comment_prefix, message)
+    }
+
+    fn format_mirror(&self, mirror: &Mirror) -> String {
+        self.source().format_line(mirror)
+    }
+
+    fn format_mirrors(&self, mirrors: &[Mirror]) -> Vec<String> {
+        self.source().format_mirrors(mirrors)
+    }
+}
+
+impl FetchMirrors for DebianTarget {
+    fn fetch_mirrors(
+        &self,
+        _tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
+        let output = fetch_text_or_file(&self.mirror_list_file, self.fetch_mirrors_timeout)?;
+        let path_to_test = expand_path(&self.path_to_test, &self.suite, &self.arch);
+
+        Ok(parse_masterlist(&output.replace("\r\n", "\n"))
+            .into_iter()
+            .map(|(url, country)| Mirror {
+                country,
+                url_to_test: url
+                    .join(&path_to_test)
+                    .expect("failed to join path_to_test"),
+                url,
+                meta: Default::default(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_masterlist_stanzas() {
+        let raw = "\
+Site: ftp.de.debian.org
+Type: Push-Primary
+Archive-architecture: amd64 arm64
+Archive-http: /debian/
+Archive-rsync: debian/
+Country: DE Germany
+Sponsor: Example
+ https://example.org/
+
+Site: mirror.example.se
+Archive-https: debian
+Archive-http: debian
+Country: SE Sweden
+
+Site: cdimage-only.example.org
+CDImage-http: /debian-cd/
+Country: US United States
+";
+        let mirrors: Vec<_> = parse_masterlist(raw)
+            .into_iter()
+            .map(|(url, country)| (url.to_string(), country.map(|c| c.code)))
+            .collect();
+
+        assert_eq!(
+            mirrors,
+            [
+                ("http://ftp.de.debian.org/debian/".to_string(), Some("DE")),
+                ("https://mirror.example.se/debian/".to_string(), Some("SE")),
+                ("http://mirror.example.se/debian/".to_string(), Some("SE")),
+            ]
+        );
+    }
+}
@@ -1,9 +1,12 @@
-use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
+use crate::config::{
+    AppError, FetchMirrors, LogFormatter, default_client_builder, fetch_text_or_file,
+};
 use crate::countries::Country;
+use crate::http;
 use crate::mirror::Mirror;
+use crate::progress::{MirrorStateError, ProgressEvent};
 use crate::target_configs::endeavouros::EndeavourOSTarget;
 use futures::future::join_all;
-use reqwest;
 use std::fmt::Display;
 use std::str::FromStr;
 use std::sync::{Arc, mpsc};
@@ -21,42 +24,53 @@ async fn version_mirror(
     mirror: Mirror,
     target: Arc<EndeavourOSTarget>,
     semaphore: Arc<Semaphore>,
-    tx_progress: mpsc::Sender<String>,
+    tx_progress: mpsc::Sender<ProgressEvent>,
 ) -> VersionedMirror {
     let _permit = semaphore.acquire().await;
 
-    let client = reqwest::Client::new();
+    let client = match default_client_builder() {
+        Ok(client) => client,
+        Err(e) => {
+            tx_progress
+                .send(ProgressEvent::MirrorVersionFailed {
+                    url: mirror.url.clone(),
+                    error: MirrorStateError::ClientBuild(e.to_string()),
+                })
+                .unwrap();
+            return VersionedMirror {
+                mirror,
+                update_number: None,
+            };
+        }
+    };
     let response_result = client
         .get(mirror.url.join("state").unwrap())
         .timeout(Duration::from_millis(target.version_mirror_timeout))
         .send()
         .await;
 
-    let mut update_number = None;
-    let msg = match response_result {
+    let update = match response_result {
         Ok(response) => match response.text_with_charset("utf-8").await {
-            Ok(output) => {
-                if let Some(line) = output.lines().next() {
-                    if let Ok(number) = line.parse::<usize>() {
-                        update_number = Some(number);
-                        format!("FETCHED MIRROR VERSION {}: {}", number, mirror.url)
-                    } else {
-                        format!("FAILED TO READ MIRROR UPDATE NUMBER: {}", mirror.url)
-                    }
-                } else {
-                    format!("EMPTY MIRROR STATE: {}", mirror.url)
-                }
-            }
-            _ => {
-                format!("FAILED TO READ STATE: {}", mirror.url)
-            }
+            Ok(output) => match output.lines().next() {
+                Some(line) => line
+                    .parse::<usize>()
+                    .map_err(|_| MirrorStateError::InvalidUpdateNumber),
+                None => Err(MirrorStateError::Empty),
+            },
+            _ => Err(MirrorStateError::Read),
         },
-        _ => {
-            format!("FAILED TO CONNECT: {}", mirror.url)
-        }
+        _ => Err(MirrorStateError::Connect),
+    };
+    let url = mirror.url.clone();
+    let (update_number, event) = match update {
+        Ok(version) => (
+            Some(version),
+            ProgressEvent::MirrorVersionFetched { url, version },
+        ),
+        Err(error) => (None, ProgressEvent::MirrorVersionFailed { url, error }),
     };
 
-    tx_progress.send(msg).unwrap();
+    tx_progress.send(event).unwrap();
 
     VersionedMirror {
         mirror,
@@ -67,9 +81,9 @@ async fn version_mirror(
 fn version_mirrors(
     target: Arc<EndeavourOSTarget>,
     mirrors: Vec<Mirror>,
-    tx_progress: mpsc::Sender<String>,
+    tx_progress: mpsc::Sender<ProgressEvent>,
 ) -> Vec<VersionedMirror> {
-    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let runtime = http::runtime();
 
     let semaphore = Arc::new(Semaphore::new(target.version_mirror_concurrency));
 
@@ -82,14 +96,11 @@ fn version_mirrors(
         ))
     });
 
-    let result = runtime
+    runtime
         .block_on(join_all(handles))
         .into_iter()
         .filter_map(|r| r.ok())
-        .collect::<Vec<_>>();
-
-    runtime.shutdown_timeout(Duration::from_secs(1));
-    result
+        .collect::<Vec<_>>()
 }
 
 impl LogFormatter for EndeavourOSTarget {
@@ -103,7 +114,10 @@ impl LogFormatter for EndeavourOSTarget {
 }
 
 impl FetchMirrors for EndeavourOSTarget {
-    fn fetch_mirrors(&self, tx_progress: mpsc::Sender<String>) -> Result<Vec<Mirror>, AppError> {
+    fn fetch_mirrors(
+        &self,
+        tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
         let output = fetch_text_or_file(&self.mirror_list_file, self.fetch_mirrors_timeout)?;
 
         let mut current_country = None;
@@ -129,6 +143,7 @@ impl FetchMirrors for EndeavourOSTarget {
                     country: current_country,
                     url,
                     url_to_test,
+                    meta: Default::default(),
                 });
             }
         }
@@ -146,7 +161,7 @@ impl FetchMirrors for EndeavourOSTarget {
 
         let mirrors = if let Some(version) = max_version {
             tx_progress
-                .send(format!("TAKING MIRRORS WITH LATEST VERSION: {}", version))
+                .send(ProgressEvent::LatestVersionSelected(version))
                 .unwrap();
             versioned_mirrors
                 .into_iter()
@@ -0,0 +1,272 @@
+use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
+use crate::countries::Country;
+use crate::mirror::Mirror;
+use crate::progress::ProgressEvent;
+use crate::target_configs::fedora::FedoraTarget;
+use crate::targets::repomd::{REPOMD_PATH, find_primary_path};
+use select::document::Document;
+use select::predicate::Name;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::mpsc;
+use url::Url;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FedoraFormat {
+    Baseurl,
+    Metalink,
+}
+
+impl FromStr for FedoraFormat {
+    type Err = &'static str;
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "baseurl" => Ok(FedoraFormat::Baseurl),
+            "metalink" => Ok(FedoraFormat::Metalink),
+            _ => Err("could not parse fedora output format"),
+        }
+    }
+}
+
+/// Repository base urls of a metalink (`<url location="DE">.../repodata/repomd.xml</url>`,
+/// http(s) ones only) or of a plain mirrorlist (one base url per line)
+pub fn parse_mirror_list(text: &str) -> Vec<(Url, Option<&'static Country>)> {
+    if text.trim_start().starts_with('<') {
+        let document = Document::from(text);
+        document
+            .find(Name("url"))
+            .filter_map(|node| {
+                let text = node.text();
+                let base = text.trim().strip_suffix(REPOMD_PATH)?;
+                let url = Url::parse(base).ok()?;
+                if !matches!(url.scheme(), "http" | "https") {
+                    return None;
+                }
+                Some((url, node.attr("location").and_then(Country::from_str)))
+            })
+            .collect()
+    } else {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let line = if line.ends_with('/') {
+                    line.to_string()
+                } else {
+                    format!("{}/", line)
+                };
+                Url::parse(&line).ok().map(|url| (url, None))
+            })
+            .collect()
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl FedoraTarget {
+    fn expand(&self, s: &str) -> String {
+        s.replace("$releasever", &self.releasever)
+            .replace("$basearch", &self.basearch)
+    }
+}
+
+/// Metalink `<url>` of the repomd.xml of `mirror`, preferred by `rank`
+fn metalink_url(mirror: &Mirror, rank: usize) -> String {
+    let location = mirror
+        .country
+        .map(|c| format!(r#" location="{}""#, c.code))
+        .unwrap_or_default();
+    let url = mirror
+        .url
+        .join(REPOMD_PATH)
+        .unwrap_or_else(|_| mirror.url.clone());
+    format!(
+        r#"<url protocol="{0}" type="{0}"{1} preference="{2}">{3}</url>"#,
+        url.scheme(),
+        location,
+        100usize.saturating_sub(rank).max(1),
+        escape_xml(url.as_str())
+    )
+}
+
+impl LogFormatter for FedoraTarget {
+    fn format_comment(&self, message: impl Display) -> String {
+        match self.output_format {
+            FedoraFormat::Baseurl => format!("{}{}", self.comment_prefix, message),
+            FedoraFormat::Metalink => {
+                format!("<!-- {} -->", message.to_string().replace("--", "- -"))
+            }
+        }
+    }
+
+    fn format_mirror(&self, mirror: &Mirror) -> String {
+        match self.output_format {
+            FedoraFormat::Baseurl => format!("baseurl={}", mirror.url),
+            FedoraFormat::Metalink => metalink_url(mirror, 0),
+        }
+    }
+
+    fn format_mirrors(&self, mirrors: &[Mirror]) -> Vec<String> {
+        match self.output_format {
+            FedoraFormat::Baseurl => mirrors
+                .iter()
+                .enumerate()
+                .map(|(index, mirror)| {
+                    if index == 0 {
+                        format!("baseurl={}", mirror.url)
+                    } else {
+                        format!("        {}", mirror.url)
+                    }
+                })
+                .collect(),
+            FedoraFormat::Metalink => {
+                // no XML declaration: comments precede the root element
+                let mut lines = vec![
+                    r#"<metalink version="3.0" xmlns="http://www.metalinker.org/">"#.to_string(),
+                    r#"  <files>"#.to_string(),
+                    r#"    <file name="repomd.xml">"#.to_string(),
+                    r#"      <resources>"#.to_string(),
+                ];
+                for (index, mirror) in mirrors.iter().enumerate() {
+                    lines.push(format!("        {}", metalink_url(mirror, index)));
+                }
+                lines.extend(
+                    [
+                        "      </resources>",
+                        "    </file>",
+                        "  </files>",
+                        "</metalink>",
+                    ]
+                    .map(str::to_string),
+                );
+                lines
+            }
+        }
+    }
+}
+
+impl FetchMirrors for FedoraTarget {
+    fn fetch_mirrors(
+        &self,
+        tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
+        let output = fetch_text_or_file(
+            &self.expand(&self.mirror_list_file),
+            self.fetch_mirrors_timeout,
+        )?;
+        let mirrors = parse_mirror_list(&output);
+        let path_to_test = match &self.path_to_test {
+            Some(path) => self.expand(path),
+            None => find_primary_path(
+                mirrors.iter().map(|(url, _)| url),
+                self.fetch_mirrors_timeout,
+                &tx_progress,
+            ),
+        };
+
+        Ok(mirrors
+            .into_iter()
+            .map(|(url, country)| Mirror {
+                country,
+                url_to_test: url
+                    .join(&path_to_test)
+                    .expect("failed to join path_to_test"),
+                url,
+                meta: Default::default(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_metalink() {
+        let raw = r#"<?xml version="1.0" encoding="utf-8"?>
+<metalink version="3.0" xmlns="http://www.metalinker.org/" xmlns:mm0="http://fedorahosted.org/mirrormanager">
+ <files>
+  <file name="repomd.xml">
+   <mm0:timestamp>1700000000</mm0:timestamp>
+   <size>6000</size>
+   <verification><hash type="sha256">abc</hash></verification>
+   <resources maxconnections="1">
+    <url protocol="https" type="https" location="DE" preference="100">https://de.example.org/fedora/releases/42/Everything/x86_64/os/repodata/repomd.xml</url>
+    <url protocol="rsync" type="rsync" location="DE" preference="100">rsync://de.example.org/fedora/releases/42/Everything/x86_64/os/repodata/repomd.xml</url>
+    <url protocol="ftp" type="ftp" location="DE" preference="100">ftp://ftp.example.org/fedora/releases/42/Everything/x86_64/os/repodata/repomd.xml</url>
+    <url protocol="http" type="http" location="XX" preference="99">http://unknown.example.org/os/repodata/repomd.xml</url>
+   </resources>
+  </file>
+ </files>
+</metalink>"#;
+        let mirrors: Vec<_> = parse_mirror_list(raw)
+            .into_iter()
+            .map(|(url, country)| (url.to_string(), country.map(|c| c.code)))
+            .collect();
+
+        assert_eq!(
+            mirrors,
+            [
+                (
+                    "https://de.example.org/fedora/releases/42/Everything/x86_64/os/".to_string(),
+                    Some("DE")
+                ),
+                ("http://unknown.example.org/os/".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn formats_mirrors_as_requested() {
+        use crate::config::{Command, Config, Target};
+        use clap::Parser;
+
+        let target = |format: &str| {
+            let args = ["rate-mirrors", "fedora", "--output-format", format];
+            match Config::try_parse_from(args).unwrap().command {
+                Command::Target(Target::Fedora(target)) => target,
+                other => panic!("expected Fedora target, got {other:?}"),
+            }
+        };
+        let url = Url::parse("https://de.example.org/fedora/os/").unwrap();
+        let mirror = Mirror {
+            url_to_test: url.clone(),
+            url,
+            country: Country::from_str("DE"),
+            meta: Default::default(),
+        };
+
+        assert_eq!(
+            target("baseurl").format_mirror(&mirror),
+            "baseurl=https://de.example.org/fedora/os/"
+        );
+        assert_eq!(
+            target("metalink").format_mirror(&mirror),
+            r#"<url protocol="https" type="https" location="DE" preference="100">https://de.example.org/fedora/os/repodata/repomd.xml</url>"#
+        );
+    }
+
+    #[test]
+    fn parses_plain_mirrorlist() {
+        let raw = "# repo = rocky-BaseOS-9 arch = x86_64 country = DE\n\
+                   https://a.example/rocky/9/BaseOS/x86_64/os/\n\
+                   http://b.example/rocky/9/BaseOS/x86_64/os\n";
+        let urls: Vec<_> = parse_mirror_list(raw)
+            .into_iter()
+            .map(|(url, _)| url.to_string())
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                "https://a.example/rocky/9/BaseOS/x86_64/os/",
+                "http://b.example/rocky/9/BaseOS/x86_64/os/"
+            ]
+        );
+    }
+}
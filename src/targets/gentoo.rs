@@ -0,0 +1,117 @@
+use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
+use crate::countries::Country;
+use crate::mirror::Mirror;
+use crate::progress::ProgressEvent;
+use crate::target_configs::gentoo::GentooTarget;
+use select::document::Document;
+use select::predicate::Name;
+use std::fmt::Display;
+use std::sync::mpsc;
+use url::Url;
+
+/// Distfiles urls of mirrors3.xml: `<mirrorgroup country="DE">` groups of
+/// `<mirror>`s with one `<uri protocol="...">` per protocol
+pub fn parse_mirrors_xml(xml: &str) -> Vec<(Url, Option<&'static Country>)> {
+    let document = Document::from(xml);
+    let mut mirrors = Vec::new();
+    for group in document.find(Name("mirrorgroup")) {
+        let country = group.attr("country").and_then(Country::from_str);
+        for uri in group.find(Name("uri")) {
+            if uri.attr("protocol") == Some("rsync") {
+                continue;
+            }
+            let text = uri.text();
+            let text = text.trim();
+            let text = if text.ends_with('/') {
+                text.to_string()
+            } else {
+                format!("{}/", text)
+            };
+            if let Ok(url) = Url::parse(&text) {
+                mirrors.push((url, country));
+            }
+        }
+    }
+    mirrors
+}
+
+impl LogFormatter for GentooTarget {
+    fn format_comment(&self, message: impl Display) -> String {
+        format!("{}{}", self.comment_prefix, message)
+    }
+
+    fn format_mirror(&self, mirror: &Mirror) -> String {
+        format!("GENTOO_MIRRORS=\"{}\"", mirror.url)
+    }
+
+    /// A single make.conf line listing mirrors fastest first
+    fn format_mirrors(&self, mirrors: &[Mirror]) -> Vec<String> {
+        vec![format!(
+            "GENTOO_MIRRORS=\"{}\"",
+            mirrors
+                .iter()
+                .map(|m| m.url.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        )]
+    }
+}
+
+impl FetchMirrors for GentooTarget {
+    fn fetch_mirrors(
+        &self,
+        _tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
+        let output = fetch_text_or_file(&self.mirror_list_file, self.fetch_mirrors_timeout)?;
+
+        Ok(parse_mirrors_xml(&output)
+            .into_iter()
+            .map(|(url, country)| Mirror {
+                country,
+                url_to_test: url
+                    .join(&self.path_to_test)
+                    .expect("failed to join path_to_test"),
+                url,
+                meta: Default::default(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mirrors3_xml() {
+        let raw = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE mirrors SYSTEM "/dtd/mirrors.dtd">
+<mirrors>
+  <mirrorgroup region="Europe" country="DE" countryname="Germany">
+    <mirror>
+      <name>Example DE</name>
+      <uri protocol="http" ipv4="y" ipv6="y" partial="n">http://ftp.example.de/gentoo</uri>
+      <uri protocol="rsync" ipv4="y" ipv6="y" partial="n">rsync://ftp.example.de/gentoo/</uri>
+    </mirror>
+  </mirrorgroup>
+  <mirrorgroup region="Europe" country="SE" countryname="Sweden">
+    <mirror>
+      <name>Example SE</name>
+      <uri protocol="https" ipv4="y" ipv6="n" partial="n">https://mirror.example.se/gentoo/</uri>
+    </mirror>
+  </mirrorgroup>
+</mirrors>"#;
+        let mirrors: Vec<_> = parse_mirrors_xml(raw)
+            .into_iter()
+            .map(|(url, country)| (url.to_string(), country.map(|c| c.code)))
+            .collect();
+
+        assert_eq!(
+            mirrors,
+            [
+                ("http://ftp.example.de/gentoo/".to_string(), Some("DE")),
+                ("https://mirror.example.se/gentoo/".to_string(), Some("SE")),
+            ]
+        );
+    }
+}
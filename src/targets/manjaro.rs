@@ -2,7 +2,8 @@
 
 use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_json_or_file};
 use crate::countries::Country;
-use crate::mirror::Mirror;
+use crate::mirror::{Mirror, MirrorMeta};
+use crate::progress::ProgressEvent;
 use crate::target_configs::manjaro::{ManjaroBranch, ManjaroTarget};
 use serde::{Deserialize, Deserializer};
 use std::fmt::Display;
@@ -52,12 +53,15 @@ impl LogFormatter for ManjaroTarget {
 }
 
 impl FetchMirrors for ManjaroTarget {
-    fn fetch_mirrors(&self, tx_progress: mpsc::Sender<String>) -> Result<Vec<Mirror>, AppError> {
+    fn fetch_mirrors(
+        &self,
+        tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
         let mirrors_data: Vec<ManjaroMirrorData> =
             fetch_json_or_file(&self.mirror_source, self.fetch_mirrors_timeout)?;
 
         tx_progress
-            .send(format!("FETCHED MIRRORS: {}", mirrors_data.len()))
+            .send(ProgressEvent::MirrorsFetched(mirrors_data.len()))
             .unwrap();
 
         let mirrors: Vec<_> = mirrors_data
@@ -66,7 +70,7 @@ impl FetchMirrors for ManjaroTarget {
                 m.last_sync.is_some()
                     && m.last_sync.unwrap() <= self.max_delay
                     && match self.branch {
-                        ManjaroBranch::Stable => m.branches.get(0) > Some(&0),
+                        ManjaroBranch::Stable => m.branches.first() > Some(&0),
                         ManjaroBranch::Testing => m.branches.get(1) > Some(&0),
                         ManjaroBranch::Unstable => m.branches.get(2) > Some(&0),
                     }
@@ -86,6 +90,10 @@ impl FetchMirrors for ManjaroTarget {
                             country: Country::from_str(&m.country),
                             url,
                             url_to_test,
+                            meta: MirrorMeta {
+                                sync_delay: m.last_sync,
+                                upstream_score: None,
+                            },
                         })
                     })
                     .collect::<Vec<_>>()
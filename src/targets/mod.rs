@@ -1,3 +1,5 @@
+pub mod alpine;
+pub mod apt;
 pub mod arch4edu;
 pub mod archarm;
 pub mod archlinux;
@@ -7,8 +9,15 @@ pub mod artix;
 pub mod blackarch;
 pub mod cachyos;
 pub mod chaotic;
+pub mod debian;
 pub mod endeavouros;
+pub mod fedora;
+pub mod gentoo;
 pub mod manjaro;
 pub mod openbsd;
+pub mod opensuse;
+pub mod pacman_mirrorlist;
 pub mod rebornos;
+pub mod repomd;
 pub mod stdin;
+pub mod ubuntu;
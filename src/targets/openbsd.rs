@@ -1,6 +1,7 @@
 use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
 use crate::countries::Country;
 use crate::mirror::Mirror;
+use crate::progress::ProgressEvent;
 use crate::target_configs::openbsd::OpenBSDTarget;
 use std::fmt::Display;
 use std::sync::mpsc;
@@ -17,7 +18,10 @@ impl LogFormatter for OpenBSDTarget {
 }
 
 impl FetchMirrors for OpenBSDTarget {
-    fn fetch_mirrors(&self, _tx_progress: mpsc::Sender<String>) -> Result<Vec<Mirror>, AppError> {
+    fn fetch_mirrors(
+        &self,
+        _tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
         let output = fetch_text_or_file(&self.mirror_source, self.fetch_mirrors_timeout)?;
 
         let urls = output
@@ -31,9 +35,7 @@ impl FetchMirrors for OpenBSDTarget {
                 !url_part.is_empty() && !description_part.is_empty()
             })
             .filter_map(|(url_part, description_part)| {
-                Url::parse(&url_part)
-                    .ok()
-                    .map(|url| (url, description_part))
+                Url::parse(url_part).ok().map(|url| (url, description_part))
             });
 
         let result: Vec<_> = urls
@@ -62,6 +64,7 @@ impl FetchMirrors for OpenBSDTarget {
                         country: Country::from_str(country),
                         url,
                         url_to_test,
+                        meta: Default::default(),
                     })
             })
             .collect();
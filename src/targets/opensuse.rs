@@ -0,0 +1,224 @@
+use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
+use crate::countries::Country;
+use crate::mirror::Mirror;
+use crate::progress::ProgressEvent;
+use crate::target_configs::opensuse::OpenSUSETarget;
+use crate::targets::repomd::find_primary_path;
+use select::document::Document;
+use select::predicate::Name;
+use serde_json::Value;
+use std::fmt::Display;
+use std::sync::mpsc;
+use url::Url;
+
+/// Whether a JSON flag like `"enabled": 1` / `"enabled": false` is off
+fn is_disabled(value: Option<&Value>) -> bool {
+    match value {
+        Some(Value::Bool(enabled)) => !enabled,
+        Some(Value::Number(n)) => n.as_i64() == Some(0),
+        _ => false,
+    }
+}
+
+/// Mirror roots of MirrorCache `/rest/server` entries, which carry either a
+/// `url` or `hostname` + `urldir`, along with a `country`
+pub fn parse_server_json(value: &Value) -> Vec<(String, Option<&'static Country>)> {
+    let servers = match value {
+        Value::Array(servers) => servers,
+        Value::Object(object) => match object.get("data").or_else(|| object.get("servers")) {
+            Some(Value::Array(servers)) => servers,
+            _ => return Vec::new(),
+        },
+        _ => return Vec::new(),
+    };
+    servers
+        .iter()
+        .filter(|server| !is_disabled(server.get("enabled")))
+        .filter_map(|server| {
+            let root = match (server.get("url"), server.get("hostname")) {
+                (Some(Value::String(url)), _) => url.clone(),
+                (_, Some(Value::String(hostname))) => {
+                    let urldir = server.get("urldir").and_then(Value::as_str).unwrap_or("");
+                    format!("http://{}{}", hostname, urldir)
+                }
+                _ => return None,
+            };
+            let country = server
+                .get("country")
+                .and_then(Value::as_str)
+                .and_then(Country::from_str);
+            Some((root, country))
+        })
+        .collect()
+}
+
+/// Links of a mirror list page, one list item per mirror, with the country
+/// code in parentheses, e.g. `<li><a href="https://ftp.fau.de/opensuse/...">ftp.fau.de</a> (DE)</li>`
+pub fn parse_mirror_list_html(html: &str) -> Vec<(String, Option<&'static Country>)> {
+    let document = Document::from(html);
+    document
+        .find(Name("li"))
+        .filter_map(|item| {
+            let href = item
+                .find(Name("a"))
+                .filter_map(|link| link.attr("href"))
+                .find(|href| href.starts_with("http://") || href.starts_with("https://"))?;
+            let text = item.text();
+            let country = text
+                .split('(')
+                .skip(1)
+                .filter_map(|part| part.split_once(')').map(|(code, _)| code.trim()))
+                .find(|code| code.len() == 2)
+                .and_then(Country::from_str);
+            Some((href.to_string(), country))
+        })
+        .collect()
+}
+
+/// Repository url for a mirror root or for a link pointing inside the repository
+fn repo_url(link: &str, repo_path: &str) -> Option<Url> {
+    let repo_path = repo_path.trim_matches('/');
+    if let Some(index) = link.find(repo_path) {
+        return Url::parse(&format!("{}/", &link[..index + repo_path.len()])).ok();
+    }
+    let root = if link.ends_with('/') {
+        link.to_string()
+    } else {
+        format!("{}/", link)
+    };
+    Url::parse(&root)
+        .ok()?
+        .join(&format!("{}/", repo_path))
+        .ok()
+}
+
+impl LogFormatter for OpenSUSETarget {
+    fn format_comment(&self, message: impl Display) -> String {
+        format!("{}{}", self.comment_prefix, message)
+    }
+
+    fn format_mirror(&self, mirror: &Mirror) -> String {
+        format!("baseurl={}", mirror.url)
+    }
+
+    /// A zypper .repo stanza using the fastest mirror; the runners-up
+    /// are listed as commented out alternatives
+    fn format_mirrors(&self, mirrors: &[Mirror]) -> Vec<String> {
+        let Some((first, rest)) = mirrors.split_first() else {
+            return Vec::new();
+        };
+        let mut lines = vec![
+            format!("[{}]", self.repo_alias),
+            format!("name={}", self.repo_name),
+            "enabled=1".to_string(),
+            "autorefresh=1".to_string(),
+            self.format_mirror(first),
+        ];
+        lines.extend(rest.iter().map(|m| format!("#{}", self.format_mirror(m))));
+        lines.push("type=rpm-md".to_string());
+        lines
+    }
+}
+
+impl FetchMirrors for OpenSUSETarget {
+    fn fetch_mirrors(
+        &self,
+        tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
+        let output = fetch_text_or_file(&self.mirror_list_file, self.fetch_mirrors_timeout)?;
+        let links = match serde_json::from_str::<Value>(&output) {
+            Ok(value) => parse_server_json(&value),
+            Err(_) => parse_mirror_list_html(&output),
+        };
+        let repos: Vec<_> = links
+            .into_iter()
+            .filter_map(|(link, country)| Some((repo_url(&link, &self.repo_path)?, country)))
+            .collect();
+        let path_to_test = match &self.path_to_test {
+            Some(path) => path.clone(),
+            None => find_primary_path(
+                repos.iter().map(|(url, _)| url),
+                self.fetch_mirrors_timeout,
+                &tx_progress,
+            ),
+        };
+
+        Ok(repos
+            .into_iter()
+            .map(|(url, country)| Mirror {
+                country,
+                url_to_test: url
+                    .join(&path_to_test)
+                    .expect("failed to join path_to_test"),
+                url,
+                meta: Default::default(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolved(
+        links: Vec<(String, Option<&'static Country>)>,
+    ) -> Vec<(String, Option<&'static str>)> {
+        links
+            .into_iter()
+            .map(|(link, country)| {
+                let url = repo_url(&link, "tumbleweed/repo/oss/").unwrap();
+                (url.to_string(), country.map(|c| c.code))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_mirrorcache_server_json() {
+        let value: Value = serde_json::from_str(
+            r#"[
+                {"hostname": "ftp.fau.de", "urldir": "/opensuse", "country": "de", "enabled": 1},
+                {"hostname": "off.example.org", "urldir": "/opensuse", "country": "fr", "enabled": 0},
+                {"url": "https://mirror.example.se/pub/opensuse/", "country": "se"}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            resolved(parse_server_json(&value)),
+            [
+                (
+                    "http://ftp.fau.de/opensuse/tumbleweed/repo/oss/".to_string(),
+                    Some("DE")
+                ),
+                (
+                    "https://mirror.example.se/pub/opensuse/tumbleweed/repo/oss/".to_string(),
+                    Some("SE")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_mirror_list_page() {
+        let html = r#"<html><body><ul>
+<li><a href="https://ftp.fau.de/opensuse/tumbleweed/repo/oss/repodata/repomd.xml">ftp.fau.de</a> (DE)</li>
+<li><a href="/details">details</a> <a href="http://mirror.example.org/opensuse/">mirror.example.org</a></li>
+<li>no link here</li>
+</ul></body></html>"#;
+
+        assert_eq!(
+            resolved(parse_mirror_list_html(html)),
+            [
+                (
+                    "https://ftp.fau.de/opensuse/tumbleweed/repo/oss/".to_string(),
+                    Some("DE")
+                ),
+                (
+                    "http://mirror.example.org/opensuse/tumbleweed/repo/oss/".to_string(),
+                    None
+                ),
+            ]
+        );
+    }
+}
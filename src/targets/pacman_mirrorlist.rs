@@ -0,0 +1,198 @@
+use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
+use crate::countries::Country;
+use crate::mirror::Mirror;
+use crate::progress::ProgressEvent;
+use crate::target_configs::pacman_mirrorlist::PacmanMirrorlistTarget;
+use std::fmt::Display;
+use std::sync::mpsc;
+use url::Url;
+
+#[derive(Debug, PartialEq)]
+pub enum Line<'a> {
+    /// country header; `None` for headers naming no known country
+    Country(Option<&'static Country>),
+    /// `Server = ` template, possibly commented out
+    Server(&'a str),
+    Other,
+}
+
+/// `XX` of a trailing `(XX)`, as in `# Australia (AU)`
+fn parenthesized_code(comment: &str) -> Option<&str> {
+    let inside = comment.trim_end().strip_suffix(')')?;
+    let (_, code) = inside.rsplit_once('(')?;
+    if code.len() == 2 && code.bytes().all(|b| b.is_ascii_uppercase()) {
+        Some(code)
+    } else {
+        None
+    }
+}
+
+/// Recognizes `## Country`, `# Country (CC)` and `## ... code=CC` headers
+/// along with both active and commented out servers.
+pub fn parse_line(line: &str) -> Line<'_> {
+    let trimmed = line.trim();
+    let uncommented = trimmed.trim_start_matches('#').trim_start();
+    let hashes = trimmed.len() - trimmed.trim_start_matches('#').len();
+
+    if let Some(rest) = uncommented.strip_prefix("Server") {
+        if let Some(template) = rest.trim_start().strip_prefix('=') {
+            return Line::Server(template.trim());
+        }
+    }
+    if hashes == 0 || uncommented.is_empty() {
+        return Line::Other;
+    }
+    if let Some(code) = uncommented
+        .split_whitespace()
+        .find_map(|token| token.strip_prefix("code="))
+    {
+        return Line::Country(Country::from_str(code));
+    }
+    if let Some(code) = parenthesized_code(uncommented) {
+        return Line::Country(Country::from_str(code));
+    }
+    if hashes >= 2 {
+        return Line::Country(Country::from_str(uncommented));
+    }
+    Line::Other
+}
+
+impl PacmanMirrorlistTarget {
+    fn expand(&self, template: &str) -> String {
+        template
+            .replace("$repo", &self.repo)
+            .replace("$arch", &self.arch)
+    }
+
+    /// Mirror url keeps the server template as is, so that it is written
+    /// back unchanged; only the url to test has variables substituted.
+    fn parse_mirrorlist(
+        &self,
+        text: &str,
+        tx_progress: &mpsc::Sender<ProgressEvent>,
+    ) -> Vec<Mirror> {
+        let mut current_country = None;
+        let mut mirrors = Vec::new();
+
+        for line in text.lines() {
+            let template = match parse_line(line) {
+                Line::Country(country) => {
+                    current_country = country;
+                    continue;
+                }
+                Line::Server(template) => template,
+                Line::Other => continue,
+            };
+            let mut expanded = self.expand(template);
+            if !expanded.ends_with('/') {
+                expanded.push('/');
+            }
+            let parsed = Url::parse(template).and_then(|url| {
+                let url_to_test = Url::parse(&expanded)?.join(&self.expand(&self.path_to_test))?;
+                Ok((url, url_to_test))
+            });
+            match parsed {
+                Ok((url, url_to_test)) => mirrors.push(Mirror {
+                    country: current_country,
+                    url,
+                    url_to_test,
+                    meta: Default::default(),
+                }),
+                Err(e) => {
+                    tx_progress
+                        .send(ProgressEvent::ServerSkipped {
+                            url: template.to_string(),
+                            error: e.to_string(),
+                        })
+                        .ok();
+                }
+            }
+        }
+
+        mirrors
+    }
+}
+
+impl LogFormatter for PacmanMirrorlistTarget {
+    fn format_comment(&self, message: impl Display) -> String {
+        format!("{}{}", self.comment_prefix, message)
+    }
+
+    fn format_mirror(&self, mirror: &Mirror) -> String {
+        format!("Server = {}", mirror.url)
+    }
+}
+
+impl FetchMirrors for PacmanMirrorlistTarget {
+    fn fetch_mirrors(
+        &self,
+        tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
+        let output = fetch_text_or_file(&self.mirror_list_file, self.fetch_mirrors_timeout)?;
+        Ok(self.parse_mirrorlist(&output, &tx_progress))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_target() -> PacmanMirrorlistTarget {
+        PacmanMirrorlistTarget {
+            mirror_list_file: String::new(),
+            fetch_mirrors_timeout: 15000,
+            repo: "extra".to_string(),
+            arch: "x86_64".to_string(),
+            path_to_test: "$repo.files".to_string(),
+            comment_prefix: "# ".to_string(),
+        }
+    }
+
+    #[test]
+    fn recognizes_country_header_styles() {
+        let code = |line| match parse_line(line) {
+            Line::Country(country) => Some(country.map(|c| c.code)),
+            _ => None,
+        };
+        assert_eq!(code("## Germany"), Some(Some("DE")));
+        assert_eq!(code("# Australia (AU)"), Some(Some("AU")));
+        assert_eq!(code("## tier=1 code=FR"), Some(Some("FR")));
+        assert_eq!(code("## tier=1 code=GLOBAL"), Some(None));
+        assert_eq!(code("## Generated on 2025-01-01"), Some(None));
+        assert_eq!(code("# a plain comment"), None);
+        assert_eq!(code("#"), None);
+    }
+
+    #[test]
+    fn keeps_templates_and_expands_url_to_test() {
+        let raw = "\
+## Worldwide
+Server = https://geo.example.org/archlinux/$repo/os/$arch
+## Germany
+#Server = https://de.example.org/$repo/os/$arch
+# Sweden (SE)
+Server=https://se.example.org/pkgs/$arch/$repo
+Server = not a url
+";
+        let (tx, rx) = mpsc::channel();
+        let mirrors = make_target().parse_mirrorlist(raw, &tx);
+
+        assert_eq!(mirrors.len(), 3);
+        assert!(mirrors[0].country.is_none());
+        assert_eq!(
+            make_target().format_mirror(&mirrors[0]),
+            "Server = https://geo.example.org/archlinux/$repo/os/$arch"
+        );
+        assert_eq!(
+            mirrors[0].url_to_test.as_str(),
+            "https://geo.example.org/archlinux/extra/os/x86_64/extra.files"
+        );
+        assert_eq!(mirrors[1].country.map(|c| c.code), Some("DE"));
+        assert_eq!(mirrors[2].country.map(|c| c.code), Some("SE"));
+        assert_eq!(
+            mirrors[2].url_to_test.as_str(),
+            "https://se.example.org/pkgs/x86_64/extra/extra.files"
+        );
+        assert_eq!(rx.try_iter().count(), 1);
+    }
+}
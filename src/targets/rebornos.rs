@@ -1,5 +1,6 @@
 use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
 use crate::mirror::Mirror;
+use crate::progress::ProgressEvent;
 use crate::target_configs::rebornos::RebornOSTarget;
 use std::fmt::Display;
 use std::sync::mpsc;
@@ -16,7 +17,10 @@ impl LogFormatter for RebornOSTarget {
 }
 
 impl FetchMirrors for RebornOSTarget {
-    fn fetch_mirrors(&self, _tx_progress: mpsc::Sender<String>) -> Result<Vec<Mirror>, AppError> {
+    fn fetch_mirrors(
+        &self,
+        _tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
         let output = fetch_text_or_file(&self.mirror_list_file, self.fetch_mirrors_timeout)?;
 
         let urls: Vec<Url> = output
@@ -35,6 +39,7 @@ impl FetchMirrors for RebornOSTarget {
                     .join(&self.path_to_test)
                     .expect("failed to join path-to-test"),
                 url,
+                meta: Default::default(),
             })
             .collect();
 
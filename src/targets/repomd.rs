@@ -0,0 +1,86 @@
+use crate::config::fetch_text;
+use crate::progress::ProgressEvent;
+use select::document::Document;
+use select::predicate::{Attr, Name, Predicate};
+use std::sync::mpsc;
+use url::Url;
+
+/// Index of rpm-md repository metadata, relative to the repository url
+pub const REPOMD_PATH: &str = "repodata/repomd.xml";
+
+/// Number of mirrors to try when looking up the primary metadata file
+const REPOMD_ATTEMPTS: usize = 3;
+
+/// `href` of the primary metadata `<location>` in repomd.xml
+fn primary_location(repomd: &str) -> Option<String> {
+    let document = Document::from(repomd);
+    let data = document
+        .find(Name("data").and(Attr("type", "primary")))
+        .next()?;
+    data.find(Name("location"))
+        .next()?
+        .attr("href")
+        .map(str::to_string)
+}
+
+/// Path of the primary metadata file, which is large and has a
+/// content-addressed name, as listed by repomd.xml of the first reachable
+/// repository; falls back to repomd.xml itself.
+pub fn find_primary_path<'a>(
+    repo_urls: impl Iterator<Item = &'a Url>,
+    timeout_ms: u64,
+    tx_progress: &mpsc::Sender<ProgressEvent>,
+) -> String {
+    for url in repo_urls.take(REPOMD_ATTEMPTS) {
+        let Ok(repomd_url) = url.join(REPOMD_PATH) else {
+            continue;
+        };
+        match fetch_text(repomd_url.as_str(), timeout_ms) {
+            Ok(repomd) => {
+                if let Some(location) = primary_location(&repomd) {
+                    return location;
+                }
+            }
+            Err(e) => {
+                tx_progress
+                    .send(ProgressEvent::MetadataFetchFailed {
+                        url: repomd_url,
+                        error: e.to_string(),
+                    })
+                    .ok();
+            }
+        }
+    }
+    tx_progress
+        .send(ProgressEvent::PrimaryMetadataNotFound {
+            fallback: REPOMD_PATH,
+        })
+        .ok();
+    REPOMD_PATH.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_primary_location_in_repomd() {
+        let repomd = r#"<?xml version="1.0" encoding="UTF-8"?>
+<repomd xmlns="http://linux.duke.edu/metadata/repo">
+  <revision>1700000000</revision>
+  <data type="filelists">
+    <checksum type="sha256">aaa</checksum>
+    <location href="repodata/aaa-filelists.xml.zst"/>
+  </data>
+  <data type="primary">
+    <checksum type="sha256">bbb</checksum>
+    <location href="repodata/bbb-primary.xml.zst"/>
+    <size>123</size>
+  </data>
+</repomd>"#;
+        assert_eq!(
+            primary_location(repomd).as_deref(),
+            Some("repodata/bbb-primary.xml.zst")
+        );
+    }
+}
@@ -5,6 +5,7 @@ use std::io::{self, BufRead};
 use std::sync::mpsc;
 
 use crate::mirror::{Mirror, MirrorInfo};
+use crate::progress::ProgressEvent;
 
 impl LogFormatter for StdinTarget {
     fn format_comment(&self, message: impl Display) -> String {
@@ -24,7 +25,10 @@ impl LogFormatter for StdinTarget {
 }
 
 impl FetchMirrors for StdinTarget {
-    fn fetch_mirrors(&self, _tx_progress: mpsc::Sender<String>) -> Result<Vec<Mirror>, AppError> {
+    fn fetch_mirrors(
+        &self,
+        _tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
         let mirrors: Vec<_> = io::stdin()
             .lock()
             .lines()
@@ -37,6 +41,7 @@ impl FetchMirrors for StdinTarget {
                             .join(&self.path_to_test)
                             .expect("failed to join path-to-test"),
                         url: info.url,
+                        meta: Default::default(),
                     }),
                     Err(err) => {
                         eprintln!("{}", err);
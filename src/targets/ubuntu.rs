@@ -0,0 +1,164 @@
+use crate::config::{AppError, FetchMirrors, LogFormatter, fetch_text_or_file};
+use crate::countries::Country;
+use crate::mirror::{Mirror, MirrorInfo};
+use crate::progress::ProgressEvent;
+use crate::target_configs::ubuntu::UbuntuTarget;
+use crate::targets::apt::{AptSource, expand_path};
+use select::document::Document;
+use select::predicate::Name;
+use std::fmt::Display;
+use std::sync::mpsc;
+use url::Url;
+
+/// Mirrors of the Launchpad archive mirrors page: rows with a `head` class
+/// name a country, the following rows link to http/https archive urls.
+pub fn parse_launchpad_html(html: &str) -> Vec<MirrorInfo> {
+    let document = Document::from(html);
+    let mut country: Option<&'static Country> = None;
+    let mut mirrors = Vec::new();
+
+    for row in document.find(Name("tr")) {
+        if row
+            .attr("class")
+            .is_some_and(|class| class.split_whitespace().any(|c| c == "head"))
+        {
+            country = row
+                .find(Name("th"))
+                .next()
+                .and_then(|th| Country::from_str(th.text().trim()));
+            continue;
+        }
+        for link in row.find(Name("a")) {
+            let protocol = link.text();
+            if !matches!(protocol.trim(), "http" | "https") {
+                continue;
+            }
+            if let Some(url) = link.attr("href").and_then(|href| Url::parse(href).ok()) {
+                mirrors.push(MirrorInfo { url, country });
+            }
+        }
+    }
+    mirrors
+}
+
+fn parse_mirror_list(text: &str, tx_progress: &mpsc::Sender<ProgressEvent>) -> Vec<MirrorInfo> {
+    if text.trim_start().starts_with('<') {
+        return parse_launchpad_html(text);
+    }
+    text.lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .filter_map(|line| match MirrorInfo::parse(line, "\t") {
+            Ok(info) => Some(info),
+            Err(err) => {
+                tx_progress
+                    .send(ProgressEvent::ServerSkipped {
+                        url: line.trim().to_string(),
+                        error: err.to_string(),
+                    })
+                    .ok();
+                None
+            }
+        })
+        .collect()
+}
+
+impl UbuntuTarget {
+    fn source(&self) -> AptSource<'_> {
+        AptSource {
+            format: self.output_format,
+            suite: &self.suite,
+            components: &self.components,
+            signed_by: &self.signed_by,
+        }
+    }
+}
+
+impl LogFormatter for UbuntuTarget {
+    fn format_comment(&self, message: impl Display) -> String {
+        format!("{}{}", self.comment_prefix, message)
+    }
+
+    fn format_mirror(&self, mirror: &Mirror) -> String {
+        self.source().format_line(mirror)
+    }
+
+    fn format_mirrors(&self, mirrors: &[Mirror]) -> Vec<String> {
+        self.source().format_mirrors(mirrors)
+    }
+}
+
+impl FetchMirrors for UbuntuTarget {
+    fn fetch_mirrors(
+        &self,
+        tx_progress: mpsc::Sender<ProgressEvent>,
+    ) -> Result<Vec<Mirror>, AppError> {
+        let output = fetch_text_or_file(&self.mirror_list_file, self.fetch_mirrors_timeout)?;
+        let path_to_test = expand_path(&self.path_to_test, &self.suite, &self.arch);
+
+        Ok(parse_mirror_list(&output, &tx_progress)
+            .into_iter()
+            .map(|info| {
+                let mut url = info.url;
+                if !url.path().ends_with('/') {
+                    url.set_path(&format!("{}/", url.path()));
+                }
+                Mirror {
+                    country: info.country,
+                    url_to_test: url
+                        .join(&path_to_test)
+                        .expect("failed to join path_to_test"),
+                    url,
+                    meta: Default::default(),
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_launchpad_archive_mirrors_page() {
+        let html = r#"<html><body><table id="mirrors_list">
+<tr class="head"><th colspan="2">Germany</th><th>1 Gbps</th></tr>
+<tr>
+  <td><a href="https://launchpad.net/ubuntu/+mirror/de.example">Example DE</a></td>
+  <td>
+    <a href="http://de.example.org/ubuntu/">http</a>
+    <a href="https://de.example.org/ubuntu/">https</a>
+    <a href="rsync://de.example.org/ubuntu/">rsync</a>
+  </td>
+  <td>1 Gbps</td><td>Up to date</td>
+</tr>
+<tr class="head"><th colspan="2">Atlantis</th></tr>
+<tr><td><a href="http://x.example/">X</a></td><td><a href="http://x.example/ubuntu">http</a></td></tr>
+</table></body></html>"#;
+        let (tx, _rx) = mpsc::channel();
+        let mirrors: Vec<_> = parse_mirror_list(html, &tx)
+            .into_iter()
+            .map(|info| (info.url.to_string(), info.country.map(|c| c.code)))
+            .collect();
+
+        assert_eq!(
+            mirrors,
+            [
+                ("http://de.example.org/ubuntu/".to_string(), Some("DE")),
+                ("https://de.example.org/ubuntu/".to_string(), Some("DE")),
+                ("http://x.example/ubuntu".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_plain_url_lists() {
+        let (tx, _rx) = mpsc::channel();
+        let mirrors = parse_mirror_list(
+            "http://a.example/ubuntu/\nFR\thttp://b.example/ubuntu/\n",
+            &tx,
+        );
+        assert_eq!(mirrors.len(), 2);
+        assert_eq!(mirrors[1].country.map(|c| c.code), Some("FR"));
+    }
+}
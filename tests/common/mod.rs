@@ -0,0 +1,380 @@
+//! Loopback mirrors with scripted behavior for end-to-end tests.
+#![allow(dead_code)]
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio::time::sleep;
+
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// How a mock mirror answers every request
+#[derive(Debug, Clone, Copy)]
+pub enum Behavior {
+    /// serves `size` bytes at `bandwidth` bytes/s after `latency`
+    Serve {
+        bandwidth: usize,
+        latency: Duration,
+        size: usize,
+    },
+    /// serves the first `after` bytes at `initial` bytes/s, the rest at
+    /// `bandwidth` bytes/s
+    SlowStart {
+        initial: usize,
+        after: usize,
+        bandwidth: usize,
+        size: usize,
+    },
+    /// serves the first `ranges` `bytes=start-end` ranges requested over a
+    /// connection of a file of `size` bytes at `bandwidth` bytes/s, the
+    /// whole file otherwise
+    Ranged {
+        bandwidth: usize,
+        size: usize,
+        ranges: usize,
+    },
+    /// answers 416 Range Not Satisfiable to any `Range` request, otherwise
+    /// like `Behavior::serve`
+    RejectRanges { bandwidth: usize },
+    /// serves like `Behavior::serve`, with `Last-Modified` set to the unix
+    /// timestamp `synced_at`
+    Synced { bandwidth: usize, synced_at: i64 },
+    /// serves `db` for `*.db` requests, otherwise like `Behavior::serve`
+    Repo { bandwidth: usize, db: &'static [u8] },
+    /// answers DNS over HTTPS queries of any name with the IPv4 loopback
+    Doh,
+    /// resets the connection right after reading the request
+    Reset,
+    /// answers 404 Not Found
+    NotFound,
+    /// sends headers, but never the body
+    Stall,
+}
+
+impl Behavior {
+    pub fn ranged(bandwidth: usize, size: usize) -> Self {
+        Behavior::Ranged {
+            bandwidth,
+            size,
+            ranges: usize::MAX,
+        }
+    }
+
+    pub fn serve(bandwidth: usize) -> Self {
+        Behavior::Serve {
+            bandwidth,
+            latency: Duration::ZERO,
+            size: 8 * 1024 * 1024,
+        }
+    }
+}
+
+pub struct MockMirror {
+    pub addr: SocketAddr,
+    pub country: Option<&'static str>,
+}
+
+impl MockMirror {
+    /// Starts a server on a loopback port in a background thread, which
+    /// lives as long as the test process.
+    pub fn start(country: Option<&'static str>, behavior: Behavior) -> Self {
+        Self::start_on("127.0.0.1", country, behavior)
+    }
+
+    /// Like `start`, on the IPv6 loopback
+    pub fn start_v6(country: Option<&'static str>, behavior: Behavior) -> Self {
+        Self::start_on("[::1]", country, behavior)
+    }
+
+    fn start_on(host: &str, country: Option<&'static str>, behavior: Behavior) -> Self {
+        let std_listener = std::net::TcpListener::bind(format!("{}:0", host)).unwrap();
+        std_listener.set_nonblocking(true).unwrap();
+        let addr = std_listener.local_addr().unwrap();
+        thread::spawn(move || {
+            Runtime::new().unwrap().block_on(async move {
+                let listener = TcpListener::from_std(std_listener).unwrap();
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(handle(stream, behavior));
+                }
+            })
+        });
+        Self { addr, country }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}/mirror/", self.addr)
+    }
+
+    /// `COUNTRY<tab>URL` or `URL` line of the stdin target
+    pub fn stdin_line(&self) -> String {
+        match self.country {
+            Some(country) => format!("{}\t{}", country, self.url()),
+            None => self.url(),
+        }
+    }
+}
+
+/// Reads request headers, returning them from the request line on
+async fn read_request(stream: &mut TcpStream) -> Option<String> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => request.extend_from_slice(&buf[..n]),
+        }
+    }
+    Some(String::from_utf8_lossy(&request).into_owned())
+}
+
+/// First and last byte of the `Range` header of `request`
+fn requested_range(request: &str) -> Option<(usize, usize)> {
+    let range = request.lines().find_map(|line| {
+        line.to_ascii_lowercase()
+            .strip_prefix("range: bytes=")
+            .map(str::to_string)
+    })?;
+    let (start, end) = range.trim().split_once('-')?;
+    Some((start.parse().ok()?, end.parse().ok()?))
+}
+
+async fn write_paced(stream: &mut TcpStream, size: usize, bandwidth: usize) -> bool {
+    let chunk = vec![0u8; CHUNK_SIZE];
+    let mut left = size;
+    while left > 0 {
+        let n = left.min(CHUNK_SIZE);
+        if stream.write_all(&chunk[..n]).await.is_err() {
+            return false;
+        }
+        left -= n;
+        sleep(Duration::from_secs_f64(n as f64 / bandwidth as f64)).await;
+    }
+    true
+}
+
+async fn handle(mut stream: TcpStream, behavior: Behavior) {
+    let mut ranges_served = 0;
+    while let Some(request) = read_request(&mut stream).await {
+        let head = request.starts_with("HEAD ");
+        let headers = |status: &str, size: usize| {
+            format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nContent-Type: application/octet-stream\r\n\r\n",
+                status, size
+            )
+        };
+        match behavior {
+            Behavior::Serve {
+                bandwidth,
+                latency,
+                size,
+            } => {
+                sleep(latency).await;
+                if stream
+                    .write_all(headers("200 OK", size).as_bytes())
+                    .await
+                    .is_err()
+                    || (!head && !write_paced(&mut stream, size, bandwidth).await)
+                {
+                    return;
+                }
+            }
+            Behavior::SlowStart {
+                initial,
+                after,
+                bandwidth,
+                size,
+            } => {
+                if stream
+                    .write_all(headers("200 OK", size).as_bytes())
+                    .await
+                    .is_err()
+                    || !write_paced(&mut stream, after, initial).await
+                    || !write_paced(&mut stream, size - after, bandwidth).await
+                {
+                    return;
+                }
+            }
+            Behavior::Ranged {
+                bandwidth,
+                size,
+                ranges,
+            } => {
+                let range = requested_range(&request).filter(|_| ranges_served < ranges);
+                ranges_served += range.is_some() as usize;
+                let (response, length) = match range {
+                    Some((start, _)) if start >= size => {
+                        (headers("416 Range Not Satisfiable", 0), 0)
+                    }
+                    Some((start, end)) => {
+                        let end = end.min(size - 1);
+                        let content_range = format!(
+                            "\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                            start, end, size
+                        );
+                        let length = end + 1 - start;
+                        let response = headers("206 Partial Content", length);
+                        (response.replacen("\r\n\r\n", &content_range, 1), length)
+                    }
+                    None => (headers("200 OK", size), size),
+                };
+                if stream.write_all(response.as_bytes()).await.is_err()
+                    || !write_paced(&mut stream, length, bandwidth).await
+                {
+                    return;
+                }
+            }
+            Behavior::RejectRanges { bandwidth } => {
+                let size = 8 * 1024 * 1024;
+                let response = match requested_range(&request) {
+                    Some(_) => headers("416 Range Not Satisfiable", 0),
+                    None => headers("200 OK", size),
+                };
+                if stream.write_all(response.as_bytes()).await.is_err()
+                    || (response.starts_with("HTTP/1.1 200")
+                        && !write_paced(&mut stream, size, bandwidth).await)
+                {
+                    return;
+                }
+            }
+            Behavior::Synced {
+                bandwidth,
+                synced_at,
+            } => {
+                let size = 8 * 1024 * 1024;
+                let last_modified = chrono::DateTime::from_timestamp(synced_at, 0)
+                    .unwrap()
+                    .format("%a, %d %b %Y %H:%M:%S GMT");
+                let headers = headers("200 OK", size).replacen(
+                    "\r\n\r\n",
+                    &format!("\r\nLast-Modified: {}\r\n\r\n", last_modified),
+                    1,
+                );
+                if stream.write_all(headers.as_bytes()).await.is_err()
+                    || (!head && !write_paced(&mut stream, size, bandwidth).await)
+                {
+                    return;
+                }
+            }
+            Behavior::Repo { db, .. } if request.contains(".db ") => {
+                let response = headers("200 OK", db.len());
+                if stream.write_all(response.as_bytes()).await.is_err()
+                    || stream.write_all(db).await.is_err()
+                {
+                    return;
+                }
+            }
+            Behavior::Repo { bandwidth, .. } => {
+                let size = 8 * 1024 * 1024;
+                if stream
+                    .write_all(headers("200 OK", size).as_bytes())
+                    .await
+                    .is_err()
+                    || !write_paced(&mut stream, size, bandwidth).await
+                {
+                    return;
+                }
+            }
+            Behavior::Doh => {
+                // no questions, a single A record of 127.0.0.1
+                let mut answer = vec![0, 0, 0x81, 0x80, 0, 0, 0, 1, 0, 0, 0, 0];
+                answer.extend_from_slice(&[0, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 127, 0, 0, 1]);
+                let response = headers("200 OK", answer.len())
+                    .replace("application/octet-stream", "application/dns-message");
+                if stream.write_all(response.as_bytes()).await.is_err()
+                    || stream.write_all(&answer).await.is_err()
+                {
+                    return;
+                }
+            }
+            Behavior::Reset => {
+                stream.set_linger(Some(Duration::ZERO)).ok();
+                return;
+            }
+            Behavior::NotFound => {
+                let body = "not found";
+                let response = format!("{}{}", headers("404 Not Found", body.len()), body);
+                if stream.write_all(response.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+            Behavior::Stall => {
+                stream
+                    .write_all(headers("200 OK", 1024 * 1024).as_bytes())
+                    .await
+                    .ok();
+                sleep(Duration::from_secs(3600)).await;
+                return;
+            }
+        }
+    }
+}
+
+/// Options keeping speed tests short; config files and `RATE_MIRRORS_*` env
+/// vars of the environment are ignored.
+pub fn rate_mirrors() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rate_mirrors"));
+    command.env_clear().args([
+        "--config=/dev/null",
+        "--allow-root",
+        "--per-mirror-timeout=2000",
+        "--min-per-mirror=100",
+        "--max-per-mirror=400",
+        "--min-bytes-per-mirror=16384",
+        "--eps-checks=4",
+        "--max-jumps=3",
+    ]);
+    command
+}
+
+pub fn run_with_stdin(mut command: Command, stdin: &str) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Non-comment output lines
+pub fn output_lines(output: &Output) -> Vec<String> {
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Gzipped pacman database of `packages`, leaked to be served by
+/// `Behavior::Repo`
+pub fn pacman_db(packages: &[&str]) -> &'static [u8] {
+    let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+        Vec::new(),
+        flate2::Compression::fast(),
+    ));
+    for name in packages {
+        let desc = format!(
+            "%FILENAME%\n{0}-1.0-1-any.pkg.tar.zst\n\n%NAME%\n{0}\n",
+            name
+        );
+        let mut header = tar::Header::new_gnu();
+        header.set_size(desc.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, format!("{}-1.0-1/desc", name), desc.as_bytes())
+            .unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap().leak()
+}
@@ -0,0 +1,678 @@
+mod common;
+
+use common::{Behavior, MockMirror, output_lines, pacman_db, rate_mirrors, run_with_stdin};
+use futures::StreamExt;
+use futures::executor::block_on;
+use rate_mirrors::countries::Country;
+use rate_mirrors::mirror::Mirror;
+use rate_mirrors::progress::ProgressEvent;
+use rate_mirrors::ranker::{Event, RankerOptions, rank};
+use serde_json::Value;
+use std::fs;
+use std::process::Command;
+use std::time::Duration;
+use url::Url;
+
+const KB: usize = 1024;
+const MB: usize = 1024 * 1024;
+
+fn stdin_target(args: &[&str], mirrors: &[&MockMirror]) -> (Command, String) {
+    let mut command = rate_mirrors();
+    command.args(args).args(["stdin", "--path-to-test=file"]);
+    let lines: Vec<_> = mirrors.iter().map(|m| m.stdin_line()).collect();
+    (command, lines.join("\n"))
+}
+
+#[test]
+fn ranks_mirrors_by_speed() {
+    let slow = MockMirror::start(Some("FR"), Behavior::serve(256 * KB));
+    let fast = MockMirror::start(Some("US"), Behavior::serve(8 * MB));
+    let medium = MockMirror::start(Some("DE"), Behavior::serve(MB));
+
+    let (command, stdin) = stdin_target(&[], &[&slow, &fast, &medium]);
+    let output = run_with_stdin(command, &stdin);
+
+    assert!(output.status.success());
+    assert_eq!(
+        output_lines(&output),
+        vec![fast.url(), medium.url(), slow.url()]
+    );
+}
+
+#[test]
+fn skips_failing_mirrors() {
+    let good = MockMirror::start(Some("US"), Behavior::serve(4 * MB));
+    let reset = MockMirror::start(Some("DE"), Behavior::Reset);
+    let not_found = MockMirror::start(Some("FR"), Behavior::NotFound);
+    let stalled = MockMirror::start(None, Behavior::Stall);
+    let slow_start = MockMirror::start(
+        Some("GB"),
+        Behavior::SlowStart {
+            initial: 64 * KB,
+            after: 16 * KB,
+            bandwidth: MB,
+            size: 8 * MB,
+        },
+    );
+    let latent = MockMirror::start(
+        Some("NL"),
+        Behavior::Serve {
+            bandwidth: 4 * MB,
+            latency: Duration::from_millis(300),
+            size: 8 * MB,
+        },
+    );
+
+    let (command, stdin) = stdin_target(
+        &["--report=-"],
+        &[&reset, &not_found, &stalled, &good, &slow_start, &latent],
+    );
+    let output = run_with_stdin(command, &stdin);
+    assert!(output.status.success());
+
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let mirrors = report["mirrors"].as_array().unwrap();
+    let errors = |mirror: &MockMirror| -> Vec<String> {
+        let entry = mirrors
+            .iter()
+            .find(|m| m["url"] == mirror.url())
+            .unwrap_or_else(|| panic!("{} is not reported", mirror.url()));
+        entry["tests"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["error"].as_str().unwrap_or("").to_string())
+            .collect()
+    };
+    assert!(errors(&reset).iter().all(|e| !e.is_empty()));
+    assert_eq!(errors(&not_found), vec!["too few bytes downloaded"]);
+    assert_eq!(errors(&stalled), vec!["too few bytes downloaded"]);
+    for mirror in [&good, &slow_start, &latent] {
+        assert!(errors(mirror).iter().all(|e| e.is_empty()));
+    }
+
+    let ranked: Vec<_> = mirrors
+        .iter()
+        .filter(|m| !m["rank"].is_null())
+        .map(|m| m["url"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(ranked.len(), 3);
+    assert_eq!(ranked[0], good.url());
+}
+
+#[test]
+fn falls_back_to_untested_mirrors() {
+    let reset = MockMirror::start(Some("US"), Behavior::Reset);
+    let not_found = MockMirror::start(Some("DE"), Behavior::NotFound);
+
+    let (command, stdin) = stdin_target(&[], &[&reset, &not_found]);
+    let output = run_with_stdin(command, &stdin);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("FAILED TO TEST SPEEDS, RETURNING UNTESTED MIRRORS"));
+    let mut lines = output_lines(&output);
+    lines.sort();
+    let mut expected = vec![reset.url(), not_found.url()];
+    expected.sort();
+    assert_eq!(lines, expected);
+
+    let (command, stdin) = stdin_target(&["--disable-untested-fallback"], &[&reset, &not_found]);
+    let output = run_with_stdin(command, &stdin);
+    assert!(!output.status.success());
+    assert!(output_lines(&output).is_empty());
+}
+
+#[test]
+fn ranks_by_latency_without_downloading() {
+    let latent = |latency| Behavior::Serve {
+        bandwidth: 64 * MB,
+        latency: Duration::from_millis(latency),
+        size: MB,
+    };
+    let far = MockMirror::start(Some("JP"), latent(250));
+    let near = MockMirror::start(Some("US"), latent(0));
+    let middle = MockMirror::start(Some("CA"), latent(100));
+    let missing = MockMirror::start(Some("MX"), Behavior::NotFound);
+
+    let dir = std::env::temp_dir().join(format!("rate-mirrors-e2e-latency-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let report_path = dir.join("report.json");
+
+    let report_arg = format!("--report={}", report_path.display());
+    let (command, stdin) = stdin_target(
+        &["--rank-by=latency", "--latency-probes=3", &report_arg],
+        &[&far, &missing, &near, &middle],
+    );
+    let output = run_with_stdin(command, &stdin);
+    let report: Value = serde_json::from_slice(&fs::read(&report_path).unwrap()).unwrap();
+    fs::remove_dir_all(&dir).ok();
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("HTTP 404 FROM"));
+    assert_eq!(
+        output_lines(&output),
+        vec![near.url(), middle.url(), far.url()]
+    );
+    let mirrors = report["mirrors"].as_array().unwrap();
+    let far = mirrors.iter().find(|m| m["url"] == far.url()).unwrap();
+    let test = &far["tests"][0];
+    let ms = |field: &str| test[field].as_f64().unwrap();
+    // the server's delay is no part of the connection set-up
+    assert!(ms("tcp_ms") < 250.);
+    assert!(test["tls_ms"].is_null());
+    assert!(ms("ttfb_ms") >= 250.);
+}
+
+#[test]
+fn ranks_pacman_mirrorlist_file() {
+    let fast = MockMirror::start(None, Behavior::serve(8 * MB));
+    let slow = MockMirror::start(None, Behavior::serve(512 * KB));
+    let dir = std::env::temp_dir().join(format!("rate-mirrors-e2e-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("mirrorlist");
+    fs::write(
+        &path,
+        format!(
+            "## Germany\nServer = {}$repo/os/$arch\n## France\n#Server = {}$repo/os/$arch\n",
+            slow.url(),
+            fast.url()
+        ),
+    )
+    .unwrap();
+
+    let mut command = rate_mirrors();
+    command.args([
+        "pacman-mirrorlist",
+        &format!("--mirror-list-file={}", path.display()),
+    ]);
+    let output = run_with_stdin(command, "");
+    fs::remove_dir_all(&dir).ok();
+
+    assert!(output.status.success());
+    assert_eq!(
+        output_lines(&output),
+        vec![
+            format!("Server = {}$repo/os/$arch", fast.url()),
+            format!("Server = {}$repo/os/$arch", slow.url()),
+        ]
+    );
+}
+
+#[test]
+fn hops_to_neighbors_of_entry_country() {
+    let mirrors: Vec<_> = [("US", 4 * MB), ("CA", MB), ("MX", MB), ("JP", MB)]
+        .into_iter()
+        .map(|(code, bandwidth)| {
+            let server = MockMirror::start(Some(code), Behavior::serve(bandwidth));
+            let url = Url::parse(&server.url()).unwrap();
+            Mirror {
+                country: Country::from_str(code),
+                url_to_test: url.join("file").unwrap(),
+                url,
+                meta: Default::default(),
+            }
+        })
+        .collect();
+    let options = RankerOptions {
+        min_per_mirror: 100,
+        max_per_mirror: 400,
+        min_bytes_per_mirror: 16 * KB,
+        eps_checks: 4,
+        max_jumps: 2,
+        ..RankerOptions::default()
+    };
+
+    let events: Vec<_> = block_on(rank(mirrors, options).collect());
+    let progress: Vec<_> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Progress(progress) => Some(progress),
+            Event::Finished(_) => None,
+        })
+        .collect();
+    assert!(matches!(progress[0], ProgressEvent::JumpStarted(1)));
+    assert!(matches!(progress[1], ProgressEvent::CountryExplored("US")));
+    assert!(
+        progress
+            .iter()
+            .any(|e| matches!(e, ProgressEvent::NeighborSelected { .. }))
+    );
+    match events.last() {
+        Some(Event::Finished(results)) => {
+            assert_eq!(results.len(), 4);
+            assert_eq!(results[0].item.country.unwrap().code, "US");
+        }
+        other => panic!("unexpected last event {:?}", other),
+    }
+}
+
+#[test]
+fn reports_speed_stats_of_samples() {
+    let fast = MockMirror::start(Some("US"), Behavior::serve(8 * MB));
+    let slow = MockMirror::start(Some("DE"), Behavior::serve(MB));
+
+    let (command, stdin) = stdin_target(&["--samples=3", "--report=-"], &[&slow, &fast]);
+    let output = run_with_stdin(command, &stdin);
+    assert!(output.status.success());
+
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let mirrors = report["mirrors"].as_array().unwrap();
+    let first = mirrors.iter().find(|m| m["rank"] == 1).unwrap();
+    assert_eq!(first["url"], fast.url());
+    for mirror in mirrors {
+        let stats = &mirror["speed_stats"];
+        assert_eq!(stats["samples"], 3);
+        assert!(stats["ci_low"].as_f64().unwrap() <= stats["median"].as_f64().unwrap());
+    }
+}
+
+#[test]
+fn reports_phases_of_requests() {
+    let latent = MockMirror::start(
+        Some("US"),
+        Behavior::Serve {
+            bandwidth: 8 * MB,
+            latency: Duration::from_millis(300),
+            size: 8 * MB,
+        },
+    );
+
+    let (command, stdin) = stdin_target(&["--report=-"], &[&latent]);
+    let output = run_with_stdin(command, &stdin);
+    assert!(output.status.success());
+
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    for test in report["mirrors"][0]["tests"].as_array().unwrap() {
+        let ms = |field: &str| test[field].as_f64().unwrap();
+        // the server's delay is no part of the connection set-up
+        assert!(ms("tcp_ms") < 300.);
+        assert!(test["tls_ms"].is_null());
+        assert!(ms("ttfb_ms") >= 300.);
+        assert!(ms("connection_time_ms") >= ms("tcp_ms") + ms("ttfb_ms") - 1.);
+    }
+}
+
+#[test]
+fn warms_up_connections_of_retests() {
+    let fast = MockMirror::start(Some("US"), Behavior::serve(8 * MB));
+    let slow = MockMirror::start(Some("DE"), Behavior::serve(MB));
+
+    let (command, stdin) = stdin_target(&["--warm-retest", "--report=-"], &[&slow, &fast]);
+    let output = run_with_stdin(command, &stdin);
+    assert!(output.status.success());
+
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let mirrors = report["mirrors"].as_array().unwrap();
+    let first = mirrors.iter().find(|m| m["rank"] == 1).unwrap();
+    assert_eq!(first["url"], fast.url());
+    for mirror in mirrors {
+        for test in mirror["tests"].as_array().unwrap() {
+            assert_eq!(test["warmup_ms"].is_f64(), test["phase"] == "retest");
+        }
+    }
+}
+
+#[test]
+fn drops_or_demotes_stale_mirrors() {
+    let now = chrono::Utc::now().timestamp();
+    let synced = |bandwidth, days_ago: i64| Behavior::Synced {
+        bandwidth,
+        synced_at: now - days_ago * 86400,
+    };
+    let stale = MockMirror::start(Some("US"), synced(8 * MB, 3));
+    let fresh = MockMirror::start(Some("CA"), synced(2 * MB, 0));
+    let slow = MockMirror::start(Some("MX"), synced(512 * KB, 0));
+
+    let (command, stdin) = stdin_target(
+        &["--verify-freshness", "--stale-mirrors=drop"],
+        &[&stale, &fresh, &slow],
+    );
+    let output = run_with_stdin(command, &stdin);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("STALE: "));
+    assert_eq!(output_lines(&output), vec![fresh.url(), slow.url()]);
+
+    let (command, stdin) = stdin_target(
+        &[
+            "--verify-freshness",
+            "--freshness-reference=http://127.0.0.1:1/",
+        ],
+        &[&stale, &fresh, &slow],
+    );
+    let output = run_with_stdin(command, &stdin);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("FRESHNESS REFERENCE FAILED: "));
+    assert_eq!(
+        output_lines(&output),
+        vec![fresh.url(), slow.url(), stale.url()]
+    );
+}
+
+#[test]
+fn excludes_mirrors_failing_repo_check() {
+    let full = pacman_db(&["bash", "glibc", "linux", "pacman"]);
+    let partial = MockMirror::start(
+        Some("US"),
+        Behavior::Repo {
+            bandwidth: 8 * MB,
+            db: pacman_db(&["bash"]),
+        },
+    );
+    let broken = MockMirror::start(Some("CA"), Behavior::serve(4 * MB));
+    let good = MockMirror::start(
+        Some("MX"),
+        Behavior::Repo {
+            bandwidth: 2 * MB,
+            db: full,
+        },
+    );
+    let slow = MockMirror::start(
+        Some("GB"),
+        Behavior::Repo {
+            bandwidth: 512 * KB,
+            db: full,
+        },
+    );
+
+    let mut command = rate_mirrors();
+    command.args(["--verify-repo", "stdin", "--path-to-test=core.files"]);
+    let stdin: Vec<_> = [&partial, &broken, &good, &slow]
+        .iter()
+        .map(|m| m.stdin_line())
+        .collect();
+    let output = run_with_stdin(command, &stdin.join("\n"));
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("missing 3 of 4 packages"));
+    assert!(stdout.contains("no packages in the database"));
+    assert_eq!(output_lines(&output), vec![good.url(), slow.url()]);
+}
+
+#[test]
+fn checks_repos_of_output_mirrors_only() {
+    let full = pacman_db(&["bash", "glibc", "linux", "pacman"]);
+    let repo = |bandwidth, db| Behavior::Repo { bandwidth, db };
+    let partial = MockMirror::start(Some("US"), repo(8 * MB, pacman_db(&["bash"])));
+    let good: Vec<_> = [4 * MB, 2 * MB, MB, 512 * KB]
+        .into_iter()
+        .map(|bandwidth| MockMirror::start(Some("US"), repo(bandwidth, full)))
+        .collect();
+    let dir = std::env::temp_dir().join(format!("rate-mirrors-e2e-repo-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let report_path = dir.join("report.json");
+
+    let mut command = rate_mirrors();
+    command.args([
+        "--verify-repo",
+        "--verify-repo-reference=http://127.0.0.1:1/",
+        "--max-mirrors-to-output=1",
+        &format!("--report={}", report_path.display()),
+        "stdin",
+        "--path-to-test=core.files",
+    ]);
+    let stdin: Vec<_> = [&partial]
+        .into_iter()
+        .chain(good.iter())
+        .map(|m| m.stdin_line())
+        .collect();
+    let output = run_with_stdin(command, &stdin.join("\n"));
+    let report: Value = serde_json::from_slice(&fs::read(&report_path).unwrap()).unwrap();
+    fs::remove_dir_all(&dir).ok();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("REPO CHECK REFERENCE FAILED"));
+    assert_eq!(output_lines(&output), vec![good[0].url()]);
+    // the partial mirror and the next two, to take a majority of
+    let checked = report["mirrors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|m| m["repo"].is_object())
+        .count();
+    assert_eq!(checked, 3);
+}
+
+#[test]
+fn tests_over_requested_ip_family() {
+    let v4 = MockMirror::start(Some("US"), Behavior::serve(2 * MB));
+    let v6 = MockMirror::start_v6(Some("CA"), Behavior::serve(4 * MB));
+
+    let (command, stdin) = stdin_target(&["--ip-family=4"], &[&v4, &v6]);
+    let output = run_with_stdin(command, &stdin);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("FAMILY FILTER: 2 -> 1 mirrors"));
+    assert_eq!(output_lines(&output), vec![v4.url()]);
+
+    let (command, stdin) = stdin_target(&["--ip-family=both", "--report=-"], &[&v4, &v6]);
+    let output = run_with_stdin(command, &stdin);
+    assert!(output.status.success());
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let mirrors = report["mirrors"].as_array().unwrap();
+    for (mirror, family) in [(&v4, "ipv4"), (&v6, "ipv6")] {
+        let entry = mirrors.iter().find(|m| m["url"] == mirror.url()).unwrap();
+        let tests = entry["tests"].as_array().unwrap();
+        assert!(tests.iter().any(|t| t["ip_family"] == "ipv4"));
+        assert!(tests.iter().any(|t| t["ip_family"] == "ipv6"));
+        assert!(
+            tests
+                .iter()
+                .filter(|t| t["error"].is_null())
+                .all(|t| t["ip_family"] == family)
+        );
+        assert!(entry["rank"].is_number());
+    }
+}
+
+#[test]
+fn tests_mirrors_through_proxy() {
+    // the mock answers proxied requests like any other
+    let proxy = MockMirror::start(None, Behavior::serve(4 * MB));
+    let mirror = "http://mirror.invalid/mirror/";
+
+    let mut command = rate_mirrors();
+    command.args([
+        &format!("--proxy=http://{}", proxy.addr),
+        "stdin",
+        "--path-to-test=file",
+    ]);
+    let output = run_with_stdin(command, mirror);
+    assert!(output.status.success());
+    assert_eq!(output_lines(&output), vec![mirror]);
+
+    let mut command = rate_mirrors();
+    command.args([
+        "--disable-untested-fallback",
+        "stdin",
+        "--path-to-test=file",
+    ]);
+    assert!(!run_with_stdin(command, mirror).status.success());
+
+    let mut command = rate_mirrors();
+    command.args(["--ca-file=/nonexistent.pem", "stdin"]);
+    let output = run_with_stdin(command, mirror);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid HTTP settings"));
+}
+
+#[test]
+fn resolves_by_configured_resolver() {
+    let a = MockMirror::start(Some("US"), Behavior::serve(4 * MB));
+    let b = MockMirror::start(Some("DE"), Behavior::serve(MB));
+    let doh = MockMirror::start(None, Behavior::Doh);
+    let url =
+        |host: &str, mirror: &MockMirror| format!("http://{}:{}/mirror/", host, mirror.addr.port());
+    let stdin = format!("US\t{}\nDE\t{}", url("a.test", &a), url("b.test", &b));
+    let dir = std::env::temp_dir().join(format!("rate-mirrors-e2e-dns-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let hosts = dir.join("hosts");
+    fs::write(&hosts, "127.0.0.1 a.test\n").unwrap();
+
+    let run = |resolver_args: &[String]| -> Value {
+        let mut command = rate_mirrors();
+        command
+            .args(resolver_args)
+            .args(["--report=-", "stdin", "--path-to-test=file"]);
+        let output = run_with_stdin(command, &stdin);
+        assert!(output.status.success());
+        serde_json::from_slice(&output.stdout).unwrap()
+    };
+    let ranked = |report: &Value| -> Vec<String> {
+        let mirrors = report["mirrors"].as_array().unwrap();
+        for mirror in mirrors.iter().filter(|m| !m["rank"].is_null()) {
+            for test in mirror["tests"].as_array().unwrap() {
+                assert_eq!(test["remote_ip"], "127.0.0.1");
+            }
+        }
+        let mut ranked: Vec<_> = mirrors.iter().filter(|m| !m["rank"].is_null()).collect();
+        ranked.sort_by_key(|m| m["rank"].as_u64());
+        ranked
+            .iter()
+            .map(|m| m["url"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    // b.test is in no hosts file but overridden
+    let static_resolver = format!("--resolver=static:{}", hosts.display());
+    let report = run(&[
+        static_resolver.clone(),
+        format!("--resolve=b.test:{}:127.0.0.1", b.addr.port()),
+    ]);
+    assert_eq!(ranked(&report), vec![url("a.test", &a), url("b.test", &b)]);
+    let report = run(&[static_resolver]);
+    assert_eq!(ranked(&report), vec![url("a.test", &a)]);
+    fs::remove_dir_all(&dir).ok();
+
+    let report = run(&[format!("--resolver=doh:http://{}/dns-query", doh.addr)]);
+    assert_eq!(ranked(&report), vec![url("a.test", &a), url("b.test", &b)]);
+}
+
+#[test]
+fn skips_tests_over_budget() {
+    let mirrors: Vec<_> = (0..3)
+        .map(|_| MockMirror::start(Some("US"), Behavior::serve(4 * MB)))
+        .collect();
+    let mirrors: Vec<_> = mirrors.iter().collect();
+
+    let (command, stdin) = stdin_target(
+        &["--concurrency=1", "--max-total-bytes=200KB", "--report=-"],
+        &mirrors,
+    );
+    let output = run_with_stdin(command, &stdin);
+    assert!(output.status.success());
+
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let reports = report["mirrors"].as_array().unwrap();
+    let with_status = |status: &str| -> Vec<&Value> {
+        reports.iter().filter(|m| m["status"] == status).collect()
+    };
+    // the first test uses up the budget, yet ranks its mirror
+    let tested = with_status("tested");
+    assert_eq!(tested.len(), 1);
+    assert_eq!(tested[0]["rank"], 1);
+    assert!(tested[0]["tests"].as_array().unwrap().len() == 1);
+    let skipped = with_status("skipped");
+    assert!(!skipped.is_empty());
+    for mirror in skipped {
+        assert!(mirror["rank"].is_null());
+        assert_eq!(
+            mirror["tests"][0]["error"],
+            "skipped, total bytes budget exhausted"
+        );
+    }
+}
+
+#[test]
+fn measures_speeds_by_range_probes() {
+    let fast = MockMirror::start(Some("US"), Behavior::ranged(8 * MB, 8 * MB));
+    let slow = MockMirror::start(Some("DE"), Behavior::ranged(MB, 8 * MB));
+    let ignoring = MockMirror::start(Some("FR"), Behavior::serve(4 * MB));
+
+    let (command, stdin) = stdin_target(&["--lite", "--report=-"], &[&slow, &ignoring, &fast]);
+    let output = run_with_stdin(command, &stdin);
+    assert!(output.status.success());
+
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let mirrors = report["mirrors"].as_array().unwrap();
+    let rank_of = |mirror: &MockMirror| {
+        let report = mirrors.iter().find(|m| m["url"] == mirror.url()).unwrap();
+        report["rank"].as_u64().unwrap()
+    };
+    assert!(rank_of(&fast) < rank_of(&slow));
+    for mirror in mirrors {
+        for test in mirror["tests"].as_array().unwrap() {
+            // 16 + 32 + 64 + 128 KiB, a chunk or so more where ranges are
+            // ignored
+            assert!(test["bytes_downloaded"].as_u64().unwrap() <= 320 * KB as u64);
+            let ranged = mirror["url"] != ignoring.url();
+            assert_eq!(test["range_probes"] == 4, ranged);
+            if ranged {
+                // the speed is that of the probes after the first one
+                let timed = test["speed"].as_f64().unwrap() * test["elapsed_ms"].as_f64().unwrap();
+                assert!((timed / 1000. - (224 * KB) as f64).abs() < 1.);
+            }
+        }
+    }
+}
+
+#[test]
+fn falls_back_from_failing_range_probes() {
+    // 416 to the first probe, 416 to the second one past the end of the
+    // file, and the whole file in answer to the second one
+    let rejecting = MockMirror::start(Some("US"), Behavior::RejectRanges { bandwidth: 4 * MB });
+    let short = MockMirror::start(Some("DE"), Behavior::ranged(4 * MB, 16 * KB));
+    let forgetting = MockMirror::start(
+        Some("FR"),
+        Behavior::Ranged {
+            bandwidth: 4 * MB,
+            size: 8 * MB,
+            ranges: 1,
+        },
+    );
+    let mirrors = [&rejecting, &short, &forgetting];
+
+    let (command, stdin) = stdin_target(&["--lite", "--report=-"], &mirrors);
+    let output = run_with_stdin(command, &stdin);
+    assert!(output.status.success());
+
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let reports = report["mirrors"].as_array().unwrap();
+    let tests_of = |mirror: &MockMirror| {
+        let report = reports.iter().find(|m| m["url"] == mirror.url()).unwrap();
+        assert_eq!(report["status"], "tested");
+        report["tests"].as_array().unwrap().clone()
+    };
+    for mirror in mirrors {
+        for test in tests_of(mirror) {
+            assert!(test["error"].is_null());
+            // 240 KiB, a chunk or so more where the whole file is read
+            assert!(test["bytes_downloaded"].as_u64().unwrap() <= 320 * KB as u64);
+        }
+    }
+    for test in tests_of(&forgetting) {
+        assert_eq!(test["range_probes"], 2);
+    }
+}
+
+#[test]
+fn rolls_back_without_allow_root() {
+    let dir =
+        std::env::temp_dir().join(format!("rate-mirrors-e2e-rollback-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("mirrorlist");
+    fs::write(&path, "new").unwrap();
+    fs::write(dir.join("mirrorlist.rate-mirrors.1"), "old").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_rate_mirrors"))
+        .env_clear()
+        .args(["--config=/dev/null", "rollback"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    let content = fs::read_to_string(&path).unwrap();
+    fs::remove_dir_all(&dir).ok();
+
+    assert!(output.status.success());
+    assert_eq!(content, "old");
+}